@@ -1,6 +1,25 @@
 fn main() {
     tonic_build::compile_protos("proto/helloworld.proto")
         .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
+
+    // Points are keys of chat histories, so they need to be hashable.
+    tonic_build::configure()
+        .type_attribute("route_guide.Point", "#[derive(Eq, Hash)]")
+        .compile(&["proto/route_guide.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
+
+    // The health checking protocol, for clients of the server's tonic-health service.
+    tonic_build::configure()
+        .build_server(false)
+        .compile(&["proto/grpc/health/v1/health.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
+
+    // The google.rpc error model, for the details of a failed call. Only messages, no services.
+    tonic_build::configure()
+        .build_client(false)
+        .build_server(false)
+        .compile(&["proto/google/rpc/status.proto", "proto/google/rpc/error_details.proto"], &["proto"])
+        .unwrap_or_else(|e| panic!("Failed to compile protos {:?}", e));
 }
 //
 // fn main() {
@@ -0,0 +1,145 @@
+/*
+-- The raw server, on tokio --
+The same static pages as `server.rs`, parsed with the same `http::Parser`, but every connection is
+a tokio task instead of a job in the `ThreadPool`. An idle keep-alive connection then costs a few
+hundred bytes of task state instead of a whole worker thread.
+
+Listens on [::1]:8081, so it can run next to `server.rs` on [::1]:8080 and both can be compared with
+`cargo run --release --example server-bench -- [::1]:8080 [::1]:8081`.
+*/
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tracing_futures::Instrument;
+
+#[path = "../src/connection_limits.rs"] mod connection_limits;
+#[path = "../src/error_pages.rs"] mod error_pages;
+#[path = "../src/http.rs"] mod http;
+#[path = "../src/pages.rs"] mod pages;
+#[path = "../src/telemetry.rs"] mod telemetry;
+use connection_limits::{ConnectionLimiter, ServerConfig};
+
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    // Log to stderr, at the level set in `RUST_LOG` (info by default).
+    telemetry::init_logging();
+
+    let config       = Arc::new(ServerConfig::from_env());
+    let limiter      = ConnectionLimiter::new(&config);
+    let mut listener = TcpListener::bind("[::1]:8081").await?;
+
+    loop {
+        let (mut stream, address) = listener.accept().await?;
+
+        let guard = match limiter.acquire(address.ip()) {
+            Ok(guard) => guard,
+            Err(rejection) => {
+                tracing::info!(peer = %address, %rejection, "rejected connection");
+                tokio::spawn(async move { reject(&mut stream).await });
+                continue;
+            },
+        };
+
+        let config = config.clone();
+        tokio::spawn(async move {
+            handle_connection(stream, &config).await;
+            drop(guard);
+        }.instrument(tracing::info_span!("connection", peer = %address)));
+    }
+}
+
+async fn reject(stream: &mut TcpStream) {
+    let response = pages::error_response(503, None)
+        .header("Connection", "close")
+        .header("Retry-After", 1);
+    let _ = timeout(Duration::from_secs(1), stream.write_all(&response.to_bytes(true))).await;
+}
+
+async fn write_response(stream: &mut TcpStream, response: &http::Response, include_body: bool, config: &ServerConfig) -> bool {
+    matches!(timeout(config.write_timeout, stream.write_all(&response.to_bytes(include_body))).await, Ok(Ok(())))
+}
+
+// Mirrors `handle_connection` in server.rs, with `tokio::time::timeout` in place of socket timeouts.
+async fn handle_connection(mut stream: TcpStream, config: &ServerConfig) {
+    let mut parser = http::Parser::new(http::Limits::default());
+    let mut buffer = [0; 4096];
+    let mut request_started = Some(Instant::now());
+
+    loop {
+        match parser.next_request() {
+            Ok(Some(request)) => {
+                let span = tracing::info_span!(
+                    "request",
+                    method = %request.method,
+                    target = %request.target,
+                    status = tracing::field::Empty,
+                );
+
+                let keep_alive = request.keep_alive();
+                let mut response = route(&request).instrument(span.clone()).await;
+                span.record("status", response.status);
+                if !keep_alive {
+                    response = response.header("Connection", "close");
+                }
+
+                let include_body = request.method != "HEAD";
+                if !write_response(&mut stream, &response, include_body, config).await || !keep_alive {
+                    return;
+                }
+
+                request_started = if parser.buffered() > 0 { Some(Instant::now()) } else { None };
+                continue;
+            },
+            Ok(None) => {},
+            Err(error) => {
+                tracing::info!(%error, "rejected request");
+                let response = pages::error_response(error.status(), None).header("Connection", "close");
+                write_response(&mut stream, &response, true, config).await;
+                return;
+            },
+        }
+
+        let limit = match request_started {
+            None => config.idle_timeout,
+            Some(started) if !parser.has_complete_head() => {
+                match config.header_timeout.checked_sub(started.elapsed()) {
+                    Some(remaining) if remaining > Duration::from_millis(0) => remaining.min(config.read_timeout),
+                    _ => return request_timeout(&mut stream, config).await,
+                }
+            },
+            Some(_) => config.read_timeout,
+        };
+
+        match timeout(limit, stream.read(&mut buffer)).await {
+            Ok(Ok(0)) | Ok(Err(_)) => return,
+            Ok(Ok(n)) => {
+                request_started.get_or_insert_with(Instant::now);
+                parser.feed(&buffer[..n]);
+            },
+            Err(_) => {
+                if request_started.is_some() {
+                    request_timeout(&mut stream, config).await;
+                }
+                return;
+            },
+        }
+    }
+}
+
+async fn request_timeout(stream: &mut TcpStream, config: &ServerConfig) {
+    tracing::info!("request timed out");
+    let response = pages::error_response(408, None).header("Connection", "close");
+    write_response(stream, &response, true, config).await;
+}
+
+async fn route(request: &http::Request) -> http::Response {
+    match pages::resolve(request) {
+        pages::Route::Page(path) => pages::page_response(request, path, tokio::fs::read(path).await),
+        pages::Route::Error(status) => pages::error_response(status, Some(request)),
+    }
+}
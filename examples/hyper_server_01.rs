@@ -1,6 +1,6 @@
 /*
 -- Simple "Hello, world!" server --
-Any request to 127.0.0.1:3000 will respond with "Hello, World".
+Any request to https://127.0.0.1:3000 will respond with "Hello, World".
 
 Tutorial: https://hyper.rs/guides/server/hello-world/
 Service: https://docs.rs/hyper/0.13.9/hyper/service/trait.Service.html
@@ -18,6 +18,10 @@ use std::convert::Infallible;
 use std::net::SocketAddr;
 use hyper::{Body, Request, Response, Server};
 use hyper::service::{make_service_fn, service_fn};
+use hyper::server::accept;
+
+#[path = "../src/file_watch.rs"] mod file_watch;
+#[path = "../src/tls.rs"] mod tls;
 
 
 async fn service(_req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
@@ -27,7 +31,10 @@ async fn service(_req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
 
 #[tokio::main]
 async fn main() {
-    // We'll bind to 127.0.0.1:3000
+    // Log to stderr, at the level set in `RUST_LOG`.
+    tracing_subscriber::fmt::init();
+
+    // We'll bind to 127.0.0.1:3000 and speak HTTPS, with h2 or http/1.1 depending on the client.
     let address = SocketAddr::from(([127, 0, 0, 1], 3000));
 
     // A `Service` is needed for every connection, so this
@@ -37,10 +44,12 @@ async fn main() {
         Ok::<_, Infallible>(service_fn(service))
     });
 
-    let server = Server::bind(&address).serve(make_service);
+    // TLS with the same identity as the tonic server, reloaded when the files change.
+    let incoming = tls::incoming(address).await.expect("failed to set up TLS");
+    let server = Server::builder(accept::from_stream(incoming)).serve(make_service);
 
     // Run this server for... forever!
     if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+        tracing::error!(error = %e, "server error");
     }
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 /*
 -- Responding on request without streaming --
-A request to 127.0.0.1:3000/echo/reverse will respond with the same data but reversed.
+A request to https://127.0.0.1:3000/echo/reverse will respond with the same data but reversed.
 
 Tutorial: https://hyper.rs/guides/server/echo/
 
@@ -10,9 +10,14 @@ use std::convert::Infallible;
 use std::net::SocketAddr;
 use hyper::{Body, Request, Response, Server};
 use hyper::service::{make_service_fn, service_fn};
+use hyper::server::accept;
 
 use hyper::{Method, StatusCode};
 
+#[path = "../src/error_pages.rs"] mod error_pages;
+#[path = "../src/file_watch.rs"] mod file_watch;
+#[path = "../src/tls.rs"] mod tls;
+
 use futures::TryStreamExt as _;
 
 
@@ -63,7 +68,7 @@ async fn service(request: Request<Body>) -> Result<Response<Body>, hyper::Error>
             *response.body_mut() = reverse_response(request).await?;
         },
         _ => {
-            *response.status_mut() = StatusCode::NOT_FOUND;
+            response = error_pages::hyper_response(StatusCode::NOT_FOUND, &request);
         },
     };
 
@@ -73,7 +78,10 @@ async fn service(request: Request<Body>) -> Result<Response<Body>, hyper::Error>
 
 #[tokio::main]
 async fn main() {
-    // We'll bind to 127.0.0.1:3000
+    // Log to stderr, at the level set in `RUST_LOG`.
+    tracing_subscriber::fmt::init();
+
+    // We'll bind to 127.0.0.1:3000 and speak HTTPS, with h2 or http/1.1 depending on the client.
     let address = SocketAddr::from(([127, 0, 0, 1], 3000));
 
     // A `Service` is needed for every connection, so this
@@ -83,10 +91,12 @@ async fn main() {
         Ok::<_, Infallible>(service_fn(service))
     });
 
-    let server = Server::bind(&address).serve(make_service);
+    // TLS with the same identity as the tonic server, reloaded when the files change.
+    let incoming = tls::incoming(address).await.expect("failed to set up TLS");
+    let server = Server::builder(accept::from_stream(incoming)).serve(make_service);
 
     // Run this server for... forever!
     if let Err(e) = server.await {
-        eprintln!("server error: {}", e);
+        tracing::error!(error = %e, "server error");
     }
 }
\ No newline at end of file
@@ -8,9 +8,14 @@ use std::convert::Infallible;
 use std::net::SocketAddr;
 use hyper::{Body, Request, Response, Server};
 use hyper::service::{make_service_fn, service_fn};
+use hyper::server::accept;
 
 use hyper::{Method, StatusCode};
 
+#[path = "../src/error_pages.rs"] mod error_pages;
+#[path = "../src/file_watch.rs"] mod file_watch;
+#[path = "../src/tls.rs"] mod tls;
+
 use futures::TryStreamExt as _;
 
 
@@ -68,7 +73,7 @@ async fn service(request: Request<Body>) -> Result<Response<Body>, hyper::Error>
             *response.body_mut() = reverse_response(request).await?;
         },
         _ => {
-            *response.status_mut() = StatusCode::NOT_FOUND;
+            response = error_pages::hyper_response(StatusCode::NOT_FOUND, &request);
         },
     };
 
@@ -78,7 +83,10 @@ async fn service(request: Request<Body>) -> Result<Response<Body>, hyper::Error>
 
 #[tokio::main]
 async fn main() {
-    // We'll bind to 127.0.0.1:3000
+    // Log to stderr, at the level set in `RUST_LOG`.
+    tracing_subscriber::fmt::init();
+
+    // We'll bind to 127.0.0.1:3000 and speak HTTPS, with h2 or http/1.1 depending on the client.
     let address = SocketAddr::from(([127, 0, 0, 1], 3000));
 
     // A `Service` is needed for every connection, so this
@@ -88,11 +96,13 @@ async fn main() {
         Ok::<_, Infallible>(service_fn(service))
     });
 
-    let server = Server::bind(&address).serve(make_service);
+    // TLS with the same identity as the tonic server, reloaded when the files change.
+    let incoming = tls::incoming(address).await.expect("failed to set up TLS");
+    let server = Server::builder(accept::from_stream(incoming)).serve(make_service);
 
     // @NEW: And now add a graceful shutdown signal and wait.
     let graceful = server.with_graceful_shutdown(shutdown_signal());
     if let Err(e) = graceful.await {
-        eprintln!("server error: {}", e);
+        tracing::error!(error = %e, "server error");
     }
 }
\ No newline at end of file
@@ -14,6 +14,8 @@ use std::future::Future;
 use hyper::{Body, Request, Response};  // @CHANGED: Removed Server in favor for tonic::transport::Server.
 use hyper::{Method, StatusCode};
 
+#[path = "../src/error_pages.rs"] mod error_pages;
+
 use futures::TryStreamExt as _;
 
 // @NEW
@@ -105,7 +107,7 @@ async fn service(request: Request<Body>) -> Result<Response<Body>, hyper::Error>
             *response.body_mut() = reverse_response(request).await?;
         },
         _ => {
-            *response.status_mut() = StatusCode::NOT_FOUND;
+            response = error_pages::hyper_response(StatusCode::NOT_FOUND, &request);
         },
     };
 
@@ -0,0 +1,70 @@
+/*
+-- Serving static files --
+GET and HEAD requests to https://127.0.0.1:3000 are served from the `static/` directory, e.g.
+`curl --cacert data/tls/ca.pem -i https://127.0.0.1:3000/html/` returns `static/html/index.html`.
+
+The service picks a Content-Type from the file extension, serves `index.html` for directories and
+refuses paths that would escape `static/`. It also answers conditional requests (`curl -i -H
+'If-None-Match: "..."'` gives a 304), byte ranges (`curl -i -H 'Range: bytes=0-9'` gives a 206) and
+serves `file.gz` instead of `file` when it exists and the client accepts gzip.
+
+Files are streamed with `Body::wrap_stream`, just like the echo server streams the request body.
+*/
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use hyper::{Body, Request, Response, Server};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::server::accept;
+
+use hyper::{Method, StatusCode, header};
+
+#[path = "../src/error_pages.rs"] mod error_pages;
+#[path = "../src/static_files.rs"] mod static_files;  // @NEW
+#[path = "../src/file_watch.rs"] mod file_watch;
+#[path = "../src/tls.rs"] mod tls;
+use static_files::StaticFiles;
+
+
+// @CHANGED: The service now carries state, the directory it serves files from.
+async fn service(files: StaticFiles, request: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/") => {
+            let response = Response::builder()
+                .status(StatusCode::FOUND)
+                .header(header::LOCATION, "/html/")
+                .body(Body::empty())
+                .unwrap();
+            Ok(response)
+        },
+        _ => Ok(files.serve(&request).await),
+    }
+}
+
+
+#[tokio::main]
+async fn main() {
+    // Log to stderr, at the level set in `RUST_LOG`.
+    tracing_subscriber::fmt::init();
+
+    // We'll bind to 127.0.0.1:3000 and speak HTTPS, with h2 or http/1.1 depending on the client.
+    let address = SocketAddr::from(([127, 0, 0, 1], 3000));
+
+    let files = StaticFiles::new("static");
+
+    // @CHANGED: Every connection gets its own copy of `files`, and so does every request.
+    let make_service = make_service_fn(move |_conn| {
+        let files = files.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| service(files.clone(), request)))
+        }
+    });
+
+    // TLS with the same identity as the tonic server, reloaded when the files change.
+    let incoming = tls::incoming(address).await.expect("failed to set up TLS");
+    let server = Server::builder(accept::from_stream(incoming)).serve(make_service);
+
+    // Run this server for... forever!
+    if let Err(e) = server.await {
+        tracing::error!(error = %e, "server error");
+    }
+}
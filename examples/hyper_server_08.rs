@@ -0,0 +1,148 @@
+/*
+-- Compressing responses and decompressing requests --
+The echo server from before, wrapped in compression. Responses are compressed with brotli, gzip or
+deflate depending on the request's Accept-Encoding, and request bodies sent with a Content-Encoding
+are decompressed before they reach the `/echo` handlers:
+
+    curl --cacert data/tls/ca.pem -s --compressed -d "$(seq 1000)" https://127.0.0.1:3000/echo/uppercase
+    seq 1000 | gzip | curl --cacert data/tls/ca.pem -s --data-binary @- -H 'Content-Encoding: gzip' https://127.0.0.1:3000/echo
+
+Compression runs as a stream around the response body, so `uppercase_response` still never holds
+more than one chunk in memory.
+*/
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use hyper::{Body, Request, Response, Server};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::server::accept;
+
+use hyper::{Method, StatusCode, header};
+
+#[path = "../src/compression.rs"] mod compression;  // @NEW
+#[path = "../src/error_pages.rs"] mod error_pages;
+#[path = "../src/file_watch.rs"] mod file_watch;
+#[path = "../src/tls.rs"] mod tls;
+use compression::CompressionConfig;
+
+use futures::TryStreamExt as _;
+
+
+async fn shutdown_signal() {
+    // Wait for the CTRL+C signal.
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to install CTRL+C signal handler");
+}
+
+
+async fn reverse_response(request: Request<Body>) -> Result<Body, hyper::Error> {
+    // Await the full body to be concatenated into a single `Bytes`...
+    let full_body = hyper::body::to_bytes(request.into_body()).await?;
+
+    // Iterate the full body in reverse order and collect into a new Vec.
+    let reversed = full_body.iter()
+        .rev()
+        .cloned()
+        .collect::<Vec<u8>>();
+
+    Ok(reversed.into())
+}
+
+
+fn uppercase_response(request: Request<Body>) -> Body {
+    let mapping = request
+        .into_body()
+        .map_ok(|chunk| {
+            chunk.iter()
+                .map(|byte| byte.to_ascii_uppercase())
+                .collect::<Vec<u8>>()
+        });
+
+    // Use `Body::wrap_stream` to convert it to a `Body`...
+    Body::wrap_stream(mapping)
+}
+
+
+async fn service(request: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    let mut response = Response::new(Body::empty());
+
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/") => {
+            *response.body_mut() = Body::from("Try POSTing data to /echo");
+        },
+        (&Method::POST, "/echo") => {
+            *response.body_mut() = request.into_body();
+        },
+        (&Method::POST, "/echo/uppercase") => {
+            *response.body_mut() = uppercase_response(request);
+        },
+        (&Method::POST, "/echo/reverse") => {
+            *response.body_mut() = reverse_response(request).await?;
+        },
+        _ => {
+            response = error_pages::hyper_response(StatusCode::NOT_FOUND, &request);
+        },
+    };
+
+    // @NEW: Only text is compressed, so say that this is text.
+    if !response.headers().contains_key(header::CONTENT_TYPE) {
+        response.headers_mut().insert(header::CONTENT_TYPE, header::HeaderValue::from_static("text/plain"));
+    }
+
+    Ok(response)
+}
+
+
+// @NEW: Wraps `service` in decompression of the request and compression of the response.
+async fn compressed_service(config: CompressionConfig, request: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    // The request is consumed by the service, so take what is needed of it before calling it.
+    let accept_encoding = request.headers().get(header::ACCEPT_ENCODING).cloned();
+    let mut head = Request::new(());
+    *head.uri_mut() = request.uri().clone();
+    *head.headers_mut() = request.headers().clone();
+
+    let request = match compression::decompress_request(&config, request) {
+        Ok(request) => request,
+        Err(response) => return Ok(response),
+    };
+
+    // A handler that reads the whole body, like `/echo/reverse`, fails before it answers when the
+    // body decompresses to too much. Streamed answers have already started by then, and are cut off.
+    let response = match service(request).await {
+        Ok(response) => response,
+        Err(e) if compression::is_too_large(&e) => error_pages::hyper_response(StatusCode::PAYLOAD_TOO_LARGE, &head),
+        Err(e) => return Err(e),
+    };
+    Ok(compression::compress_response(&config, accept_encoding.as_ref(), response))
+}
+
+
+#[tokio::main]
+async fn main() {
+    // Log to stderr, at the level set in `RUST_LOG`.
+    tracing_subscriber::fmt::init();
+
+    // We'll bind to 127.0.0.1:3000 and speak HTTPS, with h2 or http/1.1 depending on the client.
+    let address = SocketAddr::from(([127, 0, 0, 1], 3000));
+
+    let config = CompressionConfig::default();
+
+    // A `Service` is needed for every connection, so this
+    // creates one from our `compressed_service` function.
+    let make_service = make_service_fn(move |_conn| {
+        let config = config.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| compressed_service(config.clone(), request)))
+        }
+    });
+
+    // TLS with the same identity as the tonic server, reloaded when the files change.
+    let incoming = tls::incoming(address).await.expect("failed to set up TLS");
+    let server = Server::builder(accept::from_stream(incoming)).serve(make_service);
+
+    // And now add a graceful shutdown signal and wait.
+    let graceful = server.with_graceful_shutdown(shutdown_signal());
+    if let Err(e) = graceful.await {
+        tracing::error!(error = %e, "server error");
+    }
+}
@@ -0,0 +1,736 @@
+/*
+-- RouteGuide from the command line --
+Talks to `tonic-server`, one call per subcommand:
+
+    route-guide-cli --token 1234 get-feature --lat 40.9146138 --lng -74.6188906
+    route-guide-cli --token 1234 list --rect 40,-75,42,-73 --output ndjson
+    route-guide-cli --token 1234 search "berkshire valley" --rect 40,-75,42,-73
+    route-guide-cli --token 1234 plan --from 40.9146138,-74.6188906 --to 41.0040646,-74.1008441 --max-hop 15000
+    route-guide-cli --token 1234 record --from-file route.gpx
+    route-guide-cli --token 1234 heatmap --rect 40,-75,42,-73 --geohash 5 --routes
+    route-guide-cli --token 1234 nearest --lat 40.9146138 --lng -74.6188906 --k 5
+    route-guide-cli --token 1234 radius --lat 40.9146138 --lng -74.6188906 --meters 20000
+    route-guide-cli --token 1234 chat --room hikers --lat 40.9146138 --lng -74.6188906
+    route-guide-cli --token 1234 fence add --name park --circle 40.9146138,-74.6188906,500
+    route-guide-cli --token 1234 track < positions.csv
+    route-guide-cli health
+    route-guide-cli convert --input data/route_guide_db.json --output features.gpx
+
+Coordinates are in decimal degrees. Files can be GPX, GeoJSON, CSV or the JSON of the feature
+database, by extension; `convert` works without a server, and `--route` converts a route instead
+of features. `chat` sends every line read from stdin as a note at the given
+location and prints the notes of the room until stdin is closed. `track` sends every `lat,lng` line
+read from stdin as a position and prints the geofences entered and left.
+*/
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use futures::stream;
+use serde_json::{json, Value};
+use structopt::StructOpt;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tonic::metadata::{AsciiMetadataValue, MetadataValue};
+use tonic::{Request, Status};
+
+pub mod route_guide {tonic::include_proto!("route_guide");}
+pub mod health {tonic::include_proto!("grpc.health.v1");}
+use health::health_client::HealthClient;
+use health::HealthCheckRequest;
+use route_guide::route_guide_client::RouteGuideClient;
+use route_guide::{
+    AggregateRequest, Cell, Circle, Feature, Geofence, GeofenceEvent, GeofenceId, ListGeofencesRequest, NearbyFeature, NearestRequest,
+    PlanRouteRequest, Point, RadiusRequest, Rectangle, RouteConstraints, RouteNote, RouteSummary, SearchRequest,
+    Waypoint,
+};
+use route_guide::aggregate_request::Grid;
+use route_guide::geofence::Shape;
+use route_guide::geofence_event::Kind;
+
+#[path = "../src/channel.rs"] mod channel;
+#[path = "../src/chat.rs"] mod chat;
+#[path = "../src/deadline.rs"] mod deadline;
+#[path = "../src/error.rs"] mod error;
+#[path = "../src/formats.rs"] mod formats;
+#[path = "../src/telemetry.rs"] mod telemetry;
+use error::ErrorDetails;
+use formats::{degrees_to_e7, e7_to_degrees};
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "route-guide-cli", about = "A command-line client for the RouteGuide service.")]
+struct Options {
+    /// Server to connect to. Give it more than once to balance calls between servers.
+    #[structopt(long = "endpoint", default_value = "http://[::1]:50051")]
+    endpoints: Vec<String>,
+
+    /// CA certificate to verify the server with.
+    #[structopt(long, parse(from_os_str), default_value = "data/tls/ca.pem")]
+    ca: PathBuf,
+
+    /// The name the server's certificate is checked against.
+    #[structopt(long, default_value = "example.com")]
+    domain: String,
+
+    /// Client certificate, for servers that ask for one. Needs --key.
+    #[structopt(long, parse(from_os_str), requires = "key")]
+    cert: Option<PathBuf>,
+
+    /// Private key of the client certificate.
+    #[structopt(long, parse(from_os_str), requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// Bearer token for the authorization metadata.
+    #[structopt(long, env = "ROUTE_GUIDE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// How to print results: table, json or ndjson.
+    #[structopt(long, default_value = "table")]
+    output: Output,
+
+    /// Seconds to wait for a call. Doesn't apply to chat.
+    #[structopt(long, default_value = "10")]
+    timeout: u64,
+
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Shows the feature at a point.
+    GetFeature {
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lat: i32,
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lng: i32,
+    },
+    /// Lists the features in a rectangle, given as two corners: lat,lng,lat,lng.
+    List {
+        #[structopt(long, parse(try_from_str = parse_rectangle), allow_hyphen_values = true)]
+        rect: Rectangle,
+        /// Also writes the features to a .gpx, .geojson, .csv or .json file.
+        #[structopt(long, parse(from_os_str))]
+        save: Option<PathBuf>,
+    },
+    /// Lists the features nearest to a point, nearest first.
+    Nearest {
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lat: i32,
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lng: i32,
+        /// How many features.
+        #[structopt(long, default_value = "10")]
+        k: i32,
+    },
+    /// Lists the features within a distance of a point, nearest first.
+    Radius {
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lat: i32,
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lng: i32,
+        #[structopt(long)]
+        meters: i32,
+    },
+    /// Searches feature names, best match first.
+    Search {
+        query: String,
+        /// Only features in this rectangle: lat,lng,lat,lng.
+        #[structopt(long, parse(try_from_str = parse_rectangle), allow_hyphen_values = true)]
+        rect: Option<Rectangle>,
+        /// At most this many results; the server's default if not given.
+        #[structopt(long, default_value = "0")]
+        limit: i32,
+    },
+    /// Plans a route between two points, given as lat,lng, through the known features.
+    Plan {
+        #[structopt(long, parse(try_from_str = parse_point), allow_hyphen_values = true)]
+        from: Point,
+        #[structopt(long, parse(try_from_str = parse_point), allow_hyphen_values = true)]
+        to: Point,
+        /// The longest distance between waypoints in metres; the server's default if not given.
+        #[structopt(long, default_value = "0")]
+        max_hop: i32,
+        /// The longest route in metres; no limit if not given.
+        #[structopt(long, default_value = "0")]
+        max_distance: i32,
+        /// Also writes the route to a .gpx, .geojson, .csv or .json file.
+        #[structopt(long, parse(from_os_str))]
+        save: Option<PathBuf>,
+    },
+    /// Records a route read from a .gpx, .geojson, .csv or .json file and shows the summary.
+    Record {
+        #[structopt(long, parse(from_os_str))]
+        from_file: PathBuf,
+    },
+    /// Chats in a room: lines from stdin are sent as notes at the given location.
+    Chat {
+        #[structopt(long, default_value = "")]
+        room: String,
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lat: i32,
+        #[structopt(long, parse(try_from_str = degrees_to_e7), allow_hyphen_values = true)]
+        lng: i32,
+    },
+    /// Counts features, or recorded route points, per grid cell of a rectangle: lat,lng,lat,lng.
+    Heatmap {
+        #[structopt(long, parse(try_from_str = parse_rectangle), allow_hyphen_values = true)]
+        rect: Rectangle,
+        /// Square cells of this many degrees on each side.
+        #[structopt(long, parse(try_from_str = degrees_to_e7), required_unless = "geohash", conflicts_with = "geohash")]
+        cell: Option<i32>,
+        /// Geohash cells of this many characters.
+        #[structopt(long)]
+        geohash: Option<i32>,
+        /// Count the points of recorded routes instead of features.
+        #[structopt(long)]
+        routes: bool,
+    },
+    /// Manages geofences.
+    Fence(FenceCommand),
+    /// Reports the geofences entered and left while moving through the lat,lng lines of stdin.
+    Track,
+    /// Asks the server whether it is serving.
+    Health {
+        /// The service to ask about; all of them by default.
+        #[structopt(long, default_value = "")]
+        service: String,
+    },
+    /// Converts features, or a route, from one file format to another.
+    Convert {
+        #[structopt(long, parse(from_os_str))]
+        input: PathBuf,
+        #[structopt(long, parse(from_os_str))]
+        output: PathBuf,
+        /// The files hold a route rather than features.
+        #[structopt(long)]
+        route: bool,
+    },
+}
+
+
+#[derive(Debug, StructOpt)]
+enum FenceCommand {
+    /// Adds a geofence, either a rectangle or a circle.
+    Add {
+        #[structopt(long, default_value = "")]
+        name: String,
+        /// Two corners: lat,lng,lat,lng.
+        #[structopt(long, parse(try_from_str = parse_rectangle), allow_hyphen_values = true,
+                    required_unless = "circle", conflicts_with = "circle")]
+        rect: Option<Rectangle>,
+        /// The center and radius in metres: lat,lng,meters.
+        #[structopt(long, parse(try_from_str = parse_circle), allow_hyphen_values = true)]
+        circle: Option<Circle>,
+    },
+    /// Lists the geofences.
+    List,
+    /// Deletes a geofence.
+    Delete {
+        id: String,
+    },
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Output {
+    Table,
+    Json,
+    Ndjson,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Output, String> {
+        match s {
+            "table"  => Ok(Output::Table),
+            "json"   => Ok(Output::Json),
+            "ndjson" => Ok(Output::Ndjson),
+            _ => Err(format!("unknown output format {:?}; use table, json or ndjson", s)),
+        }
+    }
+}
+
+/// Prints rows as they come, or at the end for JSON, which is one array.
+struct Printer {
+    output: Output,
+    columns: &'static [&'static str],
+    rows: Vec<Value>,
+}
+
+impl Printer {
+    fn new(output: Output, columns: &'static [&'static str]) -> Printer {
+        if output == Output::Table {
+            println!("{}", columns.iter().map(|c| c.to_uppercase()).collect::<Vec<_>>().join("\t"));
+        }
+        Printer { output, columns, rows: Vec::new() }
+    }
+
+    fn row(&mut self, row: Value) {
+        match self.output {
+            Output::Table => {
+                let cells: Vec<String> = self.columns.iter().map(|column| match &row[*column] {
+                    Value::String(s) => s.clone(),
+                    Value::Null => String::new(),
+                    other => other.to_string(),
+                }).collect();
+                println!("{}", cells.join("\t"));
+            },
+            Output::Json => self.rows.push(row),
+            Output::Ndjson => println!("{}", row),
+        }
+    }
+
+    fn finish(self) {
+        if self.output == Output::Json {
+            println!("{}", Value::Array(self.rows));
+        }
+    }
+}
+
+
+fn parse_point(text: &str) -> Result<Point, String> {
+    let values = text.split(',').map(degrees_to_e7).collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [latitude, longitude] => Ok(Point { latitude, longitude }),
+        _ => Err(String::from("expected lat,lng")),
+    }
+}
+
+fn parse_circle(text: &str) -> Result<Circle, String> {
+    let mut parts = text.rsplitn(2, ',');
+    let radius = parts.next().and_then(|radius| radius.trim().parse().ok());
+    match (parts.next().map(parse_point), radius) {
+        (Some(center), Some(radius)) => Ok(Circle { center: Some(center?), radius }),
+        _ => Err(String::from("expected lat,lng,meters")),
+    }
+}
+
+fn parse_rectangle(text: &str) -> Result<Rectangle, String> {
+    let values = text.split(',').map(degrees_to_e7).collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [lo_latitude, lo_longitude, hi_latitude, hi_longitude] => Ok(Rectangle {
+            lo: Some(Point { latitude: lo_latitude, longitude: lo_longitude }),
+            hi: Some(Point { latitude: hi_latitude, longitude: hi_longitude }),
+        }),
+        _ => Err(String::from("expected lat,lng,lat,lng")),
+    }
+}
+
+fn point_json(point: Option<&Point>) -> Value {
+    // An f64 prints with the shortest digits that read back as the same value, so the degrees
+    // come out exactly as `e7_to_degrees` wrote them.
+    let degrees = |e7| e7_to_degrees(e7).parse::<f64>().map(Value::from).unwrap_or(Value::Null);
+    let point = point.cloned().unwrap_or_default();
+    json!({ "lat": degrees(point.latitude), "lng": degrees(point.longitude) })
+}
+
+fn feature_row(feature: &Feature) -> Value {
+    let point = point_json(feature.location.as_ref());
+    json!({ "lat": point["lat"], "lng": point["lng"], "name": feature.name })
+}
+
+fn nearby_row(nearby: &NearbyFeature) -> Value {
+    let feature = nearby.feature.clone().unwrap_or_default();
+    let point = point_json(feature.location.as_ref());
+    json!({ "lat": point["lat"], "lng": point["lng"], "name": feature.name, "distance": nearby.distance })
+}
+
+fn waypoint_row(waypoint: &Waypoint) -> Value {
+    let point = point_json(waypoint.location.as_ref());
+    json!({ "lat": point["lat"], "lng": point["lng"], "name": waypoint.name, "distance": waypoint.distance })
+}
+
+fn fence_row(fence: &Geofence) -> Value {
+    let shape = match &fence.shape {
+        Some(Shape::Rectangle(rectangle)) => {
+            let (lo, hi) = (point_json(rectangle.lo.as_ref()), point_json(rectangle.hi.as_ref()));
+            format!("rect {},{},{},{}", lo["lat"], lo["lng"], hi["lat"], hi["lng"])
+        },
+        Some(Shape::Circle(circle)) => {
+            let center = point_json(circle.center.as_ref());
+            format!("circle {},{},{}", center["lat"], center["lng"], circle.radius)
+        },
+        None => String::new(),
+    };
+    json!({ "id": fence.id, "name": fence.name, "shape": shape })
+}
+
+fn fence_event_row(event: &GeofenceEvent) -> Value {
+    let point = point_json(event.location.as_ref());
+    let fence = event.geofence.clone().unwrap_or_default();
+    let kind = if event.kind == Kind::Exit as i32 { "exit" } else { "enter" };
+    json!({ "lat": point["lat"], "lng": point["lng"], "event": kind, "id": fence.id, "name": fence.name })
+}
+
+fn cell_row(cell: &Cell) -> Value {
+    let bounds = cell.bounds.clone().unwrap_or_default();
+    let (lo, hi) = (point_json(bounds.lo.as_ref()), point_json(bounds.hi.as_ref()));
+    json!({
+        "lo_lat": lo["lat"],
+        "lo_lng": lo["lng"],
+        "hi_lat": hi["lat"],
+        "hi_lng": hi["lng"],
+        "geohash": cell.geohash,
+        "count": cell.count,
+        "routes": cell.routes,
+    })
+}
+
+fn note_row(note: &RouteNote) -> Value {
+    let point = point_json(note.location.as_ref());
+    json!({ "lat": point["lat"], "lng": point["lng"], "message": note.message })
+}
+
+fn summary_row(summary: &RouteSummary) -> Value {
+    json!({
+        "points": summary.point_count,
+        "features": summary.feature_count,
+        "distance": summary.distance,
+        "seconds": summary.elapsed_time,
+    })
+}
+
+
+/// A failed call, with the details the server sent.
+struct CallError(Status);
+
+impl fmt::Display for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.0.code(), self.0.message())?;
+
+        let details = ErrorDetails::from_status(&self.0);
+        for violation in details.bad_request.iter().flat_map(|bad_request| &bad_request.field_violations) {
+            write!(f, "\n  {}: {}", violation.field, violation.description)?;
+        }
+        if let Some(resource) = &details.resource_info {
+            write!(f, "\n  {} {}: {}", resource.resource_type, resource.resource_name, resource.description)?;
+        }
+        if let Some(info) = &details.error_info {
+            write!(f, "\n  reason: {}", info.reason)?;
+        }
+        if let Some(retry_after) = details.retry_after() {
+            write!(f, "\n  retry after {:?}", retry_after)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for CallError {}
+
+impl From<Status> for CallError {
+    fn from(status: Status) -> CallError {
+        CallError(status)
+    }
+}
+
+
+/// Adds the token, the chat room and the trace context to every call.
+#[allow(clippy::result_large_err)]  // tonic interceptors return a `Status`.
+fn interceptor(token: Option<AsciiMetadataValue>, room: Option<AsciiMetadataValue>)
+    -> impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static {
+    move |mut request: Request<()>| {
+        if let Some(token) = &token {
+            request.metadata_mut().insert("authorization", token.clone());
+        }
+        if let Some(room) = &room {
+            request.metadata_mut().insert(chat::ROOM_METADATA, room.clone());
+        }
+        telemetry::inject_context(request.metadata_mut());
+        Ok(request)
+    }
+}
+
+
+async fn run(options: Options) -> Result<(), Box<dyn Error>> {
+    if let Command::Convert { input, output, route } = &options.command {
+        return convert(input, output, *route);
+    }
+
+    let tls = channel::Tls {
+        ca: &options.ca,
+        domain: &options.domain,
+        identity: options.cert.as_ref().zip(options.key.as_ref()),
+    };
+    let channel = channel::connect(&options.endpoints, tls).await?;
+    let token = match &options.token {
+        Some(token) => Some(MetadataValue::from_str(&format!("Bearer {}", token))?),
+        None => None,
+    };
+    let room = match &options.command {
+        Command::Chat { room, .. } => Some(MetadataValue::from_str(room)?),
+        _ => None,
+    };
+
+    let timeout = Duration::from_secs(options.timeout);
+    let mut client = RouteGuideClient::with_interceptor(channel.clone(), interceptor(token.clone(), room));
+
+    match options.command {
+        Command::GetFeature { lat, lng } => {
+            let point = Point { latitude: lat, longitude: lng };
+            let feature = deadline::call(timeout, client.get_feature(deadline::request(point, timeout)))
+                .await
+                .map_err(CallError)?
+                .into_inner();
+
+            let mut printer = Printer::new(options.output, &["lat", "lng", "name"]);
+            printer.row(feature_row(&feature));
+            printer.finish();
+        },
+
+        Command::List { rect, save } => {
+            let mut stream = client
+                .list_features(deadline::request(rect, timeout))
+                .await
+                .map_err(CallError)?
+                .into_inner();
+
+            let mut printer = Printer::new(options.output, &["lat", "lng", "name"]);
+            let mut features = Vec::new();
+            while let Some(feature) = stream.message().await.map_err(CallError)? {
+                printer.row(feature_row(&feature));
+                features.push(feature);
+            }
+            printer.finish();
+
+            if let Some(save) = save {
+                formats::write_features(&save, &features).map_err(|e| format!("{}: {}", save.display(), e))?;
+            }
+        },
+
+        Command::Nearest { lat, lng, k } => {
+            let request = NearestRequest { point: Some(Point { latitude: lat, longitude: lng }), k };
+            let stream = client.find_nearest(deadline::request(request, timeout)).await.map_err(CallError)?;
+            print_nearby(options.output, stream.into_inner()).await?;
+        },
+
+        Command::Radius { lat, lng, meters } => {
+            let request = RadiusRequest { point: Some(Point { latitude: lat, longitude: lng }), meters };
+            let stream = client.search_radius(deadline::request(request, timeout)).await.map_err(CallError)?;
+            print_nearby(options.output, stream.into_inner()).await?;
+        },
+
+        Command::Search { query, rect, limit } => {
+            let request = SearchRequest { query, area: rect, limit };
+            let mut stream = client
+                .search_features(deadline::request(request, timeout))
+                .await
+                .map_err(CallError)?
+                .into_inner();
+
+            let mut printer = Printer::new(options.output, &["lat", "lng", "name", "score"]);
+            while let Some(result) = stream.message().await.map_err(CallError)? {
+                let mut row = feature_row(&result.feature.unwrap_or_default());
+                row["score"] = json!(result.score);
+                printer.row(row);
+            }
+            printer.finish();
+        },
+
+        Command::Plan { from, to, max_hop, max_distance, save } => {
+            let constraints = RouteConstraints { max_hop, max_distance };
+            let request = PlanRouteRequest { from: Some(from), to: Some(to), constraints: Some(constraints) };
+            let mut stream = client
+                .plan_route(deadline::request(request, timeout))
+                .await
+                .map_err(CallError)?
+                .into_inner();
+
+            let mut printer = Printer::new(options.output, &["lat", "lng", "name", "distance"]);
+            let mut points = Vec::new();
+            while let Some(waypoint) = stream.message().await.map_err(CallError)? {
+                printer.row(waypoint_row(&waypoint));
+                points.extend(waypoint.location);
+            }
+            printer.finish();
+
+            if let Some(save) = save {
+                formats::write_route(&save, &points).map_err(|e| format!("{}: {}", save.display(), e))?;
+            }
+        },
+
+        Command::Record { from_file } => {
+            let points = formats::read_route(&from_file)
+                .map_err(|e| format!("{}: {}", from_file.display(), e))?;
+            let request = deadline::request(stream::iter(points), timeout);
+            let summary = client.record_route(request).await.map_err(CallError)?.into_inner();
+
+            let mut printer = Printer::new(options.output, &["points", "features", "distance", "seconds"]);
+            printer.row(summary_row(&summary));
+            printer.finish();
+        },
+
+        Command::Chat { lat, lng, .. } => {
+            let location = Point { latitude: lat, longitude: lng };
+            let outbound = async_stream::stream! {
+                let mut lines = BufReader::new(tokio::io::stdin()).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    yield RouteNote { location: Some(location.clone()), message: line };
+                }
+            };
+
+            let mut inbound = client.route_chat(Request::new(outbound)).await.map_err(CallError)?.into_inner();
+
+            let mut printer = Printer::new(options.output, &["lat", "lng", "message"]);
+            while let Some(note) = inbound.message().await.map_err(CallError)? {
+                printer.row(note_row(&note));
+            }
+            printer.finish();
+        },
+
+        Command::Heatmap { rect, cell, geohash, routes } => {
+            let grid = match (cell, geohash) {
+                (Some(size), _) => Grid::GridSize(size),
+                (None, Some(precision)) => Grid::GeohashPrecision(precision),
+                (None, None) => unreachable!("structopt requires one of them"),
+            };
+            let request = deadline::request(AggregateRequest { area: Some(rect), grid: Some(grid) }, timeout);
+            let mut stream = if routes {
+                client.aggregate_routes(request).await
+            } else {
+                client.aggregate_features(request).await
+            }
+            .map_err(CallError)?
+            .into_inner();
+
+            let mut printer = Printer::new(
+                options.output,
+                &["lo_lat", "lo_lng", "hi_lat", "hi_lng", "geohash", "count", "routes"],
+            );
+            while let Some(cell) = stream.message().await.map_err(CallError)? {
+                printer.row(cell_row(&cell));
+            }
+            printer.finish();
+        },
+
+        Command::Fence(FenceCommand::Add { name, rect, circle }) => {
+            let shape = match (rect, circle) {
+                (Some(rectangle), _) => Shape::Rectangle(rectangle),
+                (None, Some(circle)) => Shape::Circle(circle),
+                (None, None) => unreachable!("structopt requires one of them"),
+            };
+            let fence = Geofence { id: String::new(), name, shape: Some(shape) };
+            let fence = deadline::call(timeout, client.create_geofence(deadline::request(fence, timeout)))
+                .await
+                .map_err(CallError)?
+                .into_inner();
+
+            let mut printer = Printer::new(options.output, &["id", "name", "shape"]);
+            printer.row(fence_row(&fence));
+            printer.finish();
+        },
+
+        Command::Fence(FenceCommand::List) => {
+            let mut stream = client
+                .list_geofences(deadline::request(ListGeofencesRequest {}, timeout))
+                .await
+                .map_err(CallError)?
+                .into_inner();
+
+            let mut printer = Printer::new(options.output, &["id", "name", "shape"]);
+            while let Some(fence) = stream.message().await.map_err(CallError)? {
+                printer.row(fence_row(&fence));
+            }
+            printer.finish();
+        },
+
+        Command::Fence(FenceCommand::Delete { id }) => {
+            let request = deadline::request(GeofenceId { id }, timeout);
+            let fence = deadline::call(timeout, client.delete_geofence(request)).await.map_err(CallError)?.into_inner();
+
+            let mut printer = Printer::new(options.output, &["id", "name", "shape"]);
+            printer.row(fence_row(&fence));
+            printer.finish();
+        },
+
+        Command::Track => {
+            let outbound = async_stream::stream! {
+                let mut lines = BufReader::new(tokio::io::stdin()).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    match parse_point(&line) {
+                        Ok(point) => yield point,
+                        Err(e) => tracing::warn!(line = %line, "skipping position: {}", e),
+                    }
+                }
+            };
+
+            let mut inbound = client.track_position(Request::new(outbound)).await.map_err(CallError)?.into_inner();
+
+            let mut printer = Printer::new(options.output, &["lat", "lng", "event", "id", "name"]);
+            while let Some(event) = inbound.message().await.map_err(CallError)? {
+                printer.row(fence_event_row(&event));
+            }
+            printer.finish();
+        },
+
+        Command::Health { service } => {
+            let mut health = HealthClient::with_interceptor(channel, interceptor(token, None));
+            let request = deadline::request(HealthCheckRequest { service: service.clone() }, timeout);
+            let response = deadline::call(timeout, health.check(request)).await.map_err(CallError)?.into_inner();
+
+            let status = match response.status {
+                1 => "SERVING",
+                2 => "NOT_SERVING",
+                3 => "SERVICE_UNKNOWN",
+                _ => "UNKNOWN",
+            };
+            let mut printer = Printer::new(options.output, &["service", "status"]);
+            printer.row(json!({ "service": service, "status": status }));
+            printer.finish();
+        },
+
+        Command::Convert { .. } => unreachable!("converted before connecting"),
+    }
+
+    Ok(())
+}
+
+async fn print_nearby(output: Output, mut stream: tonic::Streaming<NearbyFeature>) -> Result<(), CallError> {
+    let mut printer = Printer::new(output, &["lat", "lng", "name", "distance"]);
+    while let Some(nearby) = stream.message().await.map_err(CallError)? {
+        printer.row(nearby_row(&nearby));
+    }
+    printer.finish();
+    Ok(())
+}
+
+fn convert(input: &Path, output: &Path, route: bool) -> Result<(), Box<dyn Error>> {
+    let in_file = |e: formats::FormatError| format!("{}: {}", input.display(), e);
+    let out_file = |e: formats::FormatError| format!("{}: {}", output.display(), e);
+
+    if route {
+        let points = formats::read_route(input).map_err(in_file)?;
+        formats::write_route(output, &points).map_err(out_file)?;
+    } else {
+        let features = formats::read_features(input).map_err(in_file)?;
+        formats::write_features(output, &features).map_err(out_file)?;
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let options = Options::from_args();
+
+    let _telemetry = match telemetry::init("route-guide-cli") {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        },
+    };
+
+    if let Err(e) = run(options).await {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,420 @@
+/*
+-- RouteGuide load generator --
+Keeps a number of workers calling `tonic-server` for a fixed duration, each picking the next call
+from a weighted mix, and writes a JSON summary: throughput, latency percentiles (overall and per
+call) and failures by status code.
+
+    cargo run --release --example tonic-server
+    cargo run --release --example route-guide-load -- --token 1234 --concurrency 64 --duration 30 \
+        --mix get-feature=80,list=10,record=5,chat=5 --distribution features --summary load.json
+
+`--rate` caps the calls per second of all workers together; each worker gets an equal share.
+Points come from `--distribution`: `uniform` (random whole degrees, nearly always a miss for
+GetFeature), `features` (exactly on known features) or `cluster:<lat>,<lng>,<radius>` in E7.
+A RouteChat call opens a stream, sends `--notes` notes and reads the answers until the server
+closes it; its latency is that of the whole stream.
+*/
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream;
+use hdrhistogram::Histogram;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Value};
+use structopt::StructOpt;
+use tonic::metadata::MetadataValue;
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+pub mod route_guide {tonic::include_proto!("route_guide");}
+use route_guide::route_guide_client::RouteGuideClient;
+use route_guide::{Feature, Point, Rectangle, RouteNote};
+
+#[path = "../src/channel.rs"] mod channel;
+#[path = "../src/data.rs"] mod data;
+#[path = "../src/deadline.rs"] mod deadline;
+#[path = "../src/formats.rs"] mod formats;
+#[path = "../src/points.rs"] mod points;
+use points::Distribution;
+
+
+/// Latencies are recorded in microseconds, up to a minute, with 3 significant digits.
+const MAX_LATENCY_US: u64 = 60_000_000;
+
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "route-guide-load", about = "Puts load on the RouteGuide service and measures it.")]
+struct Options {
+    /// Server to connect to. Give it more than once to balance calls between servers.
+    #[structopt(long = "endpoint", default_value = "http://[::1]:50051")]
+    endpoints: Vec<String>,
+
+    /// CA certificate to verify the server with.
+    #[structopt(long, parse(from_os_str), default_value = "data/tls/ca.pem")]
+    ca: PathBuf,
+
+    /// The name the server's certificate is checked against.
+    #[structopt(long, default_value = "example.com")]
+    domain: String,
+
+    /// Bearer token for the authorization metadata.
+    #[structopt(long, env = "ROUTE_GUIDE_TOKEN", hide_env_values = true)]
+    token: Option<String>,
+
+    /// Weights of the calls to make: get-feature, list, record and chat.
+    #[structopt(long, default_value = "get-feature=100")]
+    mix: Mix,
+
+    /// Workers making calls at the same time.
+    #[structopt(long, default_value = "16")]
+    concurrency: usize,
+
+    /// Calls per second of all workers together; as fast as possible if 0.
+    #[structopt(long, default_value = "0")]
+    rate: f64,
+
+    /// Seconds to run for.
+    #[structopt(long, default_value = "10")]
+    duration: u64,
+
+    /// Where points come from: uniform, features or cluster:lat,lng,radius.
+    #[structopt(long, default_value = "uniform")]
+    distribution: Distribution,
+
+    /// The feature database, for `--distribution features`.
+    #[structopt(long, parse(from_os_str), default_value = "data/route_guide_db.json")]
+    features: PathBuf,
+
+    /// Points per RecordRoute call.
+    #[structopt(long, default_value = "10")]
+    points: usize,
+
+    /// Notes per RouteChat stream.
+    #[structopt(long, default_value = "5")]
+    notes: usize,
+
+    /// Seconds a single call may take.
+    #[structopt(long, default_value = "10")]
+    timeout: u64,
+
+    /// Where to write the JSON summary. It is printed as well.
+    #[structopt(long, parse(from_os_str), default_value = "load-summary.json")]
+    summary: PathBuf,
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Call {
+    GetFeature,
+    ListFeatures,
+    RecordRoute,
+    RouteChat,
+}
+
+impl Call {
+    fn name(self) -> &'static str {
+        match self {
+            Call::GetFeature   => "GetFeature",
+            Call::ListFeatures => "ListFeatures",
+            Call::RecordRoute  => "RecordRoute",
+            Call::RouteChat    => "RouteChat",
+        }
+    }
+}
+
+/// Calls with their weights, such as `get-feature=80,chat=20`.
+#[derive(Debug, Clone)]
+struct Mix(Vec<(Call, u32)>);
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mix, String> {
+        let mut calls = Vec::new();
+        for part in s.split(',') {
+            let mut pair = part.splitn(2, '=');
+            let call = match pair.next().unwrap_or("").trim() {
+                "get-feature" => Call::GetFeature,
+                "list"        => Call::ListFeatures,
+                "record"      => Call::RecordRoute,
+                "chat"        => Call::RouteChat,
+                other => return Err(format!("unknown call {:?}; use get-feature, list, record or chat", other)),
+            };
+            let weight = pair
+                .next()
+                .and_then(|weight| weight.trim().parse().ok())
+                .ok_or_else(|| format!("expected a weight in {:?}, like get-feature=80", part))?;
+            calls.push((call, weight));
+        }
+
+        if calls.iter().all(|&(_, weight)| weight == 0) {
+            return Err(String::from("at least one call needs a weight above 0"));
+        }
+        Ok(Mix(calls))
+    }
+}
+
+impl Mix {
+    fn pick<R: Rng>(&self, rng: &mut R) -> Call {
+        let total: u32 = self.0.iter().map(|&(_, weight)| weight).sum();
+        let mut choice = rng.gen_range(0, total);
+        for &(call, weight) in &self.0 {
+            if choice < weight {
+                return call;
+            }
+            choice -= weight;
+        }
+        unreachable!("the choice is below the total of the weights")
+    }
+}
+
+
+/// What one worker, or all of them together, measured.
+struct Stats {
+    latencies: Histogram<u64>,
+    calls: BTreeMap<Call, CallStats>,
+    /// Failed calls by status code.
+    errors: BTreeMap<String, u64>,
+}
+
+struct CallStats {
+    latencies: Histogram<u64>,
+    errors: u64,
+}
+
+impl Stats {
+    fn new() -> Stats {
+        Stats { latencies: histogram(), calls: BTreeMap::new(), errors: BTreeMap::new() }
+    }
+
+    fn record(&mut self, call: Call, latency: Duration, result: Result<(), Status>) {
+        let micros = (latency.as_micros() as u64).clamp(1, MAX_LATENCY_US);
+        let stats = self.calls.entry(call).or_insert_with(|| CallStats { latencies: histogram(), errors: 0 });
+
+        // Failed calls count towards the latencies too; a server that fails fast is still fast.
+        self.latencies.saturating_record(micros);
+        stats.latencies.saturating_record(micros);
+        if let Err(status) = result {
+            stats.errors += 1;
+            *self.errors.entry(format!("{:?}", status.code())).or_insert(0) += 1;
+        }
+    }
+
+    fn merge(&mut self, other: Stats) {
+        self.latencies.add(&other.latencies).expect("histograms have the same range");
+        for (call, stats) in other.calls {
+            let mine = self.calls.entry(call).or_insert_with(|| CallStats { latencies: histogram(), errors: 0 });
+            mine.latencies.add(&stats.latencies).expect("histograms have the same range");
+            mine.errors += stats.errors;
+        }
+        for (code, count) in other.errors {
+            *self.errors.entry(code).or_insert(0) += count;
+        }
+    }
+}
+
+fn histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, MAX_LATENCY_US, 3).expect("the bounds are valid")
+}
+
+fn latency_json(latencies: &Histogram<u64>) -> Value {
+    json!({
+        "p50": latencies.value_at_quantile(0.50),
+        "p99": latencies.value_at_quantile(0.99),
+        "p999": latencies.value_at_quantile(0.999),
+        "max": latencies.max(),
+        "mean": latencies.mean(),
+    })
+}
+
+
+/// Everything a worker needs, shared by all of them.
+#[derive(Clone)]
+struct Plan {
+    client: RouteGuideClient<Channel>,
+    mix: Mix,
+    distribution: Distribution,
+    features: Arc<Vec<Feature>>,
+    points: usize,
+    notes: usize,
+    timeout: Duration,
+    /// Time between the calls of one worker, if the rate is capped.
+    interval: Option<Duration>,
+    end: Instant,
+}
+
+async fn worker(mut plan: Plan, seed: u64) -> Stats {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut stats = Stats::new();
+    let mut ticks = plan.interval.map(tokio::time::interval);
+
+    while Instant::now() < plan.end {
+        if let Some(ticks) = ticks.as_mut() {
+            ticks.tick().await;
+            if Instant::now() >= plan.end {
+                break;
+            }
+        }
+
+        let call = plan.mix.pick(&mut rng);
+        let start = Instant::now();
+        let result = make_call(&mut plan, call, &mut rng).await;
+        stats.record(call, start.elapsed(), result);
+    }
+
+    stats
+}
+
+async fn make_call(plan: &mut Plan, call: Call, rng: &mut StdRng) -> Result<(), Status> {
+    let timeout = plan.timeout;
+    let mut point = || plan.distribution.sample(rng, &plan.features);
+
+    match call {
+        Call::GetFeature => {
+            let request = deadline::request(point(), timeout);
+            deadline::call(timeout, plan.client.get_feature(request)).await?;
+        },
+
+        Call::ListFeatures => {
+            // A square of a degree on each side, around the point.
+            let center = point();
+            let corner = |offset: i32| Point {
+                latitude: center.latitude.saturating_add(offset).clamp(-900_000_000, 900_000_000),
+                longitude: center.longitude.saturating_add(offset).clamp(-1_800_000_000, 1_800_000_000),
+            };
+            let rectangle = Rectangle { lo: Some(corner(-5_000_000)), hi: Some(corner(5_000_000)) };
+
+            let client = &mut plan.client;
+            deadline::call(timeout, async move {
+                let mut stream = client.list_features(deadline::request(rectangle, timeout)).await?.into_inner();
+                while stream.message().await?.is_some() {}
+                Ok(())
+            }).await?;
+        },
+
+        Call::RecordRoute => {
+            let points: Vec<Point> = (0..plan.points).map(|_| point()).collect();
+            let request = deadline::request(stream::iter(points), timeout);
+            deadline::call(timeout, plan.client.record_route(request)).await?;
+        },
+
+        Call::RouteChat => {
+            let notes: Vec<RouteNote> = (0..plan.notes)
+                .map(|i| RouteNote { location: Some(point()), message: format!("load note {}", i) })
+                .collect();
+
+            let client = &mut plan.client;
+            deadline::call(timeout, async move {
+                let mut stream = client.route_chat(deadline::request(stream::iter(notes), timeout)).await?.into_inner();
+                while stream.message().await?.is_some() {}
+                Ok(())
+            }).await?;
+        },
+    }
+
+    Ok(())
+}
+
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    tracing_subscriber::fmt::init();
+    let options = Options::from_args();
+
+    let features = match options.distribution {
+        Distribution::Features => data::load_path(&options.features, data::Mode::Lenient)?.features,
+        _ => Vec::new(),
+    };
+
+    let tls = channel::Tls { ca: &options.ca, domain: &options.domain, identity: None };
+    let channel = channel::connect(&options.endpoints, tls).await?;
+    let token = match &options.token {
+        Some(token) => Some(MetadataValue::from_str(&format!("Bearer {}", token))?),
+        None => None,
+    };
+    #[allow(clippy::result_large_err)]  // tonic interceptors return a `Status`.
+    let client = RouteGuideClient::with_interceptor(channel, move |mut request: Request<()>| {
+        if let Some(token) = &token {
+            request.metadata_mut().insert("authorization", token.clone());
+        }
+        Ok(request)
+    });
+
+    let duration = Duration::from_secs(options.duration);
+    let concurrency = options.concurrency.max(1);
+    let interval = if options.rate > 0.0 {
+        Some(Duration::from_secs_f64(concurrency as f64 / options.rate))
+    } else {
+        None
+    };
+    let plan = Plan {
+        client,
+        mix: options.mix.clone(),
+        distribution: options.distribution.clone(),
+        features: Arc::new(features),
+        points: options.points,
+        notes: options.notes,
+        timeout: Duration::from_secs(options.timeout),
+        interval,
+        end: Instant::now() + duration,
+    };
+
+    let started = Instant::now();
+    let workers: Vec<_> = (0..concurrency)
+        .map(|i| tokio::spawn(worker(plan.clone(), rand::random::<u64>().wrapping_add(i as u64))))
+        .collect();
+
+    let mut stats = Stats::new();
+    for worker in workers {
+        stats.merge(worker.await?);
+    }
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let calls: BTreeMap<&str, Value> = stats.calls
+        .iter()
+        .map(|(call, call_stats)| {
+            (call.name(), json!({
+                "requests": call_stats.latencies.len(),
+                "errors": call_stats.errors,
+                "latency_us": latency_json(&call_stats.latencies),
+            }))
+        })
+        .collect();
+    let requests = stats.latencies.len();
+    let failed: u64 = stats.errors.values().sum();
+
+    let summary = json!({
+        "config": {
+            "endpoints": options.endpoints,
+            "mix": options.mix.0.iter().map(|&(call, weight)| (call.name(), weight)).collect::<BTreeMap<_, _>>(),
+            "concurrency": concurrency,
+            "rate": options.rate,
+            "duration_s": options.duration,
+            "distribution": format!("{:?}", options.distribution),
+        },
+        "elapsed_s": elapsed,
+        "requests": requests,
+        "succeeded": requests - failed,
+        "failed": failed,
+        "throughput_per_s": requests as f64 / elapsed,
+        "latency_us": latency_json(&stats.latencies),
+        "errors_by_code": stats.errors,
+        "calls": calls,
+    });
+
+    let text = serde_json::to_string_pretty(&summary)?;
+    tokio::fs::write(&options.summary, &text).await?;
+    println!("{}", text);
+
+    // A run where everything failed is most likely a setup problem; say so in the exit code.
+    if requests > 0 && failed == requests {
+        let (code, _) = stats.errors.iter().max_by_key(|&(_, &count)| count).expect("failed calls have codes");
+        return Err(format!("every call failed, mostly with {}", code).into());
+    }
+    Ok(())
+}
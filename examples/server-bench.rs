@@ -0,0 +1,200 @@
+/*
+-- Blocking vs. async raw server benchmark --
+Opens many keep-alive connections to each address given and sends `GET /` on all of them, one
+request at a time per connection, for a fixed duration. Prints throughput, latency percentiles and
+how many connections failed or got a 503 for each server.
+
+    export SERVER_MAX_CONNECTIONS=1000 SERVER_MAX_CONNECTIONS_PER_IP=1000
+    cargo run --release --example server
+    cargo run --release --example async-server
+    cargo run --release --example server-bench -- [::1]:8080 [::1]:8081 --connections 500 --seconds 10
+
+Every connection comes from the same address, so the connection limits have to be raised as above;
+with the defaults (4 per address) all but four connections get a 503, and the bench only measures
+the limiter. It doesn't report latencies when most connections were turned away.
+
+With more connections than the `ThreadPool` has workers (8 by default, `SERVER_WORKERS`), the
+blocking server can only serve that many at a time, while the others wait in its queue (or are
+rejected once the queue is full).
+*/
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+
+#[derive(Default)]
+struct Results {
+    latencies: Vec<Duration>,
+    failed_connections: usize,
+    unavailable: usize,
+    errors: usize,
+}
+
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut addresses: Vec<SocketAddr> = Vec::new();
+    let mut connections = 100;
+    let mut seconds = 10;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--connections" => connections = args.next().ok_or("missing value for --connections")?.parse()?,
+            "--seconds"     => seconds = args.next().ok_or("missing value for --seconds")?.parse()?,
+            address         => addresses.push(address.parse()?),
+        }
+    }
+    if addresses.is_empty() {
+        addresses = vec!["[::1]:8080".parse()?, "[::1]:8081".parse()?];
+    }
+
+    for address in addresses {
+        let results = run(address, connections, Duration::from_secs(seconds)).await;
+        report(address, &results, connections, Duration::from_secs(seconds));
+    }
+
+    Ok(())
+}
+
+async fn run(address: SocketAddr, connections: usize, duration: Duration) -> Results {
+    let results = Arc::new(Mutex::new(Results::default()));
+    let deadline = Instant::now() + duration;
+
+    let tasks: Vec<_> = (0..connections)
+        .map(|_| {
+            let results = results.clone();
+            tokio::spawn(async move {
+                let outcome = connection(address, deadline).await;
+
+                let mut results = results.lock().await;
+                match outcome {
+                    Ok((latencies, unavailable)) => {
+                        results.latencies.extend(latencies);
+                        results.unavailable += unavailable;
+                    },
+                    Err(Failure::Connect) => results.failed_connections += 1,
+                    Err(Failure::Request(latencies)) => {
+                        results.latencies.extend(latencies);
+                        results.errors += 1;
+                    },
+                }
+            })
+        })
+        .collect();
+
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    Arc::try_unwrap(results).ok().expect("all tasks have finished").into_inner()
+}
+
+enum Failure {
+    Connect,
+    /// The connection broke after some requests had succeeded.
+    Request(Vec<Duration>),
+}
+
+/// Sends requests on a single keep-alive connection until `deadline`.
+async fn connection(address: SocketAddr, deadline: Instant) -> Result<(Vec<Duration>, usize), Failure> {
+    let mut stream = TcpStream::connect(address).await.map_err(|_| Failure::Connect)?;
+    let request = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+    let mut latencies = Vec::new();
+    let mut unavailable = 0;
+    let mut buffer = Vec::new();
+
+    while Instant::now() < deadline {
+        let start = Instant::now();
+
+        if stream.write_all(request).await.is_err() {
+            return Err(Failure::Request(latencies));
+        }
+        match read_response(&mut stream, &mut buffer).await {
+            Some(503) => {
+                // The server closes the connection after a 503.
+                unavailable += 1;
+                break;
+            },
+            Some(_) => latencies.push(start.elapsed()),
+            None => return Err(Failure::Request(latencies)),
+        }
+    }
+
+    Ok((latencies, unavailable))
+}
+
+/// Reads one response and returns its status code, or `None` if the connection failed.
+async fn read_response(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<u16> {
+    let mut chunk = [0; 4096];
+
+    let head_end = loop {
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let status = head.split(' ').nth(1)?.parse().ok()?;
+    let length: usize = head
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next()?;
+            if name.eq_ignore_ascii_case("content-length") {
+                parts.next()?.trim().parse().ok()
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap_or(0);
+
+    while buffer.len() < head_end + length {
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    buffer.drain(..head_end + length);
+    Some(status)
+}
+
+fn report(address: SocketAddr, results: &Results, connections: usize, duration: Duration) {
+    let mut latencies = results.latencies.clone();
+    latencies.sort();
+
+    let percentile = |p: f64| -> Duration {
+        if latencies.is_empty() {
+            return Duration::from_secs(0);
+        }
+        let index = ((latencies.len() as f64 * p).ceil() as usize).saturating_sub(1);
+        latencies[index.min(latencies.len() - 1)]
+    };
+
+    println!("{}", address);
+    if results.unavailable * 2 > connections {
+        println!("  {} of {} connections got a 503; raise SERVER_MAX_CONNECTIONS and", results.unavailable, connections);
+        println!("  SERVER_MAX_CONNECTIONS_PER_IP on the server to measure it rather than its limits");
+    } else {
+        println!("  requests:           {}", latencies.len());
+        println!("  throughput:         {:.0} req/s", latencies.len() as f64 / duration.as_secs_f64());
+        println!("  latency p50:        {:?}", percentile(0.50));
+        println!("  latency p99:        {:?}", percentile(0.99));
+        println!("  latency max:        {:?}", latencies.last().cloned().unwrap_or_default());
+    }
+    println!("  503 responses:      {}", results.unavailable);
+    println!("  failed connections: {}", results.failed_connections);
+    println!("  broken connections: {}", results.errors);
+}
@@ -1,125 +1,279 @@
 use std::fs;
-use std::thread;
 use std::io::prelude::*;
+use std::io::ErrorKind;
 use std::net::{TcpListener, TcpStream};
 use std::num::NonZeroU32;
-use std::sync::{mpsc, Arc, Mutex};
-use crate::Message::{NewJob, Terminate};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+#[path = "../src/connection_limits.rs"] mod connection_limits;
+#[path = "../src/error_pages.rs"] mod error_pages;
+#[path = "../src/http.rs"] mod http;
+#[path = "../src/pages.rs"] mod pages;
+#[path = "../src/telemetry.rs"] mod telemetry;
+#[path = "../src/thread_pool.rs"] mod thread_pool;
+use connection_limits::{ConnectionLimiter, ServerConfig};
+use thread_pool::{RejectionPolicy, ThreadPool};
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
-
-pub enum Message {
-    NewJob(Job),
-    Terminate
-}
 
+fn main() -> std::io::Result<()> {
+    // Log to stderr, at the level set in `RUST_LOG` (info by default).
+    telemetry::init_logging();
 
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
-}
+    let config      = Arc::new(ServerConfig::from_env());
+    let limiter     = ConnectionLimiter::new(&config);
+    let listener    = TcpListener::bind("[::1]:8080")?;
+    let workers     = NonZeroU32::new(config.workers as u32).unwrap_or_else(|| NonZeroU32::new(1).unwrap());
+    let thread_pool = ThreadPool::builder(workers)
+        .queue_capacity(config.queue_capacity())
+        .rejection_policy(RejectionPolicy::Reject)
+        .build();
 
-impl ThreadPool {
-    pub fn new(size: NonZeroU32) -> ThreadPool {
-        let size = size.get() as usize;
-        let (sender, receiver) = mpsc::channel();
+    for stream in listener.incoming() {
+        let mut stream = stream?;
 
-        let mut workers = Vec::with_capacity(size);
-        let receiver = Arc::new(Mutex::new(receiver));
+        // Check the limits before the connection takes up a place in the queue.
+        let peer = match stream.peer_addr() {
+            Ok(peer) => peer,
+            Err(_) => continue,
+        };
+        let guard = match limiter.acquire(peer.ip()) {
+            Ok(guard) => guard,
+            Err(rejection) => {
+                tracing::info!(%peer, %rejection, "rejected connection");
+                reject(&mut stream);
+                continue;
+            },
+        };
 
-        for id in 0..size {
-            workers.push(Worker::new(id, receiver.clone()));
+        // The limiter allows as many connections as there are workers and places in the queue,
+        // but connections that are about to close still count while the workers take new ones
+        // off the queue. Only this thread adds to the queue, so it can't fill up after the check.
+        if thread_pool.queued() >= config.queue_capacity() {
+            tracing::info!(%peer, "rejected connection: the queue is full");
+            reject(&mut stream);
+            continue;
         }
 
-        ThreadPool { workers, sender }
-    }
+        let config = config.clone();
+        let job = move || {
+            let span = tracing::info_span!("connection", %peer);
+            let _entered = span.enter();
+            handle_connection(stream, &config);
+            drop(guard);
+        };
 
-    pub fn execute<F>(&self, f: F)
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        let job = Box::new(f);
-        self.sender.send(NewJob(job)).unwrap();
+        if let Err(e) = thread_pool.execute(job) {
+            tracing::warn!(%peer, error = %e, "dropping connection");
+        }
     }
+
+    Ok(())
 }
 
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        for _ in &self.workers {
-            self.sender.send(Terminate);
-        }
+/// Answers a connection we won't serve with a 503. Done on the accepting thread, so the write
+/// must not be allowed to block for long.
+fn reject(stream: &mut TcpStream) {
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+    let response = pages::error_response(503, None)
+        .header("Connection", "close")
+        .header("Retry-After", 1);
+    let _ = response.write_to(stream, true);
+}
 
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
-        }
+// TcpStream needs needs to be mut because its internal state might change, as it keeps track of
+// what data it returns to us.
+fn handle_connection(mut stream: TcpStream, config: &ServerConfig) {
+    if stream.set_write_timeout(Some(config.write_timeout)).is_err() {
+        return;
     }
-}
 
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
-}
+    let mut parser = http::Parser::new(http::Limits::default());
+    let mut buffer = [0; 4096];
+
+    // When the first byte of the request being received arrived, or `None` while the connection
+    // is idle between requests. The first request is timed from when the connection was accepted.
+    let mut request_started = Some(Instant::now());
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
+    loop {
+        // Answer every complete request in the buffer before reading more, so pipelined requests
+        // are served in order.
+        match parser.next_request() {
+            Ok(Some(request)) => {
+                let span = tracing::info_span!(
+                    "request",
+                    method = %request.method,
+                    target = %request.target,
+                    status = tracing::field::Empty,
+                );
+                let _entered = span.enter();
 
-            match message {
-                NewJob(job) => {
-                    println!("Worker {} got a job; executing.", id);
-                    job();
+                let keep_alive = request.keep_alive();
+                let mut response = route(&request);
+                span.record("status", response.status);
+                if !keep_alive {
+                    response = response.header("Connection", "close");
                 }
-                Message::Terminate => {
+
+                let include_body = request.method != "HEAD";
+                if response.write_to(&mut stream, include_body).is_err() || !keep_alive {
                     return;
                 }
-            }
-        });
 
-        Worker { id, thread: Some(thread) }
+                request_started = if parser.buffered() > 0 { Some(Instant::now()) } else { None };
+                continue;
+            },
+            Ok(None) => {},
+            Err(error) => {
+                tracing::info!(%error, "rejected request");
+                let response = pages::error_response(error.status(), None).header("Connection", "close");
+                let _ = response.write_to(&mut stream, true);
+                return;
+            },
+        }
+
+        // Idle connections get `idle_timeout`. Once a request has started, its headers must be
+        // complete within `header_timeout` however slowly the bytes trickle in, and after that
+        // each read of the body gets `read_timeout`.
+        let timeout = match request_started {
+            None => config.idle_timeout,
+            Some(started) if !parser.has_complete_head() => {
+                let remaining = config.header_timeout.checked_sub(started.elapsed());
+                match remaining {
+                    Some(remaining) if remaining > Duration::from_millis(0) => remaining.min(config.read_timeout),
+                    _ => return request_timeout(&mut stream),
+                }
+            },
+            Some(_) => config.read_timeout,
+        };
+
+        if stream.set_read_timeout(Some(timeout)).is_err() {
+            return;
+        }
+
+        match stream.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(n) => {
+                request_started.get_or_insert_with(Instant::now);
+                parser.feed(&buffer[..n]);
+            },
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                // Closing an idle keep-alive connection is normal; timing out mid-request isn't.
+                if request_started.is_some() {
+                    request_timeout(&mut stream);
+                }
+                return;
+            },
+            Err(_) => return,
+        }
     }
 }
 
+fn request_timeout(stream: &mut TcpStream) {
+    tracing::info!("request timed out");
+    let response = pages::error_response(408, None).header("Connection", "close");
+    let _ = response.write_to(stream, true);
+}
 
-fn main() -> std::io::Result<()> {
-    let listener    = TcpListener::bind("[::1]:8080")?;
-    let thread_pool = ThreadPool::new(NonZeroU32::new(4).unwrap());
+fn route(request: &http::Request) -> http::Response {
+    match pages::resolve(request) {
+        pages::Route::Page(path) => pages::page_response(request, path, fs::read(path)),
+        pages::Route::Error(status) => pages::error_response(status, Some(request)),
+    }
+}
 
-    for stream in listener.incoming() {
-        let stream = stream?;
-        thread_pool.execute(|| {
-            handle_connection(stream);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Shutdown, SocketAddr};
+    use std::thread;
+
+    /// Serves one connection on a thread with `config`, like a worker would.
+    fn serve_one(config: ServerConfig) -> (SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, &config);
         });
+        (address, server)
     }
 
-    Ok(())
-}
+    fn short_timeouts() -> ServerConfig {
+        ServerConfig {
+            read_timeout: Duration::from_millis(300),
+            header_timeout: Duration::from_millis(500),
+            idle_timeout: Duration::from_millis(200),
+            ..ServerConfig::default()
+        }
+    }
 
-// TcpStream needs needs to be mut because its internal state might change, as it keeps track of
-// what data it returns to us.
-fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
+    fn read_all(stream: &mut TcpStream) -> String {
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    }
+
+    #[test]
+    fn times_out_headers_that_trickle_in() {
+        let (address, server) = serve_one(short_timeouts());
+        let mut client = TcpStream::connect(address).unwrap();
+        let started = Instant::now();
+
+        // A byte every 100 ms never lets a single read time out, but the headers don't complete.
+        for &byte in b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Slow: yes" {
+            if client.write_all(&[byte]).is_err() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+            if started.elapsed() > Duration::from_secs(2) {
+                break;
+            }
+        }
 
-    stream.read(&mut buffer).unwrap();
+        let response = read_all(&mut client);
+        assert!(response.starts_with("HTTP/1.1 408 "), "{:?}", response);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        server.join().unwrap();
+    }
 
-    // The “lossy” part of the name indicates the behavior of this function when it sees an
-    // invalid UTF-8 sequence: it will replace the invalid sequence with �.
-    println!("Request: {}", String::from_utf8_lossy(&buffer[..]));
+    #[test]
+    fn times_out_a_body_that_stops_arriving() {
+        let (address, server) = serve_one(short_timeouts());
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc").unwrap();
 
-    let get = b"GET / HTTP/1.1\r\n";
-    let (status, filename) = if buffer.starts_with(get) {
-        ("200 OK", "static/html/index.html")
-    } else {
-        ("404 NOT FOUND", "static/html/errors/404.html")
-    };
+        let response = read_all(&mut client);
+        assert!(response.starts_with("HTTP/1.1 408 "), "{:?}", response);
+        server.join().unwrap();
+    }
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let response = format!("HTTP/1.1 {}\r\n\r\n{}", status, contents);
+    #[test]
+    fn closes_idle_connections_without_an_answer() {
+        let (address, server) = serve_one(short_timeouts());
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
 
-    stream.write(response.as_bytes()).unwrap();
-    stream.flush().unwrap();
+        // The first answer, and then nothing until the idle keep-alive connection is closed.
+        let started = Instant::now();
+        let response = read_all(&mut client);
+        assert_eq!(response.matches("HTTP/1.1 ").count(), 1, "{:?}", response);
+        assert!(started.elapsed() < Duration::from_secs(2));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn answers_a_half_closed_connection() {
+        let (address, server) = serve_one(short_timeouts());
+        let mut client = TcpStream::connect(address).unwrap();
+        client.write_all(b"GET /missing HTTP/1.0\r\n\r\n").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+
+        let response = read_all(&mut client);
+        assert!(response.starts_with("HTTP/1.1 404 "), "{:?}", response);
+        server.join().unwrap();
+    }
 }
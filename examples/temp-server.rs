@@ -6,6 +6,8 @@ use std::time::Duration;
 use tokio::time::delay_for;
 use tonic_health::server::HealthReporter;
 
+#[path = "../src/telemetry.rs"] mod telemetry;
+
 pub mod hello_world {
     tonic::include_proto!("helloworld");
 }
@@ -19,7 +21,7 @@ impl Greeter for MyGreeter {
         &self,
         request: Request<HelloRequest>,
     ) -> Result<Response<HelloReply>, Status> {
-        println!("Got a request from {:?}", request.remote_addr());
+        tracing::info!(remote_addr = ?request.remote_addr(), "got a request");
 
         let reply = hello_world::HelloReply {
             message: format!("Hello {}!", request.into_inner().name),
@@ -46,6 +48,8 @@ async fn twiddle_service_status(mut reporter: HealthReporter) {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    telemetry::init_logging();
+
     let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
     health_reporter
         .set_serving::<GreeterServer<MyGreeter>>()
@@ -56,7 +60,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let addr = "[::1]:50051".parse().unwrap();
     let greeter = MyGreeter::default();
 
-    println!("HealthServer + GreeterServer listening on {}", addr);
+    tracing::info!(%addr, "HealthServer + GreeterServer listening");
 
     Server::builder()
         .add_service(health_service)
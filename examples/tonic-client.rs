@@ -2,17 +2,24 @@ use std::error::Error;
 use std::time::Duration;
 
 use futures::stream;
-use rand::rngs::ThreadRng;
 use rand::Rng;
 use tokio::time;
 use tonic::metadata::MetadataValue;
 use tonic::transport::{Certificate, Channel, ClientTlsConfig};
 use tonic::Request;
+use tracing_futures::Instrument;
 
 pub mod route_guide {tonic::include_proto!("route_guide");}
 use route_guide::route_guide_client::RouteGuideClient;
 use route_guide::{Point, Rectangle, RouteNote};
 
+#[path = "../src/deadline.rs"] mod deadline;
+#[path = "../src/error.rs"] mod error;
+#[path = "../src/points.rs"] mod points;
+#[path = "../src/telemetry.rs"] mod telemetry;
+use error::ErrorDetails;
+use points::random_point;
+
 
 async fn print_features(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
     let rectangle = Rectangle {
@@ -26,8 +33,9 @@ async fn print_features(client: &mut RouteGuideClient<Channel>) -> Result<(), Bo
         }),
     };
 
+    // The server stops listing when the deadline passes, and so do we.
     let mut stream = client
-        .list_features(Request::new(rectangle))
+        .list_features(deadline::request(rectangle, Duration::from_secs(10)))
         .await?
         .into_inner();
 
@@ -52,7 +60,7 @@ async fn run_record_route(client: &mut RouteGuideClient<Channel>) -> Result<(),
 
     match client.record_route(request).await {
         Ok(response) => println!("SUMMARY: {:?}", response.into_inner()),
-        Err(e) => println!("something went wrong: {:?}", e),
+        Err(status) => print_error(&status),
     }
 
     Ok(())
@@ -88,18 +96,29 @@ async fn run_route_chat(client: &mut RouteGuideClient<Channel>) -> Result<(), Bo
     Ok(())
 }
 
-fn random_point(rng: &mut ThreadRng) -> Point {
-    let latitude = (rng.gen_range(0, 180) - 90) * 10_000_000;
-    let longitude = (rng.gen_range(0, 360) - 180) * 10_000_000;
-    Point {
-        latitude,
-        longitude,
+fn print_error(status: &tonic::Status) {
+    println!("ERROR = {:?}: {}", status.code(), status.message());
+
+    let details = ErrorDetails::from_status(status);
+    for violation in details.bad_request.iter().flat_map(|bad_request| &bad_request.field_violations) {
+        println!("  field {}: {}", violation.field, violation.description);
+    }
+    if let Some(resource) = &details.resource_info {
+        println!("  resource {} {}: {}", resource.resource_type, resource.resource_name, resource.description);
+    }
+    if let Some(info) = &details.error_info {
+        println!("  reason {} ({})", info.reason, info.domain);
+    }
+    if let Some(retry_after) = details.retry_after() {
+        println!("  retry after {:?}", retry_after);
     }
 }
 
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let _telemetry = telemetry::init("route-guide-client")?;
+
     // TLS.
     let pem = tokio::fs::read("data/tls/ca.pem").await?;
     let ca  = Certificate::from_pem(pem);
@@ -119,11 +138,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             })
     );
 
-    // Authentication
+    // Authentication, and the trace context of the span the call is made in.
     let token = "1234";
     let token = MetadataValue::from_str(&format!("Bearer {}", token))?;
     let authentication = move |mut request: Request<()>| {
         request.metadata_mut().insert("authorization", token.clone());
+        telemetry::inject_context(request.metadata_mut());
         Ok(request)
     };
 
@@ -132,22 +152,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 
     println!("*** SIMPLE RPC ***");
-    let response = client
-        .get_feature(Request::new(Point {
-            latitude: 409_146_138,
-            longitude: -746_188_906,
-        }))
+    let timeout = Duration::from_secs(2);
+    let point = Point {
+        latitude: 409_146_138,
+        longitude: -746_188_906,
+    };
+    let response = deadline::call(timeout, client.get_feature(deadline::request(point, timeout)))
+        .instrument(tracing::info_span!("get_feature", otel.kind = "client"))
         .await?;
     println!("RESPONSE = {:?}", response);
 
+    // Nothing is there, which the server reports with the point as the missing resource.
+    match client.get_feature(Request::new(Point { latitude: 0, longitude: 0 })).await {
+        Ok(response) => println!("RESPONSE = {:?}", response),
+        Err(status) => print_error(&status),
+    }
+
     println!("\n*** SERVER STREAMING ***");
-    print_features(&mut client).await?;
+    print_features(&mut client).instrument(tracing::info_span!("list_features", otel.kind = "client")).await?;
 
     println!("\n*** CLIENT STREAMING ***");
-    run_record_route(&mut client).await?;
+    run_record_route(&mut client).instrument(tracing::info_span!("record_route", otel.kind = "client")).await?;
 
     println!("\n*** BIDIRECTIONAL STREAMING ***");
-    run_route_chat(&mut client).await?;
+    run_route_chat(&mut client).instrument(tracing::info_span!("route_chat", otel.kind = "client")).await?;
 
     Ok(())
 }
@@ -1,90 +1,81 @@
+// The `async_stream::stream!` of sse.rs nests deeper than the default limit allows.
+#![recursion_limit = "512"]
+
 use std::{
-    collections::HashMap,
-    hash::{Hasher, Hash},
+    convert::Infallible,
     task::{Context, Poll},
     pin::Pin,
     sync::Arc,
-    time::Instant,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
 };
 
 use futures_util::StreamExt;
 use futures_core::Stream;
 
-use hyper::{Body, Request as HyperRequest, Response as HyperResponse};
+use hyper::{Body, Method, Request as HyperRequest, Response as HyperResponse, StatusCode};
+use hyper::service::{make_service_fn, service_fn};
 
 use tower::Service;
 
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast::RecvError, mpsc};
 
 use tonic::{Request, Response, Status, metadata::MetadataValue};
 use tonic::body::BoxBody;
-use tonic::transport::{Identity, Server, NamedService, ServerTlsConfig};
+use tonic::transport::{Server, NamedService, ServerTlsConfig};
+
+use tracing_futures::Instrument;
 
 
 
 // Generated from .proto file.
 pub mod route_guide {tonic::include_proto!("route_guide"); /* The string must match the proto package name */}
 use route_guide::route_guide_server::{RouteGuide, RouteGuideServer};
-use route_guide::{Feature, Point, Rectangle, RouteNote, RouteSummary};
+use route_guide::{
+    AggregateRequest, Cell, Feature, Geofence, GeofenceEvent, GeofenceId, ListGeofencesRequest, NearbyFeature,
+    NearestRequest, PlanRouteRequest, Point, RadiusRequest, Rectangle, RouteNote, RouteSummary, SearchRequest,
+    SearchResult, Waypoint,
+};
+use route_guide::aggregate_request::Grid as RequestGrid;
 
+#[path = "../src/aggregate.rs"] mod aggregate;
+#[path = "../src/chat.rs"] mod chat;
 #[path = "../src/data.rs"] mod data;
+#[path = "../src/deadline.rs"] mod deadline;
+#[path = "../src/error.rs"] mod error;
+#[path = "../src/error_pages.rs"] mod error_pages;
+#[path = "../src/feature_reload.rs"] mod feature_reload;
+#[path = "../src/feature_store.rs"] mod feature_store;
+#[path = "../src/formats.rs"] mod formats;
+#[path = "../src/file_watch.rs"] mod file_watch;
+#[path = "../src/geo.rs"] mod geo;
+#[path = "../src/geofence.rs"] mod geofence;
+#[path = "../src/geohash.rs"] mod geohash;
+#[path = "../src/spatial.rs"] mod spatial;
+#[path = "../src/route_log.rs"] mod route_log;
+#[path = "../src/route_planner.rs"] mod route_planner;
+#[path = "../src/sse.rs"] mod sse;
+#[path = "../src/telemetry.rs"] mod telemetry;
+#[path = "../src/text_index.rs"] mod text_index;
+#[path = "../src/tls.rs"] mod tls;
+#[path = "../src/websocket.rs"] mod websocket;
+use aggregate::{CellCount, Grid};
+use chat::ChatHub;
+use deadline::DeadlinePolicy;
+use error::Error;
+use feature_store::FeatureStore;
+use geo::{get_distance, in_range};
+use geofence::{GeofenceRegistry, Tracker};
+use route_log::RouteLog;
+use route_planner::Constraints;
+use spatial::Nearby;
 
 
-impl Hash for Point {
-    fn hash<H>(&self, state: &mut H) where H: Hasher {
-        self.latitude.hash(state);
-        self.longitude.hash(state);
-    }
-}
-
-impl Eq for Point {}
-
-fn in_range(point: &Point, rect: &Rectangle) -> bool {
-    use std::cmp;
-
-    let lo = rect.lo.as_ref().unwrap();
-    let hi = rect.hi.as_ref().unwrap();
-
-    let left = cmp::min(lo.longitude, hi.longitude);
-    let right = cmp::max(lo.longitude, hi.longitude);
-    let top = cmp::max(lo.latitude, hi.latitude);
-    let bottom = cmp::min(lo.latitude, hi.latitude);
-
-    point.longitude >= left
-        && point.longitude <= right
-        && point.latitude >= bottom
-        && point.latitude <= top
-}
-
-/// Calculates the distance between two points using the "haversine" formula.
-/// This code was taken from http://www.movable-type.co.uk/scripts/latlong.html.
-fn get_distance(p1: &Point, p2: &Point) -> i32 {
-    const CORD_FACTOR: f64 = 1e7;
-    const R: f64 = 6_371_000.0; // meters
-
-    let lat1 = p1.latitude as f64 / CORD_FACTOR;
-    let lat2 = p2.latitude as f64 / CORD_FACTOR;
-    let lng1 = p1.longitude as f64 / CORD_FACTOR;
-    let lng2 = p2.longitude as f64 / CORD_FACTOR;
-
-    let lat_rad1 = lat1.to_radians();
-    let lat_rad2 = lat2.to_radians();
-
-    let delta_lat = (lat2 - lat1).to_radians();
-    let delta_lng = (lng2 - lng1).to_radians();
-
-    let a = (delta_lat / 2f64).sin() * (delta_lat / 2f64).sin()
-        + (lat_rad1).cos() * (lat_rad2).cos() * (delta_lng / 2f64).sin() * (delta_lng / 2f64).sin();
-
-    let c = 2f64 * a.sqrt().atan2((1f64 - a).sqrt());
-
-    (R * c) as i32
-}
-
-
-#[derive(Debug)]
 pub struct RouteGuideService {
-    features: Arc<Vec<Feature>>,
+    store: Arc<FeatureStore>,
+    chat: Arc<ChatHub>,
+    geofences: Arc<GeofenceRegistry>,
+    routes: Arc<RouteLog>,
 }
 
 
@@ -92,29 +83,55 @@ pub struct RouteGuideService {
 impl RouteGuide for RouteGuideService {
     type ListFeaturesStream = mpsc::Receiver<Result<Feature, Status>>;
     type RouteChatStream = Pin<Box<dyn Stream<Item = Result<RouteNote, Status>> + Send + Sync + 'static>>;
+    type FindNearestStream = mpsc::Receiver<Result<NearbyFeature, Status>>;
+    type SearchRadiusStream = mpsc::Receiver<Result<NearbyFeature, Status>>;
+    type SearchFeaturesStream = mpsc::Receiver<Result<SearchResult, Status>>;
+    type PlanRouteStream = mpsc::Receiver<Result<Waypoint, Status>>;
+    type ListGeofencesStream = mpsc::Receiver<Result<Geofence, Status>>;
+    type TrackPositionStream = Pin<Box<dyn Stream<Item = Result<GeofenceEvent, Status>> + Send + Sync + 'static>>;
+    type AggregateFeaturesStream = mpsc::Receiver<Result<Cell, Status>>;
+    type AggregateRoutesStream = mpsc::Receiver<Result<Cell, Status>>;
 
     async fn get_feature(&self, request: Request<Point>) -> Result<Response<Feature>, Status> {
-        for feature in &self.features[..] {
-            if feature.location.as_ref() == Some(request.get_ref()) {
+        let point = request.get_ref();
+        error::check(error::point_violations("", Some(point)))?;
+
+        for feature in &self.store.snapshot()[..] {
+            if feature.location.as_ref() == Some(point) {
                 return Ok(Response::new(feature.clone()));
             }
         }
 
-        Ok(Response::new(Feature::default()))
+        let name = format!("{},{}", point.latitude, point.longitude);
+        Err(Error::not_found("route_guide.Feature", &name, "there is no feature at this point").into())
     }
 
     async fn list_features(&self, request: Request<Rectangle>)
         -> Result<Response<Self::ListFeaturesStream>, Status> {
+        error::check(error::rectangle_violations(request.get_ref()))?;
+
         let (mut tx, rx) = mpsc::channel(4);
-        let features = self.features.clone();
+        let features = self.store.snapshot();
+        let deadline = deadline::from_metadata(request.metadata());
 
         tokio::spawn(async move {
-            for feature in &features[..] {
-                if in_range(feature.location.as_ref().unwrap(), request.get_ref()) {
-                    tx.send(Ok(feature.clone())).await.unwrap();
+            let rectangle = request.into_inner();
+            let send_all = async {
+                for feature in &features[..] {
+                    if feature.location.as_ref().is_some_and(|location| in_range(location, &rectangle)) {
+                        // The receiver is dropped when the client cancels the call, so stop then.
+                        if tx.send(Ok(feature.clone())).await.is_err() {
+                            return;
+                        }
+                    }
                 }
+            };
+
+            if let Err(status) = deadline::run_until(deadline, send_all).await {
+                // Don't wait for room for it; a client that doesn't read has its own deadline.
+                let _ = tx.try_send(Err(status));
             }
-        });
+        }.instrument(tracing::Span::current()));
 
         Ok(Response::new(rx))
     }
@@ -123,30 +140,45 @@ impl RouteGuide for RouteGuideService {
         &self,
         request: Request<tonic::Streaming<Point>>,
     ) -> Result<Response<RouteSummary>, Status> {
+        let deadline = deadline::from_metadata(request.metadata());
         let mut stream = request.into_inner();
 
+        let features = self.store.snapshot();
+
         let mut summary = RouteSummary::default();
-        let mut last_point = None;
+        // Kept for AggregateRoutes once the route is complete.
+        let mut route = Vec::new();
         let now = Instant::now();
 
-        while let Some(point) = stream.next().await {
+        while let Some(point) = deadline::run_until(deadline, stream.next()).await? {
             let point = point?;
+            // Fields of a client stream are named by the index of the message.
+            error::check(error::point_violations(&format!("[{}]", summary.point_count), Some(&point)))?;
+            // Every point is kept until the route is complete, so a route can't be any length.
+            if summary.point_count == error::MAX_ROUTE_POINTS {
+                return Err(Error::ResourceExhausted {
+                    reason: "ROUTE_TOO_LONG",
+                    message: format!("a route can have at most {} points", error::MAX_ROUTE_POINTS),
+                }.into());
+            }
             summary.point_count += 1;
 
-            for feature in &self.features[..] {
+            for feature in &features[..] {
                 if feature.location.as_ref() == Some(&point) {
                     summary.feature_count += 1;
                 }
             }
 
-            if let Some(ref last_point) = last_point {
+            if let Some(last_point) = route.last() {
                 summary.distance += get_distance(last_point, &point);
             }
 
-            last_point = Some(point);
+            route.push(point);
         }
 
         summary.elapsed_time = now.elapsed().as_secs() as i32;
+        tracing::debug!(point_count = summary.point_count, feature_count = summary.feature_count, "route recorded");
+        self.routes.record(route);
 
         Ok(Response::new(summary))
     }
@@ -155,42 +187,320 @@ impl RouteGuide for RouteGuideService {
         &self,
         request: Request<tonic::Streaming<RouteNote>>,
     ) -> Result<Response<Self::RouteChatStream>, Status> {
-        let mut notes = HashMap::new();
+        enum Event {
+            Incoming(Option<Result<RouteNote, Status>>),
+            Posted(Result<chat::Posted, RecvError>),
+            DeadlineExceeded,
+        }
+
+        let deadline = deadline::from_metadata(request.metadata());
+        let room = request
+            .metadata()
+            .get(chat::ROOM_METADATA)
+            .and_then(|room| room.to_str().ok())
+            .unwrap_or(chat::DEFAULT_ROOM)
+            .to_string();
         let mut stream = request.into_inner();
+        let chat = self.chat.clone();
+        let (id, mut posted) = chat.join();
 
+        // Notes from this client are answered with every note at the same location, like before,
+        // and notes that other clients (gRPC or WebSocket) post are passed on as they come.
         let output = async_stream::try_stream! {
-            while let Some(note) = stream.next().await {
-                let note = note?;
+            loop {
+                // `yield` doesn't work inside `select!`, so only pick the event there.
+                let event = tokio::select! {
+                    note = stream.next() => Event::Incoming(note),
+                    note = posted.recv() => Event::Posted(note),
+                    _ = deadline::passed(deadline) => Event::DeadlineExceeded,
+                };
+
+                match event {
+                    Event::Incoming(Some(note)) => {
+                        let note = note?;
+                        match error::check(error::point_violations("location", note.location.as_ref())) {
+                            Ok(()) => {},
+                            Err(e) => Err(Status::from(e))?,
+                        }
+
+                        for note in chat.post(id, &room, note) {
+                            yield note;
+                        }
+                    },
+                    Event::Incoming(None) => break,
+                    Event::Posted(Ok(posted)) if posted.is_for(id, &room) => yield posted.note,
+                    Event::Posted(Ok(_)) | Event::Posted(Err(RecvError::Lagged(_))) => {},
+                    Event::Posted(Err(RecvError::Closed)) => break,
+                    Event::DeadlineExceeded => Err(deadline::exceeded())?,
+                }
+            }
+        };
+
+        // The stream is polled after this handler returns, outside the span of the call.
+        let output = output.instrument(tracing::Span::current());
+        Ok(Response::new(Box::pin(output) as Self::RouteChatStream))
+    }
+
+    async fn find_nearest(&self, request: Request<NearestRequest>)
+        -> Result<Response<Self::FindNearestStream>, Status> {
+        let request = request.into_inner();
+        error::check(error::nearest_violations(&request))?;
+
+        let index = self.store.index();
+        let nearby = index.nearest(&request.point.unwrap_or_default(), request.k as usize);
+        Ok(Response::new(send_nearby(nearby)))
+    }
+
+    async fn search_radius(&self, request: Request<RadiusRequest>)
+        -> Result<Response<Self::SearchRadiusStream>, Status> {
+        let request = request.into_inner();
+        error::check(error::radius_violations(&request))?;
+
+        let index = self.store.index();
+        let nearby = index.within(&request.point.unwrap_or_default(), request.meters);
+        Ok(Response::new(send_nearby(nearby)))
+    }
+
+    async fn search_features(&self, request: Request<SearchRequest>)
+        -> Result<Response<Self::SearchFeaturesStream>, Status> {
+        let request = request.into_inner();
+        error::check(error::search_violations(&request))?;
+
+        let limit = if request.limit == 0 { DEFAULT_SEARCH_RESULTS } else { request.limit as usize };
+        let area = request.area;
+        let in_area = |feature: &Feature| match &area {
+            Some(area) => feature.location.as_ref().is_some_and(|location| in_range(location, area)),
+            None => true,
+        };
+
+        let index = self.store.text_index();
+        let results = index
+            .search(&request.query, in_area)
+            .into_iter()
+            .take(limit)
+            .map(|hit| SearchResult { feature: Some(hit.feature.clone()), score: hit.score })
+            .collect();
+        Ok(Response::new(send_all(results)))
+    }
+
+    async fn plan_route(&self, request: Request<PlanRouteRequest>)
+        -> Result<Response<Self::PlanRouteStream>, Status> {
+        let request = request.into_inner();
+        error::check(error::plan_route_violations(&request))?;
 
-                let location = note.location.clone().unwrap();
+        let (from, to) = (request.from.unwrap_or_default(), request.to.unwrap_or_default());
+        let constraints = request.constraints.unwrap_or_default();
+        let constraints = Constraints {
+            max_hop: if constraints.max_hop == 0 { DEFAULT_MAX_HOP } else { constraints.max_hop },
+            max_distance: if constraints.max_distance == 0 { None } else { Some(i64::from(constraints.max_distance)) },
+        };
+
+        let name = format!("{},{}->{},{}", from.latitude, from.longitude, to.latitude, to.longitude);
+
+        // Planning can take a while over a big database, so it gets a thread of its own.
+        let index = self.store.index();
+        let planned = tokio::task::spawn_blocking(move || {
+            route_planner::plan(&index, &from, &to, &constraints).map(|steps| {
+                steps
+                    .into_iter()
+                    .map(|step| Waypoint {
+                        location: Some(step.location),
+                        name: step.feature.map(|feature| feature.name.clone()).unwrap_or_default(),
+                        distance: step.distance.min(i64::from(i32::MAX)) as i32,
+                    })
+                    .collect::<Vec<_>>()
+            })
+        })
+        .await
+        .map_err(|e| Status::from(Error::Internal(e.to_string())))?;
+
+        match planned {
+            Some(waypoints) => Ok(Response::new(send_all(waypoints))),
+            None => Err(Error::not_found("route_guide.Route", &name, "no route within the constraints connects the points").into()),
+        }
+    }
+
+    async fn create_geofence(&self, request: Request<Geofence>) -> Result<Response<Geofence>, Status> {
+        let fence = request.into_inner();
+        error::check(error::geofence_violations(&fence, false))?;
+
+        let fence = self.geofences.create(fence);
+        tracing::info!(id = %fence.id, name = %fence.name, "geofence created");
+        Ok(Response::new(fence))
+    }
+
+    async fn get_geofence(&self, request: Request<GeofenceId>) -> Result<Response<Geofence>, Status> {
+        let id = &request.get_ref().id;
+        self.geofences.get(id).map(Response::new).ok_or_else(|| geofence_not_found(id))
+    }
+
+    async fn list_geofences(&self, _request: Request<ListGeofencesRequest>)
+        -> Result<Response<Self::ListGeofencesStream>, Status> {
+        Ok(Response::new(send_all(self.geofences.list())))
+    }
+
+    async fn update_geofence(&self, request: Request<Geofence>) -> Result<Response<Geofence>, Status> {
+        let fence = request.into_inner();
+        error::check(error::geofence_violations(&fence, true))?;
 
-                let location_notes = notes.entry(location).or_insert(vec![]);
-                location_notes.push(note);
+        let id = fence.id.clone();
+        let fence = self.geofences.update(fence).ok_or_else(|| geofence_not_found(&id))?;
+        tracing::info!(id = %fence.id, name = %fence.name, "geofence updated");
+        Ok(Response::new(fence))
+    }
 
-                for note in location_notes {
-                    yield note.clone();
+    async fn delete_geofence(&self, request: Request<GeofenceId>) -> Result<Response<Geofence>, Status> {
+        let id = &request.get_ref().id;
+        let fence = self.geofences.delete(id).ok_or_else(|| geofence_not_found(id))?;
+        tracing::info!(id = %fence.id, name = %fence.name, "geofence deleted");
+        Ok(Response::new(fence))
+    }
+
+    async fn track_position(
+        &self,
+        request: Request<tonic::Streaming<Point>>,
+    ) -> Result<Response<Self::TrackPositionStream>, Status> {
+        let deadline = deadline::from_metadata(request.metadata());
+        let mut stream = request.into_inner();
+        let geofences = self.geofences.clone();
+        let mut tracker = Tracker::new();
+        let mut count = 0;
+
+        let output = async_stream::try_stream! {
+            while let Some(point) = deadline::run_until(deadline, stream.next()).await? {
+                let point = point?;
+                // Fields of a client stream are named by the index of the message.
+                match error::check(error::point_violations(&format!("[{}]", count), Some(&point))) {
+                    Ok(()) => {},
+                    Err(e) => Err(Status::from(e))?,
+                }
+                count += 1;
+
+                for event in tracker.update(&geofences, &point) {
+                    yield event;
                 }
             }
         };
 
-        Ok(Response::new(Box::pin(output) as Self::RouteChatStream))
+        // The stream is polled after this handler returns, outside the span of the call.
+        let output = output.instrument(tracing::Span::current());
+        Ok(Response::new(Box::pin(output) as Self::TrackPositionStream))
+    }
+
+    async fn aggregate_features(&self, request: Request<AggregateRequest>)
+        -> Result<Response<Self::AggregateFeaturesStream>, Status> {
+        let (area, grid) = aggregate_request(request.into_inner())?;
+
+        let features = self.store.snapshot();
+        let cells = tokio::task::spawn_blocking(move || aggregate::features(&features, &area, grid))
+            .await
+            .map_err(|e| Status::from(Error::Internal(e.to_string())))?;
+        Ok(Response::new(send_all(cells.into_iter().map(cell).collect())))
+    }
+
+    async fn aggregate_routes(&self, request: Request<AggregateRequest>)
+        -> Result<Response<Self::AggregateRoutesStream>, Status> {
+        let (area, grid) = aggregate_request(request.into_inner())?;
+
+        let routes = self.routes.routes();
+        let cells = tokio::task::spawn_blocking(move || aggregate::routes(&routes, &area, grid))
+            .await
+            .map_err(|e| Status::from(Error::Internal(e.to_string())))?;
+        Ok(Response::new(send_all(cells.into_iter().map(cell).collect())))
+    }
+}
+
+#[allow(clippy::result_large_err)]  // The error is what the handler answers with.
+fn aggregate_request(request: AggregateRequest) -> Result<(Rectangle, Grid), Status> {
+    error::check(error::aggregate_violations(&request))?;
+
+    let grid = match request.grid {
+        Some(RequestGrid::GridSize(size)) => Grid::Size(size),
+        Some(RequestGrid::GeohashPrecision(precision)) => Grid::Geohash(precision as usize),
+        None => unreachable!("checked above"),
+    };
+    Ok((request.area.unwrap_or_default(), grid))
+}
+
+fn cell(count: CellCount) -> Cell {
+    Cell {
+        bounds: Some(count.bounds),
+        geohash: count.geohash,
+        count: count.count as i64,
+        routes: count.routes as i64,
     }
 }
 
+fn geofence_not_found(id: &str) -> Status {
+    Error::not_found("route_guide.Geofence", id, "there is no geofence with this id").into()
+}
+
+const DEFAULT_SEARCH_RESULTS: usize = 50;
+const DEFAULT_MAX_HOP: i32 = 10_000; // meters
+
+/// Streams search results in the order they are in, nearest first.
+fn send_nearby(nearby: Vec<Nearby<'_>>) -> mpsc::Receiver<Result<NearbyFeature, Status>> {
+    let results = nearby
+        .into_iter()
+        .map(|nearby| NearbyFeature { feature: Some(nearby.feature.clone()), distance: nearby.distance })
+        .collect();
+    send_all(results)
+}
+
+/// Streams results that are all known up front, stopping if the client goes away.
+fn send_all<T: Send + 'static>(results: Vec<T>) -> mpsc::Receiver<Result<T, Status>> {
+    let (mut tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        for result in results {
+            if tx.send(Ok(result)).await.is_err() {
+                return;
+            }
+        }
+    }.instrument(tracing::Span::current()));
+    rx
+}
+
 fn check_authentication(request: Request<()>) -> Result<Request<()>, Status> {
     let token = "1234";
     let token = MetadataValue::from_str(&format!("Bearer {}", token)).unwrap();
 
     match request.metadata().get("authorization") {
         Some(t) if token == t => Ok(request),
-        _ => Err(Status::unauthenticated("No valid auth token")),
+        Some(_) => Err(Error::Unauthenticated { reason: "INVALID_TOKEN" }.into()),
+        None => Err(Error::Unauthenticated { reason: "MISSING_TOKEN" }.into()),
     }
 }
 
 
+// Calls past this many at once are turned away with UNAVAILABLE and a RetryInfo, rather than
+// queued behind the others. A call counts until its handler has returned the response headers.
+const MAX_CALLS_IN_FLIGHT: usize = 1000;
+const BUSY_RETRY_AFTER: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Clone)]
 struct InterceptedService<S> {
     inner: S,
+    deadlines: Arc<DeadlinePolicy>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+// Counts a call in flight until it is dropped.
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn start(count: &Arc<AtomicUsize>) -> Option<InFlight> {
+        if count.fetch_add(1, Ordering::SeqCst) >= MAX_CALLS_IN_FLIGHT {
+            count.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(InFlight(count.clone()))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl<S> Service<HyperRequest<Body>> for InterceptedService<S>
@@ -211,14 +521,46 @@ impl<S> Service<HyperRequest<Body>> for InterceptedService<S>
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, req: HyperRequest<Body>) -> Self::Future {
+    fn call(&mut self, mut req: HyperRequest<Body>) -> Self::Future {
         let mut svc = self.inner.clone();
 
+        let in_flight = match InFlight::start(&self.in_flight) {
+            Some(in_flight) => in_flight,
+            None => {
+                tracing::warn!(rpc.method = %req.uri().path(), "too many calls in flight; turning one away");
+                let status = Status::from(Error::unavailable("the server is busy", BUSY_RETRY_AFTER));
+                return Box::pin(async move { Ok(status.to_http()) });
+            },
+        };
+
+        let path = req.uri().path().to_string();
+        let timeout = match self.deadlines.apply(&path, req.headers_mut()) {
+            Ok(timeout) => timeout,
+            Err(status) => return Box::pin(async move { Ok(status.to_http()) }),
+        };
+
+        // One span per call, continuing the client's trace if it sent one.
+        let span = tracing::info_span!(
+            "rpc",
+            otel.kind = "server",
+            rpc.system = "grpc",
+            rpc.method = %req.uri().path(),
+        );
+        telemetry::continue_trace(&span, req.headers());
+
+        // This covers the handler up to the response headers. Handlers that stream look at the
+        // deadline themselves.
         Box::pin(async move {
-            // Do async work here....
-            println!("Work is being done...");
-            svc.call(req).await
-        })
+            let _in_flight = in_flight;
+            tracing::debug!(?timeout, "handling call");
+            match timeout {
+                Some(timeout) => match tokio::time::timeout(timeout, svc.call(req)).await {
+                    Ok(response) => response,
+                    Err(_) => Ok(deadline::exceeded().to_http()),
+                },
+                None => svc.call(req).await,
+            }
+        }.instrument(span))
     }
 }
 
@@ -227,13 +569,47 @@ impl<S: NamedService> NamedService for InterceptedService<S> {
 }
 
 
+// The state the HTTP side shares with the gRPC side.
+#[derive(Clone)]
+struct WebState {
+    chat: Arc<ChatHub>,
+    store: Arc<FeatureStore>,
+}
+
+// The HTTP side of the server, for web clients.
+async fn web_service(state: WebState, request: HyperRequest<Body>) -> Result<HyperResponse<Body>, Infallible> {
+    let span = tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        http.method = %request.method(),
+        http.target = %request.uri(),
+        http.status_code = tracing::field::Empty,
+    );
+    telemetry::continue_trace(&span, request.headers());
+    let _entered = span.enter();
+
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/ws/route-chat") => websocket::upgrade(request, state.chat),
+        (&Method::GET, "/events/features") => sse::feature_events(state.store, &request),
+        _ => error_pages::hyper_response(StatusCode::NOT_FOUND, &request),
+    };
+
+    span.record("http.status_code", response.status().as_u16());
+    Ok(response)
+}
+
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // TLS.
-    let cert = tokio::fs::read("data/tls/server.pem").await?;
-    let key  = tokio::fs::read("data/tls/server.key").await?;
-    let identity = Identity::from_pem(cert, key);
-    let tls_config = ServerTlsConfig::new().identity(identity);
+    let _telemetry = telemetry::init("route-guide-server")?;
+
+    // TLS. The identity is read through a resolver that every listener shares, so a new
+    // certificate is used for new handshakes without dropping the connections that are open.
+    let resolver = tls::ReloadingResolver::new(tls::CERTIFICATE_PATH, tls::KEY_PATH)?;
+    resolver.watch();
+    resolver.reload_on_hangup()?;
+    let mut tls_config = ServerTlsConfig::new();
+    tls_config.rustls_server_config(tls::grpc_server_config(resolver));
 
     // Load-balancing.
     let (tx, mut rx) = mpsc::unbounded_channel();
@@ -242,33 +618,108 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .map(|endpoint| endpoint.parse().unwrap());
 
     // Load database.
-    let database = data::load();
-
-    // Create servers.
+    let store = FeatureStore::new(data::load()?);
+
+    // Shared by every listener, so clients on different addresses can chat with each other and see
+    // the same geofences and recorded routes.
+    let chat = ChatHub::new();
+    let geofences = GeofenceRegistry::new();
+    let routes = RouteLog::new();
+
+    // Deadlines for calls whose client doesn't ask for a shorter one. RouteChat and TrackPosition
+    // stay open.
+    let deadlines = Arc::new(DeadlinePolicy::new()
+        .method("/route_guide.RouteGuide/GetFeature", Duration::from_secs(5))
+        .method("/route_guide.RouteGuide/ListFeatures", Duration::from_secs(30))
+        .method("/route_guide.RouteGuide/RecordRoute", Duration::from_secs(5 * 60))
+        .method("/route_guide.RouteGuide/FindNearest", Duration::from_secs(5))
+        .method("/route_guide.RouteGuide/SearchRadius", Duration::from_secs(30))
+        .method("/route_guide.RouteGuide/SearchFeatures", Duration::from_secs(30))
+        .method("/route_guide.RouteGuide/PlanRoute", Duration::from_secs(30))
+        .method("/route_guide.RouteGuide/AggregateFeatures", Duration::from_secs(30))
+        .method("/route_guide.RouteGuide/AggregateRoutes", Duration::from_secs(30)));
+
+    // Health, for load balancers and `route-guide-cli health`. Not behind authentication.
+    let (mut health_reporter, health_service) = tonic_health::server::health_reporter();
+    health_reporter.set_serving::<RouteGuideServer<RouteGuideService>>().await;
+
+    // Edits to the database are picked up without a restart; calls in progress keep the features
+    // they started with.
+    feature_reload::watch(data::DEFAULT_PATH.into(), store.clone(), health_reporter.clone()).await;
+
+    // Create servers. The calls in flight are counted over every listener.
+    let in_flight = Arc::new(AtomicUsize::new(0));
     for address in addresses {
         let service = InterceptedService {
             inner: RouteGuideServer::with_interceptor(
-                RouteGuideService { features: Arc::new(database.clone()) },
+                RouteGuideService {
+                    store: store.clone(),
+                    chat: chat.clone(),
+                    geofences: geofences.clone(),
+                    routes: routes.clone(),
+                },
                 check_authentication
-            )
+            ),
+            deadlines: deadlines.clone(),
+            in_flight: in_flight.clone(),
         };
 
         let serve = Server::builder().
             tls_config(tls_config.clone())?.  // Returns a Server with TLS configuration.
             add_service(service).             // Returns a Router that routes to the service.
+            add_service(health_service.clone()).
             serve(address);                   // Serves the Server (it's async so it's not called until await).
 
         let tx = tx.clone();
         tokio::spawn(async move {
             if let Err(e) = serve.await {
-                eprintln!("Error = {:?}", e);
+                tracing::error!(%address, error = ?e, "gRPC server failed");
             }
 
             tx.send(()).unwrap();
         });
     }
 
+    // Web clients join RouteChat and follow features through plain HTTP.
+    let web_address = "127.0.0.1:3001".parse().unwrap();
+    let web_state = WebState { chat: chat.clone(), store: store.clone() };
+    let make_service = make_service_fn(move |_conn| {
+        let state = web_state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| web_service(state.clone(), request)))
+        }
+    });
+    let web = hyper::Server::bind(&web_address).serve(make_service);
+
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        if let Err(e) = web.await {
+            tracing::error!(address = %web_address, error = ?e, "web server failed");
+        }
+
+        tx.send(()).unwrap();
+    });
+
     rx.recv().await;
 
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_calls_away_past_the_limit_until_one_finishes() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let calls: Vec<InFlight> = (0..MAX_CALLS_IN_FLIGHT).map(|_| InFlight::start(&count).unwrap()).collect();
+
+        assert!(InFlight::start(&count).is_none());
+        assert_eq!(count.load(Ordering::SeqCst), MAX_CALLS_IN_FLIGHT);
+
+        drop(calls);
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+        assert!(InFlight::start(&count).is_some());
+    }
+}
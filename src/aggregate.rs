@@ -0,0 +1,191 @@
+// Counts of features and route points per cell of a grid, for density maps.
+//
+// Cells are either squares of a fixed size in E7, counted from latitude -90 and longitude -180,
+// or geohashes of a given length. Only cells with something in them are returned, ordered by
+// cell: south to north and then west to east for squares, by hash for geohashes.
+#![allow(dead_code)]
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::geo::in_range;
+use crate::geohash;
+use crate::route_guide::{Feature, Point, Rectangle};
+
+
+const MIN_LATITUDE: i64 = -900_000_000;
+const MIN_LONGITUDE: i64 = -1_800_000_000;
+
+
+#[derive(Debug, Clone, Copy)]
+pub enum Grid {
+    /// Squares this many E7 units on each side.
+    Size(i32),
+    /// Geohashes of this many characters.
+    Geohash(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Square { row: i64, column: i64 },
+    Geohash(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct CellCount {
+    pub bounds: Rectangle,
+    /// Empty for squares.
+    pub geohash: String,
+    /// Features, or route points.
+    pub count: u64,
+    /// How many routes have a point in the cell. Zero for features.
+    pub routes: u64,
+}
+
+
+/// The features in `area`, per cell.
+pub fn features(features: &[Feature], area: &Rectangle, grid: Grid) -> Vec<CellCount> {
+    let mut cells: BTreeMap<Key, (u64, u64)> = BTreeMap::new();
+    for location in features.iter().filter_map(|feature| feature.location.as_ref()) {
+        if in_range(location, area) {
+            cells.entry(key(location, grid)).or_insert((0, 0)).0 += 1;
+        }
+    }
+    counts(cells, grid)
+}
+
+/// The points of `routes` in `area`, per cell, and how many of the routes visit each cell.
+pub fn routes(routes: &[Arc<Vec<Point>>], area: &Rectangle, grid: Grid) -> Vec<CellCount> {
+    let mut cells: BTreeMap<Key, (u64, u64)> = BTreeMap::new();
+    for route in routes {
+        let mut visited = Vec::new();
+        for point in route.iter().filter(|point| in_range(point, area)) {
+            let key = key(point, grid);
+            cells.entry(key.clone()).or_insert((0, 0)).0 += 1;
+            visited.push(key);
+        }
+
+        visited.sort();
+        visited.dedup();
+        for key in visited {
+            cells.entry(key).or_insert((0, 0)).1 += 1;
+        }
+    }
+    counts(cells, grid)
+}
+
+
+fn key(point: &Point, grid: Grid) -> Key {
+    match grid {
+        Grid::Size(size) => {
+            let size = i64::from(size.max(1));
+            Key::Square {
+                row: (i64::from(point.latitude) - MIN_LATITUDE).div_euclid(size),
+                column: (i64::from(point.longitude) - MIN_LONGITUDE).div_euclid(size),
+            }
+        },
+        Grid::Geohash(precision) => Key::Geohash(geohash::encode(point, precision)),
+    }
+}
+
+fn counts(cells: BTreeMap<Key, (u64, u64)>, grid: Grid) -> Vec<CellCount> {
+    let size = match grid {
+        Grid::Size(size) => i64::from(size.max(1)),
+        Grid::Geohash(_) => 1,
+    };
+    cells
+        .into_iter()
+        .map(|(key, (count, routes))| match key {
+            Key::Square { row, column } => {
+                // The far corner is the last E7 value in the cell, so cells don't overlap.
+                let corner = |row: i64, column: i64| Point {
+                    latitude: (MIN_LATITUDE + row * size).min(i64::from(i32::MAX)) as i32,
+                    longitude: (MIN_LONGITUDE + column * size).min(i64::from(i32::MAX)) as i32,
+                };
+                let mut hi = corner(row + 1, column + 1);
+                hi.latitude -= 1;
+                hi.longitude -= 1;
+                CellCount {
+                    bounds: Rectangle { lo: Some(corner(row, column)), hi: Some(hi) },
+                    geohash: String::new(),
+                    count,
+                    routes,
+                }
+            },
+            Key::Geohash(hash) => CellCount {
+                bounds: geohash::bounds(&hash).unwrap_or_default(),
+                geohash: hash,
+                count,
+                routes,
+            },
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(latitude: i32, longitude: i32) -> Point {
+        Point { latitude, longitude }
+    }
+
+    fn area(lo: Point, hi: Point) -> Rectangle {
+        Rectangle { lo: Some(lo), hi: Some(hi) }
+    }
+
+    fn feature(latitude: i32, longitude: i32) -> Feature {
+        Feature { name: String::new(), location: Some(point(latitude, longitude)) }
+    }
+
+    fn features_in(features: &[Feature], area: Rectangle, size: i32) -> Vec<CellCount> {
+        super::features(features, &area, Grid::Size(size))
+    }
+
+    #[test]
+    fn counts_features_per_square() {
+        let features = [
+            feature(10, 10),
+            feature(20, 20),
+            feature(150, 10),
+            feature(-10, -10),
+            feature(5_000, 5_000),
+            Feature { name: String::new(), location: None },
+        ];
+        let cells = features_in(&features, area(point(-1_000, -1_000), point(1_000, 1_000)), 100);
+
+        let counts: Vec<(Option<Point>, u64)> = cells.iter().map(|cell| (cell.bounds.lo.clone(), cell.count)).collect();
+        assert_eq!(counts, [
+            (Some(point(-100, -100)), 1),
+            (Some(point(0, 0)), 2),
+            (Some(point(100, 0)), 1),
+        ]);
+        assert_eq!(cells[1].bounds.hi, Some(point(99, 99)));
+        assert!(cells.iter().all(|cell| cell.geohash.is_empty() && cell.routes == 0));
+    }
+
+    #[test]
+    fn counts_features_per_geohash() {
+        let features = [feature(1_000, 1_000), feature(2_000, 2_000), feature(-1_000, 1_000)];
+        let world = area(point(-900_000_000, -1_800_000_000), point(900_000_000, 1_800_000_000));
+        let cells = super::features(&features, &world, Grid::Geohash(2));
+
+        let counts: Vec<(&str, u64)> = cells.iter().map(|cell| (cell.geohash.as_str(), cell.count)).collect();
+        assert_eq!(counts, [("kp", 1), ("s0", 2)]);
+        assert_eq!(cells[1].bounds, geohash::bounds("s0").unwrap());
+    }
+
+    #[test]
+    fn counts_route_points_and_the_routes_through_each_cell() {
+        let routes = vec![
+            Arc::new(vec![point(10, 10), point(20, 20), point(110, 10)]),
+            Arc::new(vec![point(30, 30)]),
+            Arc::new(vec![point(5_000, 5_000)]),
+        ];
+        let cells = super::routes(&routes, &area(point(0, 0), point(1_000, 1_000)), Grid::Size(100));
+
+        let counts: Vec<(u64, u64)> = cells.iter().map(|cell| (cell.count, cell.routes)).collect();
+        assert_eq!(counts, [(3, 2), (1, 1)]);
+    }
+}
@@ -0,0 +1,39 @@
+// Channels from the RouteGuide clients to the server, over TLS. Given more than one endpoint,
+// calls are balanced between them.
+#![allow(dead_code)]
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+
+
+pub struct Tls<'a> {
+    /// The CA certificate to verify the server with.
+    pub ca: &'a Path,
+    /// The name the server's certificate is checked against.
+    pub domain: &'a str,
+    /// A client certificate and its key, for servers that ask for one.
+    pub identity: Option<(&'a PathBuf, &'a PathBuf)>,
+}
+
+pub async fn connect(endpoints: &[String], tls: Tls<'_>) -> Result<Channel, Box<dyn Error>> {
+    let ca = Certificate::from_pem(tokio::fs::read(tls.ca).await?);
+    let mut config = ClientTlsConfig::new().ca_certificate(ca).domain_name(tls.domain);
+    if let Some((cert, key)) = tls.identity {
+        let identity = Identity::from_pem(tokio::fs::read(cert).await?, tokio::fs::read(key).await?);
+        config = config.identity(identity);
+    }
+
+    let endpoints = endpoints
+        .iter()
+        .map(|endpoint| -> Result<Endpoint, Box<dyn Error>> {
+            Ok(Channel::from_shared(endpoint.clone())?.tls_config(config.clone())?)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match endpoints.len() {
+        1 => Ok(endpoints.into_iter().next().unwrap().connect().await?),
+        _ => Ok(Channel::balance_list(endpoints.into_iter())),
+    }
+}
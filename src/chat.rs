@@ -0,0 +1,206 @@
+// The chat state behind RouteChat, shared by the gRPC handler and the WebSocket endpoint.
+//
+// Every note is remembered by location, and posting a note returns the notes at that location so
+// far. Unlike the original RouteChat, which only remembered the notes of its own stream, the
+// history is shared by every stream and WebSocket of a room, and outlives them. To keep it from
+// growing forever, only the last `NOTES_PER_LOCATION` notes at a location are kept, and once notes
+// were posted at `MAX_LOCATIONS` locations the location that was first posted at is forgotten.
+// Notes are also broadcast, so that every connected client (gRPC or WebSocket) sees what the
+// others post.
+//
+// Clients chat in rooms. Notes, and the notes remembered at a location, are only shared within a
+// room. Clients that don't name one are all in the default room, `""`.
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::route_guide::{Point, RouteNote};
+
+
+/// Identifies a connected client, so it isn't sent its own notes twice.
+pub type ClientId = u64;
+
+pub const DEFAULT_ROOM: &str = "";
+/// The gRPC metadata key a RouteChat client names its room in.
+pub const ROOM_METADATA: &str = "x-route-chat-room";
+
+const NOTES_PER_LOCATION: usize = 32;
+/// Locations remembered, over all rooms.
+const MAX_LOCATIONS: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct Posted {
+    pub from: ClientId,
+    pub room: Arc<str>,
+    pub note: RouteNote,
+}
+
+impl Posted {
+    /// Whether a client in `room` should be sent this note.
+    pub fn is_for(&self, id: ClientId, room: &str) -> bool {
+        self.from != id && &*self.room == room
+    }
+}
+
+type Location = (Arc<str>, Point);
+
+#[derive(Default)]
+struct History {
+    notes: HashMap<Location, VecDeque<RouteNote>>,
+    /// The locations in `notes`, in the order they were first posted at.
+    order: VecDeque<Location>,
+}
+
+pub struct ChatHub {
+    history: Mutex<History>,
+    sender: broadcast::Sender<Posted>,
+    next_client_id: AtomicU64,
+}
+
+impl ChatHub {
+    pub fn new() -> Arc<ChatHub> {
+        // Subscribers that fall further behind than this skip ahead.
+        let (sender, _) = broadcast::channel(256);
+        Arc::new(ChatHub {
+            history: Mutex::new(History::default()),
+            sender,
+            next_client_id: AtomicU64::new(1),
+        })
+    }
+
+    pub fn join(&self) -> (ClientId, broadcast::Receiver<Posted>) {
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        (id, self.sender.subscribe())
+    }
+
+    /// Records `note` in `room` and returns the notes remembered at its location in that room,
+    /// oldest first and ending with this one.
+    pub fn post(&self, from: ClientId, room: &str, note: RouteNote) -> Vec<RouteNote> {
+        let room: Arc<str> = Arc::from(room);
+        let key = (room.clone(), note.location.clone().unwrap_or_default());
+
+        let location_notes = {
+            let mut history = self.history.lock().unwrap_or_else(PoisonError::into_inner);
+            let History { notes, order } = &mut *history;
+
+            if !notes.contains_key(&key) {
+                if order.len() == MAX_LOCATIONS {
+                    if let Some(oldest) = order.pop_front() {
+                        notes.remove(&oldest);
+                    }
+                }
+                order.push_back(key.clone());
+            }
+
+            let location_notes = notes.entry(key).or_default();
+            if location_notes.len() == NOTES_PER_LOCATION {
+                location_notes.pop_front();
+            }
+            location_notes.push_back(note.clone());
+            location_notes.iter().cloned().collect()
+        };
+
+        // Sending only fails when nobody is listening, which is fine.
+        let _ = self.sender.send(Posted { from, room, note });
+
+        location_notes
+    }
+}
+
+
+/// A `RouteNote` as WebSocket clients send and receive it:
+/// `{"location": {"latitude": 409146138, "longitude": -746188906}, "message": "Hi"}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JsonNote {
+    pub location: JsonPoint,
+    pub message: String,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct JsonPoint {
+    pub latitude: i32,
+    pub longitude: i32,
+}
+
+impl From<JsonNote> for RouteNote {
+    fn from(note: JsonNote) -> RouteNote {
+        RouteNote {
+            location: Some(Point {
+                latitude: note.location.latitude,
+                longitude: note.location.longitude,
+            }),
+            message: note.message,
+        }
+    }
+}
+
+impl From<&RouteNote> for JsonNote {
+    fn from(note: &RouteNote) -> JsonNote {
+        let location = note.location.clone().unwrap_or_default();
+        JsonNote {
+            location: JsonPoint {
+                latitude: location.latitude,
+                longitude: location.longitude,
+            },
+            message: note.message.clone(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(latitude: i32, message: &str) -> RouteNote {
+        RouteNote { location: Some(Point { latitude, longitude: 0 }), message: message.to_string() }
+    }
+
+    fn messages(notes: Vec<RouteNote>) -> Vec<String> {
+        notes.into_iter().map(|note| note.message).collect()
+    }
+
+    #[test]
+    fn shares_history_by_room_and_location() {
+        let chat = ChatHub::new();
+        assert_eq!(messages(chat.post(1, "a", note(1, "one"))), ["one"]);
+        assert_eq!(messages(chat.post(2, "a", note(1, "two"))), ["one", "two"]);
+        assert_eq!(messages(chat.post(2, "a", note(2, "elsewhere"))), ["elsewhere"]);
+        assert_eq!(messages(chat.post(3, "b", note(1, "other room"))), ["other room"]);
+    }
+
+    #[test]
+    fn keeps_only_the_latest_notes() {
+        let chat = ChatHub::new();
+        for i in 0..NOTES_PER_LOCATION + 5 {
+            chat.post(1, DEFAULT_ROOM, note(1, &i.to_string()));
+        }
+        let notes = messages(chat.post(1, DEFAULT_ROOM, note(1, "last")));
+        assert_eq!(notes.len(), NOTES_PER_LOCATION);
+        assert_eq!(notes.last().map(String::as_str), Some("last"));
+
+        for latitude in 2..MAX_LOCATIONS as i32 + 2 {
+            chat.post(1, DEFAULT_ROOM, note(latitude, "filler"));
+        }
+        let history = chat.history.lock().unwrap();
+        assert_eq!(history.notes.len(), MAX_LOCATIONS);
+        assert!(!history.notes.contains_key(&(Arc::from(DEFAULT_ROOM), Point { latitude: 1, longitude: 0 })));
+    }
+
+    #[tokio::test]
+    async fn broadcasts_notes() {
+        let chat = ChatHub::new();
+        let (id, mut posted) = chat.join();
+        chat.post(id + 1, "a", note(1, "hi"));
+
+        let posted = posted.recv().await.unwrap();
+        assert!(posted.is_for(id, "a"));
+        assert!(!posted.is_for(id + 1, "a"));
+        assert!(!posted.is_for(id, "b"));
+    }
+}
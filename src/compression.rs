@@ -0,0 +1,320 @@
+// Response compression and request decompression for hyper services.
+//
+// Responses are compressed with the best encoding the client lists in `Accept-Encoding` (brotli,
+// gzip or deflate), as a stream wrapped around the original body, so a streamed response like
+// `uppercase_response` stays streamed. Small responses and content types that don't compress well
+// are left alone. Request bodies with a `Content-Encoding` are decompressed the same way, before
+// the handler sees them. A decompressed body fails with `TooLarge` once it grows past
+// `max_decompressed_size`, so a small compressed request can't make a handler that buffers the
+// body run out of memory.
+#![allow(dead_code)]
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future;
+use std::io;
+use std::pin::Pin;
+
+use async_compression::tokio_02::bufread::{BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder, ZlibDecoder, ZlibEncoder};
+use bytes::{Bytes, BytesMut};
+use futures::TryStreamExt as _;
+use hyper::body::HttpBody as _;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::io::AsyncRead;
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use crate::error_pages;
+
+
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Responses whose size is known and smaller than this are sent as they are.
+    pub min_size: u64,
+    /// Content types (or prefixes of them, like `text/`) that are worth compressing.
+    pub content_types: Vec<String>,
+    /// The largest request body, in bytes, that a compressed request may decompress to.
+    pub max_decompressed_size: u64,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> CompressionConfig {
+        CompressionConfig {
+            min_size: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+            ].iter().map(|content_type| content_type.to_string()).collect(),
+            max_decompressed_size: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl CompressionConfig {
+    fn is_compressible(&self, content_type: &str) -> bool {
+        self.content_types.iter().any(|allowed| content_type.starts_with(allowed.as_str()))
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+    Identity,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::Brotli   => "br",
+            Encoding::Gzip     => "gzip",
+            Encoding::Deflate  => "deflate",
+            Encoding::Identity => "identity",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Encoding> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "br"                => Some(Encoding::Brotli),
+            "gzip" | "x-gzip"   => Some(Encoding::Gzip),
+            "deflate"           => Some(Encoding::Deflate),
+            "identity" | ""     => Some(Encoding::Identity),
+            _ => None,
+        }
+    }
+}
+
+
+/// Picks the encoding with the highest quality in an `Accept-Encoding` header. Ties go to the
+/// encoding that compresses best.
+pub fn negotiate(accept_encoding: Option<&str>) -> Encoding {
+    let accept_encoding = match accept_encoding {
+        Some(accept_encoding) => accept_encoding,
+        None => return Encoding::Identity,
+    };
+
+    let mut wildcard = None;
+    let mut qualities = Vec::new();
+    for coding in accept_encoding.split(',') {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let quality = parts
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name == "*" {
+            wildcard = Some(quality);
+        } else if let Some(encoding) = Encoding::from_name(name) {
+            qualities.push((encoding, quality));
+        }
+    }
+
+    let quality_of = |encoding: Encoding| {
+        qualities
+            .iter()
+            .find(|(e, _)| *e == encoding)
+            .map(|(_, quality)| *quality)
+            .or(wildcard)
+            .unwrap_or(0.0)
+    };
+
+    let mut best = (Encoding::Identity, 0.0);
+    for &encoding in &[Encoding::Brotli, Encoding::Gzip, Encoding::Deflate] {
+        let quality = quality_of(encoding);
+        if quality > best.1 {
+            best = (encoding, quality);
+        }
+    }
+    best.0
+}
+
+/// Compresses `response` for a client that sent `accept_encoding`, if it's worth it.
+pub fn compress_response(config: &CompressionConfig, accept_encoding: Option<&HeaderValue>, response: Response<Body>) -> Response<Body> {
+    let encoding = negotiate(accept_encoding.and_then(|value| value.to_str().ok()));
+    if encoding == Encoding::Identity {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+
+    let status = parts.status;
+    let already_encoded = parts.headers.contains_key(header::CONTENT_ENCODING);
+    let compressible = parts.headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| config.is_compressible(content_type));
+    // Streamed bodies have no known size and are assumed to be large.
+    let too_small = body.size_hint().exact().is_some_and(|size| size < config.min_size);
+
+    if status.is_informational() || status == StatusCode::NO_CONTENT || status == StatusCode::NOT_MODIFIED
+        || already_encoded || !compressible || too_small {
+        return Response::from_parts(parts, body);
+    }
+
+    let reader = tokio::io::stream_reader(body.map_err(io::Error::other));
+    let encoder: Pin<Box<dyn AsyncRead + Send>> = match encoding {
+        Encoding::Brotli   => Box::pin(BrotliEncoder::new(reader)),
+        Encoding::Gzip     => Box::pin(GzipEncoder::new(reader)),
+        Encoding::Deflate  => Box::pin(ZlibEncoder::new(reader)),
+        Encoding::Identity => unreachable!(),
+    };
+    let body = Body::wrap_stream(chunks(encoder));
+
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+    append_vary(&mut parts.headers);
+
+    Response::from_parts(parts, body)
+}
+
+/// Replaces a compressed request body with a stream that decompresses it. Requests with an
+/// encoding we don't know are answered with 415 Unsupported Media Type.
+#[allow(clippy::result_large_err)]  // The error is the response, sent right away.
+pub fn decompress_request(config: &CompressionConfig, request: Request<Body>) -> Result<Request<Body>, Response<Body>> {
+    let content_encoding = match request.headers().get(header::CONTENT_ENCODING) {
+        Some(value) => value.to_str().unwrap_or("").to_string(),
+        None => return Ok(request),
+    };
+
+    let encoding = match Encoding::from_name(&content_encoding) {
+        Some(encoding) => encoding,
+        None => return Err(error_pages::hyper_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, &request)),
+    };
+    if encoding == Encoding::Identity {
+        return Ok(request);
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let reader = tokio::io::stream_reader(body.map_err(io::Error::other));
+    let decoder: Pin<Box<dyn AsyncRead + Send>> = match encoding {
+        Encoding::Brotli   => Box::pin(BrotliDecoder::new(reader)),
+        Encoding::Gzip     => Box::pin(GzipDecoder::new(reader)),
+        Encoding::Deflate  => Box::pin(ZlibDecoder::new(reader)),
+        Encoding::Identity => unreachable!(),
+    };
+
+    let limit = config.max_decompressed_size;
+    let mut size = 0u64;
+    let body = Body::wrap_stream(chunks(decoder).and_then(move |chunk| {
+        size += chunk.len() as u64;
+        future::ready(if size > limit {
+            Err(io::Error::new(io::ErrorKind::InvalidData, TooLarge { limit }))
+        } else {
+            Ok(chunk)
+        })
+    }));
+
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Ok(Request::from_parts(parts, body))
+}
+
+/// What reading a decompressed request body fails with once it has more than `limit` bytes.
+#[derive(Debug)]
+pub struct TooLarge {
+    pub limit: u64,
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the decompressed body is larger than {} bytes", self.limit)
+    }
+}
+
+impl StdError for TooLarge {}
+
+/// Whether `error`, such as the error of reading a request body, was caused by `TooLarge`. Handlers
+/// that fail with it should be answered with 413 Payload Too Large.
+pub fn is_too_large(error: &(dyn StdError + 'static)) -> bool {
+    let mut cause = Some(error);
+    while let Some(error) = cause {
+        // An `io::Error` hides the error it wraps from `source`.
+        let wrapped = error.downcast_ref::<io::Error>().and_then(io::Error::get_ref);
+        if error.is::<TooLarge>() || wrapped.is_some_and(|wrapped| wrapped.is::<TooLarge>()) {
+            return true;
+        }
+        cause = error.source();
+    }
+    false
+}
+
+
+/// The bytes of `reader` as a stream of chunks, for `Body::wrap_stream`.
+fn chunks<R: AsyncRead>(reader: R) -> impl futures::Stream<Item = io::Result<Bytes>> {
+    FramedRead::new(reader, BytesCodec::new()).map_ok(BytesMut::freeze)
+}
+
+fn append_vary(headers: &mut HeaderMap) {
+    let already_varies = headers
+        .get_all(header::VARY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|name| name.trim().eq_ignore_ascii_case("accept-encoding") || name.trim() == "*");
+
+    if !already_varies {
+        headers.append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn gzip(data: Vec<u8>) -> Vec<u8> {
+        let reader = tokio::io::stream_reader(futures::stream::iter(vec![Ok::<_, io::Error>(Bytes::from(data))]));
+        let compressed: Vec<Bytes> = chunks(GzipEncoder::new(reader)).try_collect().await.unwrap();
+        compressed.concat()
+    }
+
+    fn gzipped_request(body: Vec<u8>) -> Request<Body> {
+        Request::builder()
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[test]
+    fn negotiates_the_best_accepted_encoding() {
+        assert_eq!(negotiate(None), Encoding::Identity);
+        assert_eq!(negotiate(Some("gzip, deflate, br")), Encoding::Brotli);
+        assert_eq!(negotiate(Some("gzip;q=1, br;q=0.5")), Encoding::Gzip);
+        assert_eq!(negotiate(Some("*;q=0.1, br;q=0")), Encoding::Gzip);
+        assert_eq!(negotiate(Some("compress")), Encoding::Identity);
+    }
+
+    #[tokio::test]
+    async fn decompresses_request_bodies() {
+        let text = b"hello ".repeat(1000);
+        let request = decompress_request(&CompressionConfig::default(), gzipped_request(gzip(text.clone()).await)).unwrap();
+        assert!(request.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(hyper::body::to_bytes(request.into_body()).await.unwrap(), text);
+    }
+
+    #[tokio::test]
+    async fn stops_decompressing_past_the_limit() {
+        // A few kilobytes that decompress to 64 MiB.
+        let bomb = gzip(vec![0; 64 * 1024 * 1024]).await;
+        assert!(bomb.len() < 100 * 1024);
+
+        let config = CompressionConfig { max_decompressed_size: 1024 * 1024, ..CompressionConfig::default() };
+        let request = decompress_request(&config, gzipped_request(bomb)).unwrap();
+        let error = hyper::body::to_bytes(request.into_body()).await.unwrap_err();
+        assert!(is_too_large(&error), "{}", error);
+    }
+
+    #[test]
+    fn rejects_unknown_encodings() {
+        let request = Request::builder().header(header::CONTENT_ENCODING, "zstd").body(Body::empty()).unwrap();
+        let response = decompress_request(&CompressionConfig::default(), request).unwrap_err();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+}
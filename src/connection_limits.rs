@@ -0,0 +1,224 @@
+// Connection limits and timeouts for the blocking server.
+//
+// Without them a handful of clients that connect and then send nothing (or one byte every few
+// seconds, like slowloris does) can occupy every worker in the thread pool forever.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Threads serving connections in the blocking server.
+    pub workers: usize,
+    /// Connections open at once, served or waiting for a worker. Further connections get a 503.
+    pub max_connections: usize,
+    /// Connections open at once for a single client address. The default is below `workers`, so
+    /// that one client can't hold every worker of the blocking server.
+    pub max_connections_per_ip: usize,
+    /// How long a single read may block while a request is being received.
+    pub read_timeout: Duration,
+    /// How long a single write may block.
+    pub write_timeout: Duration,
+    /// Time from the first byte of a request until its headers must be complete.
+    pub header_timeout: Duration,
+    /// How long a keep-alive connection may sit idle between requests.
+    pub idle_timeout: Duration,
+}
+
+impl Default for ServerConfig {
+    fn default() -> ServerConfig {
+        ServerConfig {
+            workers: 8,
+            max_connections: 128,
+            max_connections_per_ip: 4,
+            read_timeout: Duration::from_secs(10),
+            write_timeout: Duration::from_secs(10),
+            header_timeout: Duration::from_secs(5),
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// The defaults, overridden by any of `SERVER_WORKERS`, `SERVER_MAX_CONNECTIONS`,
+    /// `SERVER_MAX_CONNECTIONS_PER_IP`, `SERVER_READ_TIMEOUT_MS`, `SERVER_WRITE_TIMEOUT_MS`,
+    /// `SERVER_HEADER_TIMEOUT_MS` and `SERVER_IDLE_TIMEOUT_MS` that are set.
+    pub fn from_env() -> ServerConfig {
+        let defaults = ServerConfig::default();
+        let millis = |name: &str, default: Duration| {
+            env_var(name).map(Duration::from_millis).unwrap_or(default)
+        };
+
+        ServerConfig {
+            workers: env_var("SERVER_WORKERS").filter(|&workers| workers > 0).unwrap_or(defaults.workers),
+            max_connections: env_var("SERVER_MAX_CONNECTIONS").unwrap_or(defaults.max_connections),
+            max_connections_per_ip: env_var("SERVER_MAX_CONNECTIONS_PER_IP").unwrap_or(defaults.max_connections_per_ip),
+            read_timeout: millis("SERVER_READ_TIMEOUT_MS", defaults.read_timeout),
+            write_timeout: millis("SERVER_WRITE_TIMEOUT_MS", defaults.write_timeout),
+            header_timeout: millis("SERVER_HEADER_TIMEOUT_MS", defaults.header_timeout),
+            idle_timeout: millis("SERVER_IDLE_TIMEOUT_MS", defaults.idle_timeout),
+        }
+    }
+
+    /// How many connections the blocking server queues for a worker: the ones that are allowed
+    /// but can't be served yet.
+    pub fn queue_capacity(&self) -> usize {
+        self.max_connections.saturating_sub(self.workers).max(1)
+    }
+}
+
+fn env_var<T: FromStr>(name: &str) -> Option<T> {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            tracing::warn!(variable = name, value = %value, "ignoring invalid value");
+            None
+        }
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Rejection {
+    TooManyConnections,
+    TooManyConnectionsFromAddress,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TooManyConnections => write!(f, "too many connections"),
+            Rejection::TooManyConnectionsFromAddress => write!(f, "too many connections from this address"),
+        }
+    }
+}
+
+
+#[derive(Default)]
+struct Counts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Counts open connections, globally and per client address.
+pub struct ConnectionLimiter {
+    max_connections: usize,
+    max_connections_per_ip: usize,
+    counts: Mutex<Counts>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: &ServerConfig) -> Arc<ConnectionLimiter> {
+        Arc::new(ConnectionLimiter {
+            max_connections: config.max_connections,
+            max_connections_per_ip: config.max_connections_per_ip,
+            counts: Mutex::new(Counts::default()),
+        })
+    }
+
+    /// Reserves a slot for a connection from `ip`. The slot is released when the returned guard is
+    /// dropped, so keep it alive for as long as the connection is served.
+    pub fn acquire(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionGuard, Rejection> {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if counts.total >= self.max_connections {
+            return Err(Rejection::TooManyConnections);
+        }
+        // Checked before inserting, so rejected addresses don't leave entries behind.
+        if counts.per_ip.get(&ip).copied().unwrap_or(0) >= self.max_connections_per_ip {
+            return Err(Rejection::TooManyConnectionsFromAddress);
+        }
+
+        *counts.per_ip.entry(ip).or_insert(0) += 1;
+        counts.total += 1;
+
+        Ok(ConnectionGuard { limiter: self.clone(), ip })
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner).total
+    }
+}
+
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counts = self.limiter.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        counts.total -= 1;
+
+        if let Some(from_ip) = counts.per_ip.get_mut(&self.ip) {
+            *from_ip -= 1;
+            if *from_ip == 0 {
+                counts.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    fn limiter(max_connections: usize, max_connections_per_ip: usize) -> Arc<ConnectionLimiter> {
+        ConnectionLimiter::new(&ServerConfig { max_connections, max_connections_per_ip, ..ServerConfig::default() })
+    }
+
+    #[test]
+    fn limits_connections_per_address() {
+        let limiter = limiter(10, 2);
+        let (a, b) = (IpAddr::from(Ipv4Addr::new(10, 0, 0, 1)), IpAddr::from(Ipv4Addr::new(10, 0, 0, 2)));
+
+        let first = limiter.acquire(a).unwrap();
+        let _second = limiter.acquire(a).unwrap();
+        assert_eq!(limiter.acquire(a).err(), Some(Rejection::TooManyConnectionsFromAddress));
+        assert!(limiter.acquire(b).is_ok());
+
+        drop(first);
+        assert!(limiter.acquire(a).is_ok());
+    }
+
+    #[test]
+    fn limits_connections_in_total_and_releases_them() {
+        let limiter = limiter(3, 3);
+        let guards: Vec<_> = (1..=3).map(|i| limiter.acquire(IpAddr::from(Ipv4Addr::new(10, 0, 0, i))).unwrap()).collect();
+        assert_eq!(limiter.open_connections(), 3);
+        assert_eq!(limiter.acquire(IpAddr::from(Ipv4Addr::new(10, 0, 0, 9))).err(), Some(Rejection::TooManyConnections));
+
+        drop(guards);
+        assert_eq!(limiter.open_connections(), 0);
+        assert!(limiter.counts.lock().unwrap().per_ip.is_empty());
+    }
+
+    #[test]
+    fn rejections_leave_no_entries() {
+        let limiter = limiter(10, 0);
+        for i in 1..=3 {
+            let ip = IpAddr::from(Ipv4Addr::new(10, 0, 0, i));
+            assert_eq!(limiter.acquire(ip).err(), Some(Rejection::TooManyConnectionsFromAddress));
+        }
+        assert!(limiter.counts.lock().unwrap().per_ip.is_empty());
+        assert_eq!(limiter.open_connections(), 0);
+    }
+
+    #[test]
+    fn one_client_cant_take_every_worker_by_default() {
+        let config = ServerConfig::default();
+        assert!(config.max_connections_per_ip < config.workers);
+        assert_eq!(config.workers + config.queue_capacity(), config.max_connections);
+    }
+}
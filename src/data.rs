@@ -1,33 +1,316 @@
-use serde::Deserialize;
-use std::fs::File;
-
-#[derive(Debug, Deserialize)]
-struct Feature {
-    location: Location,
-    name: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct Location {
-    latitude: i32,
-    longitude: i32,
-}
-
-#[allow(dead_code)]
-pub fn load() -> Vec<crate::route_guide::Feature> {
-    let file = File::open("data/route_guide_db.json").expect("failed to open data file");
-
-    let decoded: Vec<Feature> =
-        serde_json::from_reader(&file).expect("failed to deserialize features");
-
-    decoded
-        .into_iter()
-        .map(|feature| crate::route_guide::Feature {
-            name: feature.name,
-            location: Some(crate::route_guide::Point {
-                longitude: feature.location.longitude,
-                latitude: feature.location.latitude,
-            }),
-        })
-        .collect()
-}
\ No newline at end of file
+// The feature database: a JSON array of `{"location": {"latitude", "longitude"}, "name"}`, with
+// coordinates in E7.
+//
+// Loading checks every feature. A coordinate out of range and a second feature at the same point
+// are problems in both modes; a feature without a name is only one in strict mode, since the
+// database uses those for points with nothing of note. In strict mode any problem fails the load;
+// in lenient mode features with problems are left out (nameless ones are kept) and the problems
+// are returned alongside the features to be logged. The JSON itself is read by
+// `formats::parse_database`, which the database import and export of the CLI use as well.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::formats::{self, FormatError};
+use crate::route_guide::Feature;
+
+
+pub const DEFAULT_PATH: &str = "data/route_guide_db.json";
+
+const MAX_LATITUDE: i32 = 900_000_000;
+const MAX_LONGITUDE: i32 = 1_800_000_000;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    Strict,
+    Lenient,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProblemKind {
+    LatitudeOutOfRange(i32),
+    LongitudeOutOfRange(i32),
+    /// Another feature is at the same point; `first` is its index.
+    Duplicate { first: usize },
+    EmptyName,
+}
+
+/// A problem with the feature at `index` in the array, which starts at `line` and `column`
+/// (from 1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub index: usize,
+    pub line: usize,
+    pub column: usize,
+    pub kind: ProblemKind,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: feature {} ", self.line, self.column, self.index)?;
+        match &self.kind {
+            ProblemKind::LatitudeOutOfRange(latitude) => {
+                write!(f, "has latitude {}, which is not within ±{}", latitude, MAX_LATITUDE)
+            },
+            ProblemKind::LongitudeOutOfRange(longitude) => {
+                write!(f, "has longitude {}, which is not within ±{}", longitude, MAX_LONGITUDE)
+            },
+            ProblemKind::Duplicate { first } => write!(f, "is at the same point as feature {}", first),
+            ProblemKind::EmptyName => write!(f, "has no name"),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// Not JSON, or not an array of features. Lines and columns start at 1; they are 0 when the
+    /// problem isn't at one place in the text.
+    Parse { line: usize, column: usize, message: String },
+    /// Strict mode only: the features that didn't pass.
+    Invalid(Vec<Problem>),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Parse { line, column, message } => write!(f, "line {}, column {}: {}", line, column, message),
+            LoadError::Invalid(problems) => {
+                write!(f, "{} invalid features", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
+
+impl From<FormatError> for LoadError {
+    fn from(e: FormatError) -> LoadError {
+        match e {
+            FormatError::Io(e) => LoadError::Io(e),
+            FormatError::Json { line, column, message } => LoadError::Parse { line, column, message },
+            e => LoadError::Parse { line: 0, column: 0, message: e.to_string() },
+        }
+    }
+}
+
+
+#[derive(Debug, Default)]
+pub struct Loaded {
+    pub features: Vec<Feature>,
+    /// Always empty in strict mode.
+    pub problems: Vec<Problem>,
+}
+
+
+/// The database at `DEFAULT_PATH`, leniently, with the problems logged.
+pub fn load() -> Result<Vec<Feature>, LoadError> {
+    let loaded = load_path(DEFAULT_PATH, Mode::Lenient)?;
+    for problem in &loaded.problems {
+        tracing::warn!(path = DEFAULT_PATH, "{}", problem);
+    }
+    Ok(loaded.features)
+}
+
+pub fn load_path<P: AsRef<Path>>(path: P, mode: Mode) -> Result<Loaded, LoadError> {
+    load_str(&fs::read_to_string(path)?, mode)
+}
+
+pub fn load_reader<R: Read>(mut reader: R, mode: Mode) -> Result<Loaded, LoadError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    load_str(&text, mode)
+}
+
+pub fn load_str(text: &str, mode: Mode) -> Result<Loaded, LoadError> {
+    let features = formats::parse_database(text)?;
+    // Where the features are in the text is only worked out once one has a problem.
+    let mut positions: Option<Positions> = None;
+
+    let mut loaded = Loaded::default();
+    let mut seen = HashMap::new();
+    for (index, feature) in features.into_iter().enumerate() {
+        let location = feature.location.clone().unwrap_or_default();
+        let (latitude, longitude) = (location.latitude, location.longitude);
+        let mut kinds = Vec::new();
+
+        if !(-MAX_LATITUDE..=MAX_LATITUDE).contains(&latitude) {
+            kinds.push(ProblemKind::LatitudeOutOfRange(latitude));
+        }
+        if !(-MAX_LONGITUDE..=MAX_LONGITUDE).contains(&longitude) {
+            kinds.push(ProblemKind::LongitudeOutOfRange(longitude));
+        }
+        match seen.get(&(latitude, longitude)) {
+            Some(&first) => kinds.push(ProblemKind::Duplicate { first }),
+            None => { seen.insert((latitude, longitude), index); },
+        }
+        if feature.name.trim().is_empty() && mode == Mode::Strict {
+            kinds.push(ProblemKind::EmptyName);
+        }
+
+        if kinds.is_empty() {
+            loaded.features.push(feature);
+            continue;
+        }
+        let (line, column) = positions.get_or_insert_with(|| Positions::new(text)).of_element(index);
+        loaded.problems.extend(kinds.into_iter().map(|kind| Problem { index, line, column, kind }));
+    }
+
+    if mode == Mode::Strict && !loaded.problems.is_empty() {
+        return Err(LoadError::Invalid(loaded.problems));
+    }
+    Ok(loaded)
+}
+
+
+/// The lines and columns at which the elements of the top-level array start. Elements must be
+/// asked for in increasing order, so each line is only counted once.
+struct Positions<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+    offset: usize,
+    line: usize,
+    line_start: usize,
+}
+
+impl<'a> Positions<'a> {
+    fn new(text: &'a str) -> Positions<'a> {
+        Positions { text, starts: element_starts(text), offset: 0, line: 1, line_start: 0 }
+    }
+
+    /// The line and column of element `index`, or (0, 0) if there is no such element.
+    fn of_element(&mut self, index: usize) -> (usize, usize) {
+        let offset = match self.starts.get(index) {
+            Some(&offset) if offset >= self.offset => offset,
+            _ => return (0, 0),
+        };
+
+        let skipped = &self.text[self.offset..offset];
+        if let Some(newline) = skipped.rfind('\n') {
+            self.line += skipped.matches('\n').count();
+            self.line_start = self.offset + newline + 1;
+        }
+        self.offset = offset;
+        (self.line, offset - self.line_start + 1)
+    }
+}
+
+/// The byte offsets at which the elements of the top-level array start. Only called on text that
+/// parsed, so it doesn't check the syntax.
+fn element_starts(text: &str) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut expecting = false;
+
+    for (offset, byte) in text.bytes().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {},
+            }
+            continue;
+        }
+        if expecting && !byte.is_ascii_whitespace() && byte != b']' {
+            starts.push(offset);
+            expecting = false;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                expecting = depth == 1;
+            },
+            b']' | b'}' => depth -= 1,
+            b',' if depth == 1 => expecting = true,
+            _ => {},
+        }
+    }
+
+    starts
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATABASE: &str = r#"[
+    {"location": {"latitude": 407838351, "longitude": -746143763}, "name": "Patriots Path"},
+    {"location": {"latitude": 408122808, "longitude": -743999179}, "name": ""},
+    {"location": {"latitude": 950000000, "longitude": 0}, "name": "Too far north"},
+  {"location": {"latitude": 407838351, "longitude": -746143763}, "name": "Again"}
+]"#;
+
+    #[test]
+    fn leaves_out_invalid_features_when_lenient() {
+        let loaded = load_str(DATABASE, Mode::Lenient).unwrap();
+        let names: Vec<&str> = loaded.features.iter().map(|feature| feature.name.as_str()).collect();
+        assert_eq!(names, ["Patriots Path", ""]);
+        assert_eq!(loaded.problems, [
+            Problem { index: 2, line: 4, column: 5, kind: ProblemKind::LatitudeOutOfRange(950_000_000) },
+            Problem { index: 3, line: 5, column: 3, kind: ProblemKind::Duplicate { first: 0 } },
+        ]);
+    }
+
+    #[test]
+    fn fails_on_any_problem_when_strict() {
+        match load_str(DATABASE, Mode::Strict) {
+            Err(LoadError::Invalid(problems)) => {
+                let kinds: Vec<ProblemKind> = problems.into_iter().map(|problem| problem.kind).collect();
+                assert_eq!(kinds, [
+                    ProblemKind::EmptyName,
+                    ProblemKind::LatitudeOutOfRange(950_000_000),
+                    ProblemKind::Duplicate { first: 0 },
+                ]);
+            },
+            other => panic!("expected invalid features, got {:?}", other),
+        }
+
+        let valid = r#"[{"location": {"latitude": 1, "longitude": 2}, "name": "A"}]"#;
+        assert_eq!(load_str(valid, Mode::Strict).unwrap().features.len(), 1);
+    }
+
+    #[test]
+    fn reports_where_the_json_is_broken() {
+        match load_str("[\n  {\"location\": }\n]", Mode::Lenient) {
+            Err(LoadError::Parse { line, column, .. }) => assert_eq!((line, column), (2, 16)),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        assert!(matches!(load_str("{}", Mode::Lenient), Err(LoadError::Parse { line: 0, .. })));
+        assert!(matches!(load_str(r#"[{"location": {"latitude": 1}}]"#, Mode::Lenient), Err(LoadError::Parse { .. })));
+    }
+
+    #[test]
+    fn finds_the_elements_of_the_array() {
+        let text = r#"[ {"name": "a, [b]"}, 1 ,"x\"]" ]"#;
+        assert_eq!(element_starts(text), [2, 22, 25]);
+        assert!(element_starts("[]").is_empty());
+    }
+
+    #[test]
+    fn loads_the_database_in_the_repository() {
+        let loaded = load_path(DEFAULT_PATH, Mode::Lenient).unwrap();
+        assert!(!loaded.features.is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+// Deadlines for gRPC calls, in the `grpc-timeout` header.
+//
+// A client says how long it is willing to wait with `grpc-timeout: <value><unit>`, at most 8
+// digits and a unit of H, M, S, m (milliseconds), u (microseconds) or n (nanoseconds). The server
+// has a default per method as well, and the earlier of the two wins. The server writes the
+// deadline it settled on back into the header, so handlers read one value whether the client sent
+// one or not.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use hyper::header::HeaderValue;
+use hyper::HeaderMap;
+use tokio::time::Instant;
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Request, Status};
+
+
+pub const HEADER: &str = "grpc-timeout";
+
+const MAX_DIGITS: usize = 8;
+
+
+pub fn parse(value: &str) -> Option<Duration> {
+    if value.len() < 2 || value.len() > MAX_DIGITS + 1 || !value.is_char_boundary(value.len() - 1) {
+        return None;
+    }
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// The header value for `timeout`, in the finest unit that fits in 8 digits. Rounds up, so the
+/// deadline is never earlier than asked for.
+pub fn format(timeout: Duration) -> String {
+    const LIMIT: u128 = 100_000_000;
+
+    let nanos = timeout.as_nanos();
+    let units: [(u128, char); 6] = [
+        (1, 'n'),
+        (1_000, 'u'),
+        (1_000_000, 'm'),
+        (1_000_000_000, 'S'),
+        (60 * 1_000_000_000, 'M'),
+        (60 * 60 * 1_000_000_000, 'H'),
+    ];
+
+    for &(size, unit) in &units {
+        let amount = nanos.div_ceil(size);
+        if amount < LIMIT {
+            return format!("{}{}", amount, unit);
+        }
+    }
+    format!("{}H", LIMIT - 1)
+}
+
+
+/// The server side: a default timeout per method, by its full path such as
+/// `/route_guide.RouteGuide/GetFeature`. Methods without one only have the client's deadline.
+#[derive(Debug, Clone, Default)]
+pub struct DeadlinePolicy {
+    defaults: HashMap<String, Duration>,
+}
+
+impl DeadlinePolicy {
+    pub fn new() -> DeadlinePolicy {
+        DeadlinePolicy::default()
+    }
+
+    pub fn method(mut self, path: &str, timeout: Duration) -> DeadlinePolicy {
+        self.defaults.insert(path.to_string(), timeout);
+        self
+    }
+
+    /// The timeout of a call to `path`: the client's or the method's default, whichever is
+    /// shorter. Writes it back to the headers for the handler, and removes an unreadable one.
+    /// A deadline that has already passed fails with DEADLINE_EXCEEDED, so the call isn't made.
+    #[allow(clippy::result_large_err)]  // The error is what the call answers with.
+    pub fn apply(&self, path: &str, headers: &mut HeaderMap) -> Result<Option<Duration>, Status> {
+        let requested = headers
+            .get(HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse);
+
+        let timeout = match (requested, self.defaults.get(path)) {
+            (Some(requested), Some(&default)) => Some(requested.min(default)),
+            (requested, default) => requested.or_else(|| default.cloned()),
+        };
+
+        if timeout == Some(Duration::from_secs(0)) {
+            return Err(exceeded());
+        }
+
+        match timeout.and_then(|timeout| HeaderValue::from_str(&format(timeout)).ok()) {
+            Some(value) => headers.insert(HEADER, value),
+            None => headers.remove(HEADER),
+        };
+        Ok(timeout)
+    }
+}
+
+
+/// For handlers: when the call must be done by, if it has a deadline. Measured from when the
+/// handler asks, so a little later than the client's clock says.
+pub fn from_metadata(metadata: &MetadataMap) -> Option<Instant> {
+    let timeout = metadata.get(HEADER)?.to_str().ok().and_then(parse)?;
+    Some(Instant::now() + timeout)
+}
+
+pub fn exceeded() -> Status {
+    Status::deadline_exceeded("the deadline of the call has passed")
+}
+
+/// Runs `future` until `deadline`, if there is one. Doesn't start it if the deadline has passed.
+pub async fn run_until<F, T>(deadline: Option<Instant>, future: F) -> Result<T, Status>
+where
+    F: Future<Output = T>,
+{
+    match deadline {
+        Some(deadline) if deadline <= Instant::now() => Err(exceeded()),
+        Some(deadline) => tokio::time::timeout_at(deadline, future).await.map_err(|_| exceeded()),
+        None => Ok(future.await),
+    }
+}
+
+
+/// Resolves at `deadline`, or never if there is none. For `select!`.
+pub async fn passed(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::delay_until(deadline).await,
+        None => futures::future::pending().await,
+    }
+}
+
+
+/// The client side: a request that asks the server to give up after `timeout`.
+pub fn request<T>(message: T, timeout: Duration) -> Request<T> {
+    let mut request = Request::new(message);
+    set_timeout(&mut request, timeout);
+    request
+}
+
+pub fn set_timeout<T>(request: &mut Request<T>, timeout: Duration) {
+    if let Ok(value) = MetadataValue::from_str(&format(timeout)) {
+        request.metadata_mut().insert(HEADER, value);
+    }
+}
+
+/// Waits at most `timeout` for a call. Use it with `request`, so the server stops working on the
+/// call at about the same time the client stops waiting for it.
+pub async fn call<F, T>(timeout: Duration, call: F) -> Result<T, Status>
+where
+    F: Future<Output = Result<T, Status>>,
+{
+    match tokio::time::timeout(timeout, call).await {
+        Ok(result) => result,
+        Err(_)     => Err(exceeded()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GET_FEATURE: &str = "/route_guide.RouteGuide/GetFeature";
+
+    fn headers(timeout: Option<&str>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(timeout) = timeout {
+            headers.insert(HEADER, HeaderValue::from_str(timeout).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn parses_every_unit() {
+        assert_eq!(parse("2H"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse("3M"), Some(Duration::from_secs(3 * 60)));
+        assert_eq!(parse("4S"), Some(Duration::from_secs(4)));
+        assert_eq!(parse("5m"), Some(Duration::from_millis(5)));
+        assert_eq!(parse("6u"), Some(Duration::from_micros(6)));
+        assert_eq!(parse("7n"), Some(Duration::from_nanos(7)));
+        assert_eq!(parse("12345678S"), Some(Duration::from_secs(12_345_678)));
+        assert_eq!(parse("0m"), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn refuses_invalid_timeouts() {
+        for value in &["", "1", "S", "123456789S", "5x", "-5S", "+5S", "5 S", "1.5S", "5s", "5é"] {
+            assert_eq!(parse(value), None, "{:?} was parsed", value);
+        }
+    }
+
+    #[test]
+    fn formats_in_the_finest_unit_that_fits() {
+        assert_eq!(format(Duration::from_secs(0)), "0n");
+        assert_eq!(format(Duration::from_nanos(99_999_999)), "99999999n");
+        assert_eq!(format(Duration::from_millis(100)), "100000u");
+        assert_eq!(format(Duration::from_secs(5)), "5000000u");
+        assert_eq!(format(Duration::from_secs(100)), "100000m");
+        assert_eq!(format(Duration::from_secs(100_000)), "100000S");
+        assert_eq!(format(Duration::from_secs(100_000_000)), "1666667M");
+        assert_eq!(format(Duration::MAX), "99999999H");
+        // Rounded up, never down.
+        assert_eq!(format(Duration::new(100, 1)), "100001m");
+        assert_eq!(format(Duration::from_nanos(100_000_001)), "100001u");
+
+        for &timeout in &[Duration::from_nanos(1), Duration::from_millis(1234), Duration::from_secs(86_400)] {
+            let round_trip = parse(&format(timeout)).unwrap();
+            assert!(round_trip >= timeout && round_trip - timeout < Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn applies_the_shorter_of_the_client_and_the_method_deadline() {
+        let policy = DeadlinePolicy::new().method(GET_FEATURE, Duration::from_secs(5));
+
+        let mut shorter = headers(Some("100m"));
+        assert_eq!(policy.apply(GET_FEATURE, &mut shorter).ok(), Some(Some(Duration::from_millis(100))));
+        assert_eq!(shorter[HEADER], "100000u");
+
+        let mut longer = headers(Some("1M"));
+        assert_eq!(policy.apply(GET_FEATURE, &mut longer).ok(), Some(Some(Duration::from_secs(5))));
+        assert_eq!(longer[HEADER], "5000000u");
+
+        let mut none = headers(None);
+        assert_eq!(policy.apply(GET_FEATURE, &mut none).ok(), Some(Some(Duration::from_secs(5))));
+        assert_eq!(none[HEADER], "5000000u");
+
+        // Only the client's, or none at all, for methods without a default.
+        let mut client = headers(Some("3S"));
+        assert_eq!(policy.apply("/other", &mut client).ok(), Some(Some(Duration::from_secs(3))));
+        let mut neither = headers(None);
+        assert_eq!(policy.apply("/other", &mut neither).ok(), Some(None));
+        assert!(neither.get(HEADER).is_none());
+    }
+
+    #[test]
+    fn drops_unreadable_deadlines_and_rejects_expired_ones() {
+        let policy = DeadlinePolicy::new().method(GET_FEATURE, Duration::from_secs(5));
+
+        let mut unreadable = headers(Some("5x"));
+        assert_eq!(policy.apply("/other", &mut unreadable).ok(), Some(None));
+        assert!(unreadable.get(HEADER).is_none());
+        let mut unreadable = headers(Some("5x"));
+        assert_eq!(policy.apply(GET_FEATURE, &mut unreadable).ok(), Some(Some(Duration::from_secs(5))));
+
+        let expired = policy.apply(GET_FEATURE, &mut headers(Some("0n"))).unwrap_err();
+        assert_eq!(expired.code(), tonic::Code::DeadlineExceeded);
+    }
+
+    #[test]
+    fn reads_the_deadline_from_metadata() {
+        let before = Instant::now();
+        let request = request((), Duration::from_secs(2));
+        let deadline = from_metadata(request.metadata()).unwrap();
+        assert!(deadline >= before + Duration::from_secs(2));
+        assert!(deadline <= Instant::now() + Duration::from_secs(2));
+
+        assert!(from_metadata(&MetadataMap::new()).is_none());
+        let mut request = Request::new(());
+        request.metadata_mut().insert(HEADER, MetadataValue::from_static("soon"));
+        assert!(from_metadata(request.metadata()).is_none());
+    }
+
+    #[tokio::test]
+    async fn runs_until_the_deadline() {
+        assert_eq!(run_until(None, async { 1 }).await.ok(), Some(1));
+        let later = Instant::now() + Duration::from_secs(60);
+        assert_eq!(run_until(Some(later), async { 2 }).await.ok(), Some(2));
+
+        let soon = Instant::now() + Duration::from_millis(20);
+        let status = run_until(Some(soon), futures::future::pending::<()>()).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::DeadlineExceeded);
+
+        // A passed deadline fails even a future that is ready.
+        let passed = Instant::now() - Duration::from_millis(1);
+        assert!(run_until(Some(passed), async { 3 }).await.is_err());
+    }
+}
@@ -0,0 +1,403 @@
+// Errors of the RouteGuide service, sent as `tonic::Status` with details from the `google.rpc`
+// error model, so that clients can act on them without parsing messages.
+//
+// The details travel as a serialized `google.rpc.Status` in the binary details of the status (the
+// `grpc-status-details-bin` trailer), which is where other gRPC implementations look for them.
+// `ErrorDetails::from_status` reads them back on the client.
+#![allow(dead_code)]
+
+use std::fmt;
+use std::time::Duration;
+
+use prost::Message;
+use tonic::{Code, Status};
+
+use crate::route_guide::aggregate_request::Grid;
+use crate::route_guide::geofence::Shape;
+use crate::route_guide::{
+    AggregateRequest, Geofence, NearestRequest, PlanRouteRequest, Point, RadiusRequest, Rectangle, SearchRequest,
+};
+
+
+// Generated from proto/google/rpc.
+pub mod google_rpc {tonic::include_proto!("google.rpc");}
+use google_rpc::bad_request::FieldViolation;
+use google_rpc::{BadRequest, ErrorInfo, ResourceInfo, RetryInfo};
+
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/google.rpc.";
+const DOMAIN: &str = "route-guide";
+
+const MAX_LATITUDE: i32 = 900_000_000;
+const MAX_LONGITUDE: i32 = 1_800_000_000;
+
+pub const MAX_NEAREST: i32 = 100;
+pub const MAX_RADIUS: i32 = 100_000; // meters
+pub const MAX_SEARCH_RESULTS: i32 = 1000;
+const MAX_QUERY_LENGTH: usize = 256;
+pub const MAX_GEOFENCE_RADIUS: i32 = 1_000_000; // meters
+pub const MAX_GEOHASH_PRECISION: i32 = 12;
+pub const MAX_ROUTE_POINTS: i32 = 10_000;
+
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// The request has fields that can't be used. Sent with `BadRequest`.
+    InvalidArgument(Vec<FieldViolation>),
+    /// Sent with `ResourceInfo`.
+    NotFound { resource_type: String, resource_name: String, description: String },
+    /// `reason` is a constant such as `MISSING_TOKEN`, sent with `ErrorInfo`.
+    Unauthenticated { reason: &'static str },
+    /// A limit of the server was reached. `reason` is a constant such as `ROUTE_TOO_LONG`, sent
+    /// with `ErrorInfo`.
+    ResourceExhausted { reason: &'static str, message: String },
+    /// Sent with `RetryInfo`.
+    Unavailable { message: String, retry_after: Duration },
+    Internal(String),
+}
+
+impl Error {
+    pub fn invalid_argument(violations: Vec<FieldViolation>) -> Error {
+        Error::InvalidArgument(violations)
+    }
+
+    pub fn not_found(resource_type: &str, resource_name: &str, description: &str) -> Error {
+        Error::NotFound {
+            resource_type: resource_type.to_string(),
+            resource_name: resource_name.to_string(),
+            description: description.to_string(),
+        }
+    }
+
+    pub fn unavailable(message: &str, retry_after: Duration) -> Error {
+        Error::Unavailable { message: message.to_string(), retry_after }
+    }
+
+    pub fn code(&self) -> Code {
+        match self {
+            Error::InvalidArgument(_)       => Code::InvalidArgument,
+            Error::NotFound { .. }          => Code::NotFound,
+            Error::Unauthenticated { .. }   => Code::Unauthenticated,
+            Error::ResourceExhausted { .. } => Code::ResourceExhausted,
+            Error::Unavailable { .. }       => Code::Unavailable,
+            Error::Internal(_)              => Code::Internal,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidArgument(violations) => {
+                write!(f, "invalid request")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    let separator = if i == 0 { ": " } else { "; " };
+                    write!(f, "{}{}: {}", separator, violation.field, violation.description)?;
+                }
+                Ok(())
+            },
+            Error::NotFound { resource_type, resource_name, description } => {
+                write!(f, "{} {} not found: {}", resource_type, resource_name, description)
+            },
+            Error::Unauthenticated { reason } => write!(f, "not authenticated ({})", reason),
+            Error::ResourceExhausted { message, .. } => write!(f, "{}", message),
+            Error::Unavailable { message, .. } => write!(f, "{}", message),
+            Error::Internal(message) => write!(f, "internal error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Status {
+        let details = match &error {
+            Error::InvalidArgument(violations) => {
+                vec![any("BadRequest", &BadRequest { field_violations: violations.clone() })]
+            },
+            Error::NotFound { resource_type, resource_name, description } => {
+                vec![any("ResourceInfo", &ResourceInfo {
+                    resource_type: resource_type.clone(),
+                    resource_name: resource_name.clone(),
+                    owner: String::new(),
+                    description: description.clone(),
+                })]
+            },
+            Error::Unauthenticated { reason } | Error::ResourceExhausted { reason, .. } => {
+                vec![any("ErrorInfo", &ErrorInfo {
+                    reason: reason.to_string(),
+                    domain: DOMAIN.to_string(),
+                    metadata: Default::default(),
+                })]
+            },
+            Error::Unavailable { retry_after, .. } => {
+                vec![any("RetryInfo", &RetryInfo { retry_delay: Some((*retry_after).into()) })]
+            },
+            Error::Internal(_) => Vec::new(),
+        };
+
+        let code = error.code();
+        let message = error.to_string();
+        let status = google_rpc::Status { code: code as i32, message: message.clone(), details };
+
+        let mut encoded = Vec::with_capacity(status.encoded_len());
+        status.encode(&mut encoded).expect("a Vec grows as needed");
+        Status::with_details(code, message, encoded.into())
+    }
+}
+
+fn any<M: Message>(name: &str, message: &M) -> prost_types::Any {
+    let mut value = Vec::with_capacity(message.encoded_len());
+    message.encode(&mut value).expect("a Vec grows as needed");
+    prost_types::Any { type_url: format!("{}{}", TYPE_URL_PREFIX, name), value }
+}
+
+
+/// The details the server attached to a failed call, for the client.
+#[derive(Debug, Default, Clone)]
+pub struct ErrorDetails {
+    pub bad_request: Option<BadRequest>,
+    pub resource_info: Option<ResourceInfo>,
+    pub error_info: Option<ErrorInfo>,
+    pub retry_info: Option<RetryInfo>,
+}
+
+impl ErrorDetails {
+    /// Reads the details from `status`. Details that can't be decoded, or that aren't of a type
+    /// we know, are left out.
+    pub fn from_status(status: &Status) -> ErrorDetails {
+        let mut details = ErrorDetails::default();
+
+        let status = match google_rpc::Status::decode(status.details()) {
+            Ok(status) => status,
+            Err(_) => return details,
+        };
+
+        for any in status.details {
+            let name = match any.type_url.strip_prefix(TYPE_URL_PREFIX) {
+                Some(name) => name,
+                None => continue,
+            };
+            let value = &any.value[..];
+            match name {
+                "BadRequest"   => details.bad_request = BadRequest::decode(value).ok(),
+                "ResourceInfo" => details.resource_info = ResourceInfo::decode(value).ok(),
+                "ErrorInfo"    => details.error_info = ErrorInfo::decode(value).ok(),
+                "RetryInfo"    => details.retry_info = RetryInfo::decode(value).ok(),
+                _ => {},
+            }
+        }
+
+        details
+    }
+
+    /// How long to wait before retrying, if the server said.
+    pub fn retry_after(&self) -> Option<Duration> {
+        let delay = self.retry_info.as_ref()?.retry_delay.as_ref()?;
+        Some(Duration::new(delay.seconds.max(0) as u64, delay.nanos.max(0) as u32))
+    }
+}
+
+
+pub fn violation(field: &str, description: &str) -> FieldViolation {
+    FieldViolation { field: field.to_string(), description: description.to_string() }
+}
+
+/// The problems with a point that is required, as violations of `field` and its members.
+pub fn point_violations(field: &str, point: Option<&Point>) -> Vec<FieldViolation> {
+    let point = match point {
+        Some(point) => point,
+        None => return vec![violation(field, "is required")],
+    };
+
+    let mut violations = Vec::new();
+    if !(-MAX_LATITUDE..=MAX_LATITUDE).contains(&point.latitude) {
+        violations.push(violation(&member(field, "latitude"), "must be within ±90 degrees (±900000000 in E7)"));
+    }
+    if !(-MAX_LONGITUDE..=MAX_LONGITUDE).contains(&point.longitude) {
+        violations.push(violation(&member(field, "longitude"), "must be within ±180 degrees (±1800000000 in E7)"));
+    }
+    violations
+}
+
+/// The path to a member of `field`, or to a field of the request itself if `field` is empty.
+fn member(field: &str, name: &str) -> String {
+    if field.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", field, name)
+    }
+}
+
+pub fn rectangle_violations(rectangle: &Rectangle) -> Vec<FieldViolation> {
+    let mut violations = point_violations("lo", rectangle.lo.as_ref());
+    violations.extend(point_violations("hi", rectangle.hi.as_ref()));
+    violations
+}
+
+pub fn nearest_violations(request: &NearestRequest) -> Vec<FieldViolation> {
+    let mut violations = point_violations("point", request.point.as_ref());
+    if !(1..=MAX_NEAREST).contains(&request.k) {
+        violations.push(violation("k", &format!("must be from 1 to {}", MAX_NEAREST)));
+    }
+    violations
+}
+
+pub fn radius_violations(request: &RadiusRequest) -> Vec<FieldViolation> {
+    let mut violations = point_violations("point", request.point.as_ref());
+    if !(1..=MAX_RADIUS).contains(&request.meters) {
+        violations.push(violation("meters", &format!("must be from 1 to {}", MAX_RADIUS)));
+    }
+    violations
+}
+
+pub fn search_violations(request: &SearchRequest) -> Vec<FieldViolation> {
+    let mut violations = Vec::new();
+    if !request.query.chars().any(char::is_alphanumeric) {
+        violations.push(violation("query", "must have at least one word"));
+    } else if request.query.len() > MAX_QUERY_LENGTH {
+        violations.push(violation("query", &format!("must be at most {} bytes", MAX_QUERY_LENGTH)));
+    }
+    if let Some(area) = &request.area {
+        violations.extend(point_violations("area.lo", area.lo.as_ref()));
+        violations.extend(point_violations("area.hi", area.hi.as_ref()));
+    }
+    if !(0..=MAX_SEARCH_RESULTS).contains(&request.limit) {
+        violations.push(violation("limit", &format!("must be from 1 to {}, or 0 for the default", MAX_SEARCH_RESULTS)));
+    }
+    violations
+}
+
+pub fn plan_route_violations(request: &PlanRouteRequest) -> Vec<FieldViolation> {
+    let mut violations = point_violations("from", request.from.as_ref());
+    violations.extend(point_violations("to", request.to.as_ref()));
+    if let Some(constraints) = &request.constraints {
+        if !(0..=MAX_RADIUS).contains(&constraints.max_hop) {
+            violations.push(violation("constraints.max_hop", &format!("must be from 1 to {}, or 0 for the default", MAX_RADIUS)));
+        }
+        if constraints.max_distance < 0 {
+            violations.push(violation("constraints.max_distance", "must not be negative"));
+        }
+    }
+    violations
+}
+
+/// The problems with a geofence to create (`with_id` false) or to update (`with_id` true).
+pub fn geofence_violations(fence: &Geofence, with_id: bool) -> Vec<FieldViolation> {
+    let mut violations = Vec::new();
+    if with_id && fence.id.is_empty() {
+        violations.push(violation("id", "is required"));
+    }
+    match &fence.shape {
+        Some(Shape::Rectangle(rectangle)) => {
+            violations.extend(point_violations("rectangle.lo", rectangle.lo.as_ref()));
+            violations.extend(point_violations("rectangle.hi", rectangle.hi.as_ref()));
+        },
+        Some(Shape::Circle(circle)) => {
+            violations.extend(point_violations("circle.center", circle.center.as_ref()));
+            if !(1..=MAX_GEOFENCE_RADIUS).contains(&circle.radius) {
+                violations.push(violation("circle.radius", &format!("must be from 1 to {}", MAX_GEOFENCE_RADIUS)));
+            }
+        },
+        None => violations.push(violation("shape", "a rectangle or a circle is required")),
+    }
+    violations
+}
+
+pub fn aggregate_violations(request: &AggregateRequest) -> Vec<FieldViolation> {
+    let mut violations = match &request.area {
+        Some(area) => {
+            let mut violations = point_violations("area.lo", area.lo.as_ref());
+            violations.extend(point_violations("area.hi", area.hi.as_ref()));
+            violations
+        },
+        None => vec![violation("area", "is required")],
+    };
+    match request.grid {
+        Some(Grid::GridSize(size)) if size < 1 => violations.push(violation("grid_size", "must be positive")),
+        Some(Grid::GeohashPrecision(precision)) if !(1..=MAX_GEOHASH_PRECISION).contains(&precision) => {
+            violations.push(violation("geohash_precision", &format!("must be from 1 to {}", MAX_GEOHASH_PRECISION)));
+        },
+        Some(_) => {},
+        None => violations.push(violation("grid", "grid_size or geohash_precision is required")),
+    }
+    violations
+}
+
+/// `Ok` if there are no violations, an `InvalidArgument` error with all of them otherwise.
+pub fn check(violations: Vec<FieldViolation>) -> Result<(), Error> {
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::invalid_argument(violations))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_request_round_trips() {
+        let violations = vec![violation("point.latitude", "must be within ±90 degrees"), violation("k", "must be from 1 to 100")];
+        let status = Status::from(Error::invalid_argument(violations.clone()));
+
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "invalid request: point.latitude: must be within ±90 degrees; k: must be from 1 to 100");
+        let details = ErrorDetails::from_status(&status);
+        assert_eq!(details.bad_request.unwrap().field_violations, violations);
+        assert!(details.resource_info.is_none() && details.error_info.is_none() && details.retry_info.is_none());
+    }
+
+    #[test]
+    fn resource_info_round_trips() {
+        let status = Status::from(Error::not_found("Geofence", "42", "no geofence has this id"));
+
+        assert_eq!(status.code(), Code::NotFound);
+        let info = ErrorDetails::from_status(&status).resource_info.unwrap();
+        assert_eq!((info.resource_type.as_str(), info.resource_name.as_str()), ("Geofence", "42"));
+        assert_eq!(info.description, "no geofence has this id");
+    }
+
+    #[test]
+    fn error_info_round_trips() {
+        let status = Status::from(Error::Unauthenticated { reason: "MISSING_TOKEN" });
+
+        assert_eq!(status.code(), Code::Unauthenticated);
+        let info = ErrorDetails::from_status(&status).error_info.unwrap();
+        assert_eq!((info.reason.as_str(), info.domain.as_str()), ("MISSING_TOKEN", DOMAIN));
+
+        let status = Status::from(Error::ResourceExhausted { reason: "ROUTE_TOO_LONG", message: "too many points".to_string() });
+        assert_eq!((status.code(), status.message()), (Code::ResourceExhausted, "too many points"));
+        assert_eq!(ErrorDetails::from_status(&status).error_info.unwrap().reason, "ROUTE_TOO_LONG");
+    }
+
+    #[test]
+    fn retry_info_round_trips() {
+        let status = Status::from(Error::unavailable("the server is busy", Duration::from_millis(1500)));
+
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "the server is busy");
+        assert_eq!(ErrorDetails::from_status(&status).retry_after(), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn ignores_missing_and_unknown_details() {
+        let details = ErrorDetails::from_status(&Status::from(Error::Internal("oops".to_string())));
+        assert!(details.bad_request.is_none() && details.retry_after().is_none());
+
+        assert!(ErrorDetails::from_status(&Status::not_found("plain")).resource_info.is_none());
+        assert!(ErrorDetails::from_status(&Status::with_details(Code::Internal, "junk", vec![0xff, 0xff].into())).error_info.is_none());
+
+        let unknown = google_rpc::Status {
+            code: Code::Internal as i32,
+            message: String::new(),
+            details: vec![prost_types::Any { type_url: "type.example.com/Other".to_string(), value: vec![1, 2, 3] }],
+        };
+        let mut encoded = Vec::new();
+        unknown.encode(&mut encoded).unwrap();
+        let details = ErrorDetails::from_status(&Status::with_details(Code::Internal, "", encoded.into()));
+        assert!(details.bad_request.is_none() && details.resource_info.is_none() && details.error_info.is_none());
+    }
+}
@@ -0,0 +1,314 @@
+// Error pages shared by the raw servers and the hyper services.
+//
+// Pages are rendered from `static/html/errors/<status>.html`, where `{{status}}`, `{{reason}}`,
+// `{{path}}`, `{{request_id}}`, `{{timestamp}}` and `{{message}}` are replaced by their values.
+// Clients that prefer `application/json` in their `Accept` header get a JSON body instead.
+// Templates are read once, when the first page is rendered, so editing one needs a restart.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use hyper::header;
+
+
+const TEMPLATE_DIRECTORY: &str = "static/html/errors";
+
+// Used when there is no template for a status, or it can't be read.
+const FALLBACK_TEMPLATE: &str = "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+    <meta charset=\"utf-8\">
+    <title>{{status}} {{reason}}</title>
+</head>
+<body>
+<h1>{{status}} {{reason}}</h1>
+<p>{{message}}</p>
+<p><small>Request {{request_id}} at {{timestamp}}</small></p>
+</body>
+</html>
+";
+
+
+pub struct ErrorPage {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ErrorContext<'a> {
+    pub status: u16,
+    pub reason: &'a str,
+    pub path: &'a str,
+    pub request_id: String,
+    pub timestamp: SystemTime,
+    pub message: Option<&'a str>,
+}
+
+impl<'a> ErrorContext<'a> {
+    /// `request_id` should be the client's `X-Request-Id`, if it sent one.
+    pub fn new(status: u16, reason: &'a str, path: &'a str, request_id: Option<&str>) -> ErrorContext<'a> {
+        ErrorContext {
+            status,
+            reason,
+            path,
+            request_id: request_id.map(String::from).unwrap_or_else(new_request_id),
+            timestamp: SystemTime::now(),
+            message: None,
+        }
+    }
+
+    pub fn message(mut self, message: &'a str) -> ErrorContext<'a> {
+        self.message = Some(message);
+        self
+    }
+
+    /// Renders the page as HTML or JSON, depending on the request's `Accept` header.
+    pub fn render(&self, accept: Option<&str>) -> ErrorPage {
+        if prefers_json(accept) {
+            self.render_json()
+        } else {
+            self.render_html()
+        }
+    }
+
+    pub fn render_html(&self) -> ErrorPage {
+        let template = template(self.status);
+
+        let status = self.status.to_string();
+        let timestamp = httpdate::fmt_http_date(self.timestamp);
+        let message = self.message.unwrap_or_else(|| default_message(self.status));
+
+        let body = render_template(template, &[
+            ("status", &status),
+            ("reason", self.reason),
+            ("path", self.path),
+            ("request_id", &self.request_id),
+            ("timestamp", &timestamp),
+            ("message", message),
+        ]);
+
+        ErrorPage { content_type: "text/html; charset=utf-8", body: body.into_bytes() }
+    }
+
+    pub fn render_json(&self) -> ErrorPage {
+        let body = serde_json::json!({
+            "error": {
+                "status": self.status,
+                "reason": self.reason,
+                "message": self.message.unwrap_or_else(|| default_message(self.status)),
+                "path": self.path,
+                "request_id": self.request_id,
+                "timestamp": httpdate::fmt_http_date(self.timestamp),
+            }
+        });
+
+        ErrorPage { content_type: "application/json", body: body.to_string().into_bytes() }
+    }
+}
+
+
+/// Renders `status` for a hyper request, for use in the hyper `service` functions.
+pub fn hyper_response<B>(status: hyper::StatusCode, request: &hyper::Request<B>) -> hyper::Response<hyper::Body> {
+    let header = |name: &str| request.headers().get(name).and_then(|value| value.to_str().ok());
+
+    let context = ErrorContext::new(
+        status.as_u16(),
+        status.canonical_reason().unwrap_or(""),
+        request.uri().path(),
+        header("x-request-id"),
+    );
+    let page = context.render(header("accept"));
+
+    hyper::Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, page.content_type)
+        .header("x-request-id", context.request_id.as_str())
+        .body(hyper::Body::from(page.body))
+        .unwrap()
+}
+
+
+/// The template for `status`, or the fallback if there is none.
+fn template(status: u16) -> &'static str {
+    static TEMPLATES: OnceLock<HashMap<u16, String>> = OnceLock::new();
+    TEMPLATES
+        .get_or_init(load_templates)
+        .get(&status)
+        .map_or(FALLBACK_TEMPLATE, String::as_str)
+}
+
+/// Every `<status>.html` in `TEMPLATE_DIRECTORY` that can be read.
+fn load_templates() -> HashMap<u16, String> {
+    let entries = match fs::read_dir(TEMPLATE_DIRECTORY) {
+        Ok(entries) => entries,
+        Err(_) => return HashMap::new(),
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != "html" {
+                return None;
+            }
+            let status = path.file_stem()?.to_str()?.parse().ok()?;
+            Some((status, fs::read_to_string(&path).ok()?))
+        })
+        .collect()
+}
+
+fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+fn default_message(status: u16) -> &'static str {
+    match status {
+        400 => "The request could not be understood.",
+        404 => "Sorry, I don't know what you're asking for.",
+        405 => "That method is not allowed here.",
+        408 => "The request took too long to arrive.",
+        413 => "The request body is too large.",
+        500 => "Something went wrong on our side.",
+        503 => "The server is too busy right now. Please try again shortly.",
+        _ => "",
+    }
+}
+
+/// Replaces every `{{name}}` in `template` with the HTML-escaped value of `name`. Unknown names
+/// are left as they are.
+fn render_template(template: &str, variables: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+
+        let end = match after.find("}}") {
+            Some(end) => end,
+            None => {
+                rest = &rest[start..];
+                break;
+            },
+        };
+
+        let name = after[..end].trim();
+        match variables.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => output.push_str(&escape_html(value)),
+            None => output.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+
+    output.push_str(rest);
+    output
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&'  => escaped.push_str("&amp;"),
+            '<'  => escaped.push_str("&lt;"),
+            '>'  => escaped.push_str("&gt;"),
+            '"'  => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _    => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Whether the `Accept` header ranks `application/json` above `text/html`.
+fn prefers_json(accept: Option<&str>) -> bool {
+    let accept = match accept {
+        Some(accept) => accept,
+        None => return false,
+    };
+
+    // The quality of each type when named explicitly, and through a wildcard. A named type takes
+    // precedence, so `application/json;q=0` refuses JSON whatever the wildcards say.
+    let (mut json, mut json_wildcard): (Option<f32>, f32) = (None, 0.0);
+    let (mut html, mut html_wildcard): (Option<f32>, f32) = (None, 0.0);
+
+    for media_range in accept.split(',') {
+        let mut parts = media_range.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or("").to_ascii_lowercase();
+        let quality = parts
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match media_type.as_str() {
+            "application/json" => json = Some(json.unwrap_or(0.0).max(quality)),
+            "text/html"        => html = Some(html.unwrap_or(0.0).max(quality)),
+            // Wildcards count for both, but less than naming a type explicitly.
+            "*/*" | "application/*" | "text/*" => {
+                let quality = quality * 0.9;
+                if media_type != "text/*" { json_wildcard = json_wildcard.max(quality); }
+                if media_type != "application/*" { html_wildcard = html_wildcard.max(quality); }
+            },
+            _ => {},
+        }
+    }
+
+    json.unwrap_or(json_wildcard) > html.unwrap_or(html_wildcard)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_substituted_values() {
+        let rendered = render_template("<p>{{message}}</p>", &[("message", "<script>alert(\"x & 'y'\")</script>")]);
+        assert_eq!(rendered, "<p>&lt;script&gt;alert(&quot;x &amp; &#39;y&#39;&quot;)&lt;/script&gt;</p>");
+    }
+
+    #[test]
+    fn leaves_unknown_and_unterminated_variables() {
+        let variables = [("status", "404"), ("path", "/a")];
+        assert_eq!(render_template("{{ status }} {{path}}{{status}}", &variables), "404 /a404");
+        assert_eq!(render_template("{{missing}} {{status}}", &variables), "{{missing}} 404");
+        assert_eq!(render_template("{{status}} {{status", &variables), "404 {{status");
+        assert_eq!(render_template("no variables", &variables), "no variables");
+    }
+
+    #[test]
+    fn negotiates_json_by_quality() {
+        assert!(!prefers_json(None));
+        assert!(!prefers_json(Some("")));
+        assert!(!prefers_json(Some("*/*")));
+        assert!(!prefers_json(Some("text/html,application/xhtml+xml,*/*;q=0.8")));
+        assert!(prefers_json(Some("application/json")));
+        assert!(prefers_json(Some("application/json, */*")));
+        assert!(prefers_json(Some("text/html;q=0.5, application/json;q=0.9")));
+        assert!(!prefers_json(Some("text/html;q=0.9, application/json;q=0.5")));
+        assert!(prefers_json(Some("application/*, text/html;q=0.5")));
+        // A named type wins over the wildcards that would match it.
+        assert!(!prefers_json(Some("application/json;q=0, */*")));
+        assert!(!prefers_json(Some("application/json;q=0, application/*")));
+        assert!(prefers_json(Some("text/html;q=0, */*")));
+    }
+
+    #[test]
+    fn renders_json_for_clients_that_prefer_it() {
+        let context = ErrorContext::new(404, "Not Found", "/<missing>", Some("abc")).message("Gone & lost");
+
+        let page = context.render(Some("application/json"));
+        assert_eq!(page.content_type, "application/json");
+        let body: serde_json::Value = serde_json::from_slice(&page.body).unwrap();
+        assert_eq!(body["error"]["status"], 404);
+        assert_eq!(body["error"]["path"], "/<missing>");
+        assert_eq!(body["error"]["request_id"], "abc");
+        assert_eq!(body["error"]["message"], "Gone & lost");
+
+        let page = context.render(None);
+        assert_eq!(page.content_type, "text/html; charset=utf-8");
+        assert!(String::from_utf8(page.body).unwrap().contains("Gone &amp; lost"));
+    }
+}
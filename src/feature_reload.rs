@@ -0,0 +1,94 @@
+// Reloads the feature database into a `FeatureStore` when its file changes, or when the process
+// gets SIGHUP, without restarting the server.
+//
+// The file is loaded leniently on a blocking thread and swapped in with `FeatureStore::replace`,
+// so calls that already hold a snapshot finish on the old features. A file that can't be loaded
+// leaves the current features in place. Either way the outcome is logged and reported as the
+// health of `HEALTH_SERVICE`, which is NOT_SERVING while the file on disk is broken; the
+// RouteGuide service itself stays SERVING on the last good set.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tonic_health::server::HealthReporter;
+use tonic_health::ServingStatus;
+
+use crate::data::{self, Mode};
+use crate::feature_store::FeatureStore;
+use crate::file_watch;
+
+
+pub const HEALTH_SERVICE: &str = "route_guide.FeatureDatabase";
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+
+/// Starts reloading `path` into `store`. Reloads run one at a time, in the background.
+pub async fn watch(path: PathBuf, store: Arc<FeatureStore>, mut health: HealthReporter) {
+    health.set_service_status(HEALTH_SERVICE, ServingStatus::Serving).await;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let on_change = tx.clone();
+    file_watch::watch(vec![path.clone()], WATCH_INTERVAL, move || { let _ = on_change.send(()); });
+    reload_on_hangup(tx);
+
+    tokio::spawn(async move {
+        while rx.recv().await.is_some() {
+            let status = reload(&path, &store).await;
+            health.set_service_status(HEALTH_SERVICE, status).await;
+        }
+    });
+}
+
+async fn reload(path: &Path, store: &FeatureStore) -> ServingStatus {
+    let load_path = path.to_path_buf();
+    let loaded = match tokio::task::spawn_blocking(move || data::load_path(load_path, Mode::Lenient)).await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::error!(error = %e, "feature database reload panicked");
+            return ServingStatus::NotServing;
+        },
+    };
+
+    match loaded {
+        Ok(loaded) => {
+            for problem in &loaded.problems {
+                tracing::warn!(path = %path.display(), "{}", problem);
+            }
+            let count = loaded.features.len();
+            let changes = store.replace(loaded.features);
+            tracing::info!(path = %path.display(), features = count, changes, "reloaded feature database");
+            ServingStatus::Serving
+        },
+        Err(e) => {
+            tracing::error!(path = %path.display(), error = %e, "rejected feature database; keeping the current features");
+            ServingStatus::NotServing
+        },
+    }
+}
+
+#[cfg(unix)]
+fn reload_on_hangup(tx: mpsc::UnboundedSender<()>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!(error = %e, "can't reload the feature database on SIGHUP");
+            return;
+        },
+    };
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            if tx.send(()).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn reload_on_hangup(_tx: mpsc::UnboundedSender<()>) {}
@@ -0,0 +1,164 @@
+// The feature database as the server sees it: an immutable snapshot behind an `Arc`, which
+// readers clone and keep for as long as they need it, plus a feed of changes for those that want
+// to follow along. Each snapshot comes with a spatial index and a text index of the same features,
+// built before the snapshot is swapped in.
+//
+// Every added or removed feature bumps the version by one. The last changes are kept in a log, so
+// that a client which reconnects can catch up from the version it has seen.
+#![allow(dead_code)]
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use tokio::sync::broadcast;
+
+use crate::route_guide::Feature;
+use crate::spatial::SpatialIndex;
+use crate::text_index::TextIndex;
+
+
+const LOG_CAPACITY: usize = 1024;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub version: u64,
+    pub kind: ChangeKind,
+    pub feature: Feature,
+}
+
+struct Current {
+    version: u64,
+    features: Arc<Vec<Feature>>,
+    index: Arc<SpatialIndex>,
+    text_index: Arc<TextIndex>,
+}
+
+pub struct FeatureStore {
+    current: RwLock<Current>,
+    log: Mutex<VecDeque<Change>>,
+    sender: broadcast::Sender<Change>,
+}
+
+impl FeatureStore {
+    pub fn new(features: Vec<Feature>) -> Arc<FeatureStore> {
+        let (sender, _) = broadcast::channel(LOG_CAPACITY);
+        let features = Arc::new(features);
+        let index = Arc::new(SpatialIndex::new(features.clone()));
+        let text_index = Arc::new(TextIndex::new(features.clone()));
+        Arc::new(FeatureStore {
+            current: RwLock::new(Current { version: 0, features, index, text_index }),
+            log: Mutex::new(VecDeque::with_capacity(LOG_CAPACITY)),
+            sender,
+        })
+    }
+
+    pub fn snapshot(&self) -> Arc<Vec<Feature>> {
+        self.versioned_snapshot().1
+    }
+
+    pub fn versioned_snapshot(&self) -> (u64, Arc<Vec<Feature>>) {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        (current.version, current.features.clone())
+    }
+
+    /// The spatial index of the current snapshot, which `SpatialIndex::features` returns.
+    pub fn index(&self) -> Arc<SpatialIndex> {
+        self.current.read().unwrap_or_else(PoisonError::into_inner).index.clone()
+    }
+
+    /// The text index of the current snapshot, which `TextIndex::features` returns.
+    pub fn text_index(&self) -> Arc<TextIndex> {
+        self.current.read().unwrap_or_else(PoisonError::into_inner).text_index.clone()
+    }
+
+    /// Changes made after subscribing. Subscribe before taking the snapshot to not miss any, and
+    /// skip those whose version the snapshot already includes.
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.sender.subscribe()
+    }
+
+    /// The changes after `version`, or `None` if the log doesn't reach back that far.
+    pub fn changes_since(&self, version: u64) -> Option<Vec<Change>> {
+        let current = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        if version > current.version {
+            return None;
+        }
+
+        let log = self.log.lock().unwrap_or_else(PoisonError::into_inner);
+        let oldest = log.front().map_or(current.version + 1, |change| change.version);
+        if version + 1 < oldest {
+            return None;
+        }
+
+        Some(log.iter().filter(|change| change.version > version).cloned().collect())
+    }
+
+    /// Swaps in a new set of features and publishes the difference to the old one. Readers
+    /// holding the old snapshot keep it until they are done. Returns the number of changes.
+    pub fn replace(&self, features: Vec<Feature>) -> usize {
+        // Indexing takes a while; readers carry on with the old snapshot meanwhile.
+        let features = Arc::new(features);
+        let index = Arc::new(SpatialIndex::new(features.clone()));
+        let text_index = Arc::new(TextIndex::new(features.clone()));
+
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+
+        let mut changes = Vec::new();
+        let mut old = count(&current.features);
+        for feature in features.iter() {
+            match old.get_mut(&key(feature)) {
+                Some(n) if *n > 0 => *n -= 1,
+                _ => changes.push((ChangeKind::Added, feature.clone())),
+            }
+        }
+        // Whatever is left over in `old` isn't in the new set.
+        let mut new = count(&features);
+        for feature in current.features.iter() {
+            match new.get_mut(&key(feature)) {
+                Some(n) if *n > 0 => *n -= 1,
+                _ => changes.push((ChangeKind::Removed, feature.clone())),
+            }
+        }
+
+        let mut log = self.log.lock().unwrap_or_else(PoisonError::into_inner);
+        for (kind, feature) in changes.iter().cloned() {
+            current.version += 1;
+            let change = Change { version: current.version, kind, feature };
+
+            if log.len() == LOG_CAPACITY {
+                log.pop_front();
+            }
+            log.push_back(change.clone());
+            // Nobody listening is fine.
+            let _ = self.sender.send(change);
+        }
+
+        current.features = features;
+        current.index = index;
+        current.text_index = text_index;
+        changes.len()
+    }
+}
+
+
+type Key = (i32, i32, String);
+
+fn key(feature: &Feature) -> Key {
+    let location = feature.location.clone().unwrap_or_default();
+    (location.latitude, location.longitude, feature.name.clone())
+}
+
+fn count(features: &[Feature]) -> HashMap<Key, usize> {
+    let mut counts = HashMap::new();
+    for feature in features {
+        *counts.entry(key(feature)).or_insert(0) += 1;
+    }
+    counts
+}
@@ -0,0 +1,40 @@
+// Polls files for changes to their modification time.
+//
+// Polling every few seconds is plenty for certificates and the feature database, and it works the
+// same on every platform and with editors that replace files instead of writing to them.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+
+/// Calls `on_change` from a background task whenever any of `paths` gets a new modification time
+/// (or appears or disappears). It is not called for the initial state.
+pub fn watch<F>(paths: Vec<PathBuf>, interval: Duration, mut on_change: F)
+where
+    F: FnMut() + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut last = modification_times(&paths).await;
+        let mut ticks = tokio::time::interval(interval);
+
+        loop {
+            ticks.tick().await;
+
+            let current = modification_times(&paths).await;
+            if current != last {
+                last = current;
+                on_change();
+            }
+        }
+    });
+}
+
+async fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    let mut times = Vec::with_capacity(paths.len());
+    for path in paths {
+        let modified = tokio::fs::metadata(path).await.and_then(|metadata| metadata.modified());
+        times.push(modified.ok());
+    }
+    times
+}
@@ -0,0 +1,724 @@
+// Features and routes in the file formats other tools use, both ways:
+//
+//     .gpx      GPX 1.1. Features are waypoints with a name, routes are a track (or a `<rte>`).
+//     .geojson  A FeatureCollection. Features are Point features with a `name` property, a route
+//               is a LineString feature. Coordinates are `[longitude, latitude]`.
+//     .csv      `latitude,longitude[,name]` in decimal degrees, with a header line.
+//     .json     The shape of `data/route_guide_db.json`, in E7.
+//
+// Coordinates are converted between decimal degrees and E7 as text, digit by digit, so converting
+// an E7 value to degrees and back always gives the same value. Degrees with more than 7 decimals
+// are rounded, half away from zero. JSON numbers go through an f64, whose shortest representation
+// is the text we wrote, so GeoJSON is lossless as well.
+#![allow(dead_code)]
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use serde_json::{json, Value};
+
+use crate::route_guide::{Feature, Point};
+
+
+const E7_DECIMALS: usize = 7;
+
+
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),
+    /// The extension isn't one of the formats we know.
+    UnknownFormat(String),
+    Csv { line: usize, message: String },
+    /// `position` is the byte offset in the document.
+    Gpx { position: usize, message: String },
+    /// Invalid JSON, or JSON that isn't the shape we expect. Lines and columns start at 1; they
+    /// are 0 when the problem isn't at one place in the text.
+    Json { line: usize, column: usize, message: String },
+}
+
+impl FormatError {
+    fn json(message: String) -> FormatError {
+        FormatError::Json { line: 0, column: 0, message }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Io(e)                     => write!(f, "{}", e),
+            FormatError::UnknownFormat(extension)  => write!(f, "unknown file format {:?}", extension),
+            FormatError::Csv { line, message }     => write!(f, "line {}: {}", line, message),
+            FormatError::Gpx { position, message } => write!(f, "at byte {}: {}", position, message),
+            FormatError::Json { line: 0, message, .. } => write!(f, "{}", message),
+            FormatError::Json { line, column, message } => write!(f, "line {}, column {}: {}", line, column, message),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<io::Error> for FormatError {
+    fn from(e: io::Error) -> FormatError {
+        FormatError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for FormatError {
+    fn from(e: serde_json::Error) -> FormatError {
+        FormatError::Json { line: e.line(), column: e.column(), message: e.to_string() }
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Gpx,
+    GeoJson,
+    Csv,
+    Database,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Result<Format, FormatError> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        match extension.as_str() {
+            "gpx"     => Ok(Format::Gpx),
+            "geojson" => Ok(Format::GeoJson),
+            "csv"     => Ok(Format::Csv),
+            "json"    => Ok(Format::Database),
+            _ => Err(FormatError::UnknownFormat(extension)),
+        }
+    }
+}
+
+
+/// Parses decimal degrees such as `-74.0059731` into E7.
+pub fn degrees_to_e7(text: &str) -> Result<i32, String> {
+    let invalid = || format!("{:?} is not a number of degrees", text);
+
+    let text = text.trim();
+    let (negative, unsigned) = match text.as_bytes().first() {
+        Some(b'-') => (true, &text[1..]),
+        Some(b'+') => (false, &text[1..]),
+        _ => (false, text),
+    };
+
+    let mut parts = unsigned.splitn(2, '.');
+    let whole = parts.next().unwrap_or("");
+    let fraction = parts.next().unwrap_or("");
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !is_digits(whole) || !is_digits(fraction) {
+        return Err(invalid());
+    }
+
+    // The first 7 decimals are part of the E7 value, the 8th decides the rounding.
+    let mut value: i64 = 0;
+    for digit in whole.bytes() {
+        value = value.checked_mul(10).and_then(|v| v.checked_add(i64::from(digit - b'0'))).ok_or_else(invalid)?;
+        if value > i64::from(i32::MAX) {
+            return Err(invalid());
+        }
+    }
+    let mut decimals = fraction.bytes();
+    for _ in 0..E7_DECIMALS {
+        let digit = decimals.next().map_or(0, |digit| i64::from(digit - b'0'));
+        value = value * 10 + digit;
+    }
+    if decimals.next().is_some_and(|digit| digit >= b'5') {
+        value += 1;
+    }
+
+    let value = if negative { -value } else { value };
+    if value < i64::from(i32::MIN) || value > i64::from(i32::MAX) {
+        return Err(invalid());
+    }
+    Ok(value as i32)
+}
+
+/// Formats an E7 value as decimal degrees, with no more decimals than needed.
+pub fn e7_to_degrees(value: i32) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let magnitude = i64::from(value).abs();
+    let whole = magnitude / 10_000_000;
+    let fraction = magnitude % 10_000_000;
+
+    if fraction == 0 {
+        return format!("{}{}", sign, whole);
+    }
+    let decimals = format!("{:07}", fraction);
+    format!("{}{}.{}", sign, whole, decimals.trim_end_matches('0'))
+}
+
+fn json_to_e7(value: &Value) -> Result<i32, String> {
+    match value.as_f64() {
+        // `Display` for f64 never uses an exponent.
+        Some(degrees) => degrees_to_e7(&degrees.to_string()),
+        None => Err(format!("{} is not a number of degrees", value)),
+    }
+}
+
+fn e7_to_json(value: i32) -> Value {
+    e7_to_degrees(value).parse::<f64>().map(Value::from).unwrap_or(Value::Null)
+}
+
+
+// Files.
+
+/// Reads a route, in the format its extension names.
+pub fn read_route(path: &Path) -> Result<Vec<Point>, FormatError> {
+    let format = Format::from_path(path)?;
+    parse_route(format, &fs::read_to_string(path)?)
+}
+
+pub fn write_route(path: &Path, points: &[Point]) -> Result<(), FormatError> {
+    let format = Format::from_path(path)?;
+    Ok(fs::write(path, format_route(format, points))?)
+}
+
+/// Reads features, in the format its extension names.
+pub fn read_features(path: &Path) -> Result<Vec<Feature>, FormatError> {
+    let format = Format::from_path(path)?;
+    parse_features(format, &fs::read_to_string(path)?)
+}
+
+pub fn write_features(path: &Path, features: &[Feature]) -> Result<(), FormatError> {
+    let format = Format::from_path(path)?;
+    Ok(fs::write(path, format_features(format, features))?)
+}
+
+pub fn parse_route(format: Format, text: &str) -> Result<Vec<Point>, FormatError> {
+    match format {
+        Format::Gpx => parse_gpx(text).map(|gpx| gpx.route),
+        Format::GeoJson => parse_geojson_route(text),
+        Format::Csv => Ok(parse_csv(text)?.into_iter().map(|feature| feature.location.unwrap_or_default()).collect()),
+        Format::Database => Ok(parse_database(text)?.into_iter().map(|feature| feature.location.unwrap_or_default()).collect()),
+    }
+}
+
+pub fn format_route(format: Format, points: &[Point]) -> String {
+    match format {
+        Format::Gpx => gpx_route(points),
+        Format::GeoJson => geojson_route(points),
+        Format::Csv => csv_points(points),
+        Format::Database => {
+            let features: Vec<Feature> = points
+                .iter()
+                .map(|point| Feature { name: String::new(), location: Some(point.clone()) })
+                .collect();
+            database(&features)
+        },
+    }
+}
+
+pub fn parse_features(format: Format, text: &str) -> Result<Vec<Feature>, FormatError> {
+    match format {
+        Format::Gpx => parse_gpx(text).map(|gpx| gpx.waypoints),
+        Format::GeoJson => parse_geojson_features(text),
+        Format::Csv => parse_csv(text),
+        Format::Database => parse_database(text),
+    }
+}
+
+pub fn format_features(format: Format, features: &[Feature]) -> String {
+    match format {
+        Format::Gpx => gpx_features(features),
+        Format::GeoJson => geojson_features(features),
+        Format::Csv => csv_features(features),
+        Format::Database => database(features),
+    }
+}
+
+
+// GPX.
+
+struct Gpx {
+    waypoints: Vec<Feature>,
+    /// The points of every track and route, in order.
+    route: Vec<Point>,
+}
+
+fn parse_gpx(text: &str) -> Result<Gpx, FormatError> {
+    let mut reader = Reader::from_str(text);
+    let mut buffer = Vec::new();
+    let mut gpx = Gpx { waypoints: Vec::new(), route: Vec::new() };
+
+    // The waypoint being read, and whether we're inside its `<name>`.
+    let mut waypoint: Option<Feature> = None;
+    let mut in_name = false;
+
+    loop {
+        let position = reader.buffer_position();
+        let error = |message: String| FormatError::Gpx { position, message };
+
+        match reader.read_event(&mut buffer) {
+            Ok(Event::Start(ref element)) => match element.local_name() {
+                b"wpt" => waypoint = Some(gpx_waypoint(&reader, element).map_err(error)?),
+                b"name" => in_name = waypoint.is_some(),
+                b"trkpt" | b"rtept" => gpx.route.push(gpx_point(&reader, element).map_err(error)?),
+                _ => {},
+            },
+            Ok(Event::Empty(ref element)) => match element.local_name() {
+                b"wpt" => gpx.waypoints.push(gpx_waypoint(&reader, element).map_err(error)?),
+                b"trkpt" | b"rtept" => gpx.route.push(gpx_point(&reader, element).map_err(error)?),
+                _ => {},
+            },
+            Ok(Event::Text(ref text)) | Ok(Event::CData(ref text)) if in_name => {
+                let text = text.unescape_and_decode(&reader).map_err(|e| error(e.to_string()))?;
+                if let Some(waypoint) = waypoint.as_mut() {
+                    waypoint.name.push_str(text.trim());
+                }
+            },
+            Ok(Event::End(ref element)) => match element.local_name() {
+                b"wpt" => gpx.waypoints.extend(waypoint.take()),
+                b"name" => in_name = false,
+                _ => {},
+            },
+            Ok(Event::Eof) => break,
+            Ok(_) => {},
+            Err(e) => return Err(error(e.to_string())),
+        }
+        buffer.clear();
+    }
+
+    Ok(gpx)
+}
+
+fn gpx_waypoint(reader: &Reader<&[u8]>, element: &BytesStart) -> Result<Feature, String> {
+    Ok(Feature { name: String::new(), location: Some(gpx_point(reader, element)?) })
+}
+
+fn gpx_point(reader: &Reader<&[u8]>, element: &BytesStart) -> Result<Point, String> {
+    let mut latitude = None;
+    let mut longitude = None;
+
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let value = attribute.unescape_and_decode_value(reader).map_err(|e| e.to_string())?;
+        match attribute.key {
+            b"lat" => latitude = Some(degrees_to_e7(&value)?),
+            b"lon" => longitude = Some(degrees_to_e7(&value)?),
+            _ => {},
+        }
+    }
+
+    match (latitude, longitude) {
+        (Some(latitude), Some(longitude)) => Ok(Point { latitude, longitude }),
+        _ => Err(String::from("a point needs both lat and lon")),
+    }
+}
+
+fn gpx_document(body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <gpx version=\"1.1\" creator=\"rust-server\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n\
+         {}</gpx>\n",
+        body,
+    )
+}
+
+fn gpx_route(points: &[Point]) -> String {
+    let mut body = String::from("  <trk>\n    <trkseg>\n");
+    for point in points {
+        body.push_str(&format!(
+            "      <trkpt lat=\"{}\" lon=\"{}\"/>\n",
+            e7_to_degrees(point.latitude), e7_to_degrees(point.longitude),
+        ));
+    }
+    body.push_str("    </trkseg>\n  </trk>\n");
+    gpx_document(&body)
+}
+
+fn gpx_features(features: &[Feature]) -> String {
+    let mut body = String::new();
+    for feature in features {
+        let location = feature.location.clone().unwrap_or_default();
+        body.push_str(&format!(
+            "  <wpt lat=\"{}\" lon=\"{}\">\n    <name>{}</name>\n  </wpt>\n",
+            e7_to_degrees(location.latitude), e7_to_degrees(location.longitude), escape_xml(&feature.name),
+        ));
+    }
+    gpx_document(&body)
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&'  => escaped.push_str("&amp;"),
+            '<'  => escaped.push_str("&lt;"),
+            '>'  => escaped.push_str("&gt;"),
+            '"'  => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+// GeoJSON.
+
+fn geojson_position(value: &Value) -> Result<Point, String> {
+    match value.as_array().map(Vec::as_slice) {
+        // Longitude first; an altitude may follow.
+        Some([longitude, latitude, ..]) => Ok(Point { latitude: json_to_e7(latitude)?, longitude: json_to_e7(longitude)? }),
+        _ => Err(format!("{} is not a position", value)),
+    }
+}
+
+fn geojson_features_of(value: &Value) -> Result<Vec<&Value>, FormatError> {
+    match value["type"].as_str() {
+        Some("FeatureCollection") => value["features"]
+            .as_array()
+            .map(|features| features.iter().collect())
+            .ok_or_else(|| FormatError::json(String::from("a FeatureCollection needs features"))),
+        Some("Feature") => Ok(vec![value]),
+        _ => Err(FormatError::json(String::from("expected a FeatureCollection or a Feature"))),
+    }
+}
+
+fn parse_geojson_route(text: &str) -> Result<Vec<Point>, FormatError> {
+    let document: Value = serde_json::from_str(text)?;
+    let mut points = Vec::new();
+
+    // Lines are taken whole; Point features are taken as the points of a route, in order.
+    for feature in geojson_features_of(&document)? {
+        let geometry = &feature["geometry"];
+        let positions: Vec<&Value> = match geometry["type"].as_str() {
+            Some("LineString") => geometry["coordinates"].as_array().map(|c| c.iter().collect()).unwrap_or_default(),
+            Some("MultiLineString") => geometry["coordinates"]
+                .as_array()
+                .map(|lines| lines.iter().filter_map(Value::as_array).flatten().collect())
+                .unwrap_or_default(),
+            Some("Point") => vec![&geometry["coordinates"]],
+            _ => continue,
+        };
+        for position in positions {
+            points.push(geojson_position(position).map_err(FormatError::json)?);
+        }
+    }
+
+    Ok(points)
+}
+
+fn parse_geojson_features(text: &str) -> Result<Vec<Feature>, FormatError> {
+    let document: Value = serde_json::from_str(text)?;
+    let mut features = Vec::new();
+
+    for (index, feature) in geojson_features_of(&document)?.into_iter().enumerate() {
+        let geometry = &feature["geometry"];
+        if geometry["type"].as_str() != Some("Point") {
+            continue;
+        }
+        let location = geojson_position(&geometry["coordinates"])
+            .map_err(|e| FormatError::json(format!("feature {}: {}", index, e)))?;
+        let name = feature["properties"]["name"].as_str().unwrap_or("").to_string();
+        features.push(Feature { name, location: Some(location) });
+    }
+
+    Ok(features)
+}
+
+fn geojson_point(point: &Point) -> Value {
+    json!([e7_to_json(point.longitude), e7_to_json(point.latitude)])
+}
+
+fn geojson_route(points: &[Point]) -> String {
+    let collection = json!({
+        "type": "FeatureCollection",
+        "features": [{
+            "type": "Feature",
+            "geometry": {
+                "type": "LineString",
+                "coordinates": points.iter().map(geojson_point).collect::<Vec<_>>(),
+            },
+            "properties": {},
+        }],
+    });
+    serde_json::to_string_pretty(&collection).unwrap()
+}
+
+fn geojson_features(features: &[Feature]) -> String {
+    let features: Vec<Value> = features
+        .iter()
+        .map(|feature| json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "Point",
+                "coordinates": geojson_point(&feature.location.clone().unwrap_or_default()),
+            },
+            "properties": { "name": feature.name },
+        }))
+        .collect();
+    serde_json::to_string_pretty(&json!({ "type": "FeatureCollection", "features": features })).unwrap()
+}
+
+
+// CSV.
+
+/// Parses `latitude,longitude[,name]` records. A first record that isn't numbers is taken as a
+/// header, and then the columns named `lat`/`latitude`, `lon`/`lng`/`longitude` and `name` are
+/// used. Fields may be quoted, with `""` for a quote inside, and quoted fields may span lines.
+fn parse_csv(text: &str) -> Result<Vec<Feature>, FormatError> {
+    let mut features = Vec::new();
+    let mut columns = (0, 1, Some(2));
+
+    for (index, (line, fields)) in csv_records(text)?.into_iter().enumerate() {
+        let error = |message: String| FormatError::Csv { line, message };
+
+        if index == 0 && degrees_to_e7(&fields[0]).is_err() {
+            columns = csv_columns(&fields).ok_or_else(|| error(String::from("no latitude and longitude columns")))?;
+            continue;
+        }
+
+        let field = |column: usize| fields.get(column).ok_or_else(|| error(format!("missing column {}", column + 1)));
+        let latitude = degrees_to_e7(field(columns.0)?).map_err(error)?;
+        let longitude = degrees_to_e7(field(columns.1)?).map_err(error)?;
+        let name = columns.2.and_then(|column| fields.get(column)).cloned().unwrap_or_default();
+        features.push(Feature { name, location: Some(Point { latitude, longitude }) });
+    }
+
+    Ok(features)
+}
+
+fn csv_columns(header: &[String]) -> Option<(usize, usize, Option<usize>)> {
+    let find = |names: &[&str]| header.iter().position(|name| names.contains(&name.trim().to_ascii_lowercase().as_str()));
+    Some((find(&["lat", "latitude"])?, find(&["lon", "lng", "longitude"])?, find(&["name"])))
+}
+
+/// Splits CSV into records, each with the line it starts on. Blank lines are left out, and a
+/// record always has at least one field.
+fn csv_records(text: &str) -> Result<Vec<(usize, Vec<String>)>, FormatError> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut record_line = 1;
+
+    let mut end_record = |fields: &mut Vec<String>, field: &mut String, record_line: usize| {
+        fields.push(std::mem::take(field).trim().to_string());
+        let record = std::mem::take(fields);
+        if record.len() > 1 || !record[0].is_empty() {
+            records.push((record_line, record));
+        }
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            },
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_string()),
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {},
+            '\n' if !quoted => {
+                end_record(&mut fields, &mut field, record_line);
+                line += 1;
+                record_line = line;
+            },
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            },
+        }
+    }
+    if quoted {
+        return Err(FormatError::Csv { line: record_line, message: String::from("unterminated quoted field") });
+    }
+    end_record(&mut fields, &mut field, record_line);
+
+    Ok(records)
+}
+
+fn quote_csv(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn csv_points(points: &[Point]) -> String {
+    let mut csv = String::from("latitude,longitude\n");
+    for point in points {
+        csv.push_str(&format!("{},{}\n", e7_to_degrees(point.latitude), e7_to_degrees(point.longitude)));
+    }
+    csv
+}
+
+fn csv_features(features: &[Feature]) -> String {
+    let mut csv = String::from("latitude,longitude,name\n");
+    for feature in features {
+        let location = feature.location.clone().unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            e7_to_degrees(location.latitude), e7_to_degrees(location.longitude), quote_csv(&feature.name),
+        ));
+    }
+    csv
+}
+
+
+// The database shape.
+
+/// The features of a feature database, without checking them: see `data` for that. Features
+/// without a name get an empty one.
+pub fn parse_database(text: &str) -> Result<Vec<Feature>, FormatError> {
+    let document: Value = serde_json::from_str(text)?;
+    let entries = document.as_array().ok_or_else(|| FormatError::json(String::from("expected an array of features")))?;
+
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            let e7 = |name: &str| {
+                entry["location"][name]
+                    .as_i64()
+                    .and_then(|value| if value as i32 as i64 == value { Some(value as i32) } else { None })
+                    .ok_or_else(|| FormatError::json(format!("feature {}: location.{} must be an E7 integer", index, name)))
+            };
+            Ok(Feature {
+                name: entry["name"].as_str().unwrap_or("").to_string(),
+                location: Some(Point { latitude: e7("latitude")?, longitude: e7("longitude")? }),
+            })
+        })
+        .collect()
+}
+
+fn database(features: &[Feature]) -> String {
+    let entries: Vec<Value> = features
+        .iter()
+        .map(|feature| {
+            let location = feature.location.clone().unwrap_or_default();
+            json!({
+                "location": { "latitude": location.latitude, "longitude": location.longitude },
+                "name": feature.name,
+            })
+        })
+        .collect();
+    serde_json::to_string_pretty(&entries).unwrap()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feature(name: &str, latitude: i32, longitude: i32) -> Feature {
+        Feature { name: name.to_string(), location: Some(Point { latitude, longitude }) }
+    }
+
+    fn features() -> Vec<Feature> {
+        vec![
+            feature("Patriots Path, Mendham, NJ 07945, USA", 407_838_351, -746_143_763),
+            feature("Quotes \"and\" <tags> & more", -1, 1_800_000_000),
+            feature("Two\nlines", -900_000_000, -1_800_000_000),
+            feature("", 0, 5),
+        ]
+    }
+
+    #[test]
+    fn converts_degrees_and_e7_losslessly() {
+        for &value in &[0, 1, -1, 407_838_351, -746_143_763, 10_000_000, i32::MAX, i32::MIN, -1_800_000_000] {
+            assert_eq!(degrees_to_e7(&e7_to_degrees(value)), Ok(value), "{}", value);
+            assert_eq!(json_to_e7(&e7_to_json(value)), Ok(value), "{}", value);
+        }
+        assert_eq!(e7_to_degrees(-746_143_763), "-74.6143763");
+        assert_eq!(e7_to_degrees(10_000_000), "1");
+        assert_eq!(degrees_to_e7("40.78383505"), Ok(407_838_351));
+        assert_eq!(degrees_to_e7("-40.78383505"), Ok(-407_838_351));
+        assert_eq!(degrees_to_e7(" +1.5 "), Ok(15_000_000));
+        assert_eq!(degrees_to_e7(".5"), Ok(5_000_000));
+        for invalid in &["", "-", ".", "1e5", "1.2.3", "abc", "300", "99999999999999999999"] {
+            assert!(degrees_to_e7(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn round_trips_features_in_every_format() {
+        for &format in &[Format::Gpx, Format::GeoJson, Format::Csv, Format::Database] {
+            let text = format_features(format, &features());
+            assert_eq!(parse_features(format, &text).unwrap(), features(), "{:?}:\n{}", format, text);
+        }
+    }
+
+    #[test]
+    fn round_trips_routes_in_every_format() {
+        let route: Vec<Point> = features().into_iter().filter_map(|feature| feature.location).collect();
+        for &format in &[Format::Gpx, Format::GeoJson, Format::Csv, Format::Database] {
+            let text = format_route(format, &route);
+            assert_eq!(parse_route(format, &text).unwrap(), route, "{:?}:\n{}", format, text);
+        }
+    }
+
+    #[test]
+    fn reads_csv_with_headers_quotes_and_blank_lines() {
+        let text = "\n\r\nName,Lng,Lat\r\n\"Multi\nline, \"\"quoted\"\"\",-74.5,40.25\r\n\nplain,1,2\n";
+        let parsed = parse_csv(text).unwrap();
+        assert_eq!(parsed, [
+            feature("Multi\nline, \"quoted\"", 402_500_000, -745_000_000),
+            feature("plain", 20_000_000, 10_000_000),
+        ]);
+
+        assert_eq!(parse_csv("1,2\n3,4").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn reports_csv_errors_by_line() {
+        let error = |text: &str| match parse_csv(text) {
+            Err(FormatError::Csv { line, .. }) => line,
+            other => panic!("expected a CSV error for {:?}, got {:?}", text, other),
+        };
+        assert_eq!(error("latitude,longitude\n1,2\n\"open,3\n4,5"), 3);
+        assert_eq!(error("latitude,longitude\n1,2\n\"two\nlines\",x\n"), 3);
+        assert_eq!(error("latitude,longitude\n1\n"), 2);
+        assert_eq!(error("name,height\n1,2\n"), 1);
+    }
+
+    #[test]
+    fn reads_gpx_tracks_and_routes() {
+        let text = r#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <wpt lat="40.7838351" lon="-74.6143763"><name>Patriots &amp; Path</name></wpt>
+  <trk><trkseg>
+    <trkpt lat="1" lon="2"/>
+    <trkpt lat="-1.5" lon="2.25"></trkpt>
+  </trkseg></trk>
+</gpx>"#;
+        assert_eq!(parse_features(Format::Gpx, text).unwrap(), [feature("Patriots & Path", 407_838_351, -746_143_763)]);
+        assert_eq!(parse_route(Format::Gpx, text).unwrap(), [
+            Point { latitude: 10_000_000, longitude: 20_000_000 },
+            Point { latitude: -15_000_000, longitude: 22_500_000 },
+        ]);
+        assert!(parse_route(Format::Gpx, "<gpx><trkpt lat=\"x\" lon=\"1\"/></gpx>").is_err());
+    }
+
+    #[test]
+    fn reads_geojson_positions_as_longitude_first() {
+        let text = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "geometry": {"type": "Point", "coordinates": [-74.5, 40.25]}, "properties": {"name": "A"}}
+        ]}"#;
+        assert_eq!(parse_features(Format::GeoJson, text).unwrap(), [feature("A", 402_500_000, -745_000_000)]);
+        assert!(parse_features(Format::GeoJson, "[]").is_err());
+    }
+
+    #[test]
+    fn picks_the_format_by_extension() {
+        assert_eq!(Format::from_path(Path::new("a/route.GPX")).unwrap(), Format::Gpx);
+        assert_eq!(Format::from_path(Path::new("db.json")).unwrap(), Format::Database);
+        assert!(matches!(Format::from_path(Path::new("notes.txt")), Err(FormatError::UnknownFormat(_))));
+    }
+}
@@ -0,0 +1,56 @@
+// Geometry on E7 points, shared by the RouteGuide handlers and the HTTP endpoints.
+#![allow(dead_code)]
+
+use crate::route_guide::{Point, Rectangle};
+
+
+/// Whether `point` lies inside `rect`, borders included. A rectangle without both corners
+/// contains nothing.
+pub fn in_range(point: &Point, rect: &Rectangle) -> bool {
+    use std::cmp;
+
+    let (lo, hi) = match (rect.lo.as_ref(), rect.hi.as_ref()) {
+        (Some(lo), Some(hi)) => (lo, hi),
+        _ => return false,
+    };
+
+    let left = cmp::min(lo.longitude, hi.longitude);
+    let right = cmp::max(lo.longitude, hi.longitude);
+    let top = cmp::max(lo.latitude, hi.latitude);
+    let bottom = cmp::min(lo.latitude, hi.latitude);
+
+    point.longitude >= left
+        && point.longitude <= right
+        && point.latitude >= bottom
+        && point.latitude <= top
+}
+
+/// Calculates the distance between two points using the "haversine" formula.
+/// This code was taken from http://www.movable-type.co.uk/scripts/latlong.html.
+pub fn get_distance(p1: &Point, p2: &Point) -> i32 {
+    const CORD_FACTOR: f64 = 1e7;
+    const R: f64 = 6_371_000.0; // meters
+
+    let lat1 = p1.latitude as f64 / CORD_FACTOR;
+    let lat2 = p2.latitude as f64 / CORD_FACTOR;
+    let lng1 = p1.longitude as f64 / CORD_FACTOR;
+    let lng2 = p2.longitude as f64 / CORD_FACTOR;
+
+    let lat_rad1 = lat1.to_radians();
+    let lat_rad2 = lat2.to_radians();
+
+    let delta_lat = (lat2 - lat1).to_radians();
+    let delta_lng = (lng2 - lng1).to_radians();
+
+    let a = (delta_lat / 2f64).sin() * (delta_lat / 2f64).sin()
+        + (lat_rad1).cos() * (lat_rad2).cos() * (delta_lng / 2f64).sin() * (delta_lng / 2f64).sin();
+
+    let c = 2f64 * a.sqrt().atan2((1f64 - a).sqrt());
+
+    (R * c) as i32
+}
+
+/// Whether `point` is at most `radius` metres from `center`.
+pub fn in_circle(point: &Point, center: &Point, radius: i32) -> bool {
+    get_distance(point, center) <= radius
+}
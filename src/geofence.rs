@@ -0,0 +1,216 @@
+// Geofences: areas that tracked clients are told about when they enter or leave them.
+//
+// The registry is shared by every listener. A `Tracker` follows one client: it remembers which
+// geofences the client is in, as they were when it entered, so that leaving a geofence that has
+// since been deleted or changed is still reported, with the geofence the client entered.
+#![allow(dead_code)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, PoisonError, RwLock};
+
+use crate::geo::{in_circle, in_range};
+use crate::route_guide::geofence::Shape;
+use crate::route_guide::geofence_event::Kind;
+use crate::route_guide::{Geofence, GeofenceEvent, Point};
+
+
+#[derive(Default)]
+pub struct GeofenceRegistry {
+    fences: RwLock<BTreeMap<String, Geofence>>,
+    last_id: AtomicU64,
+}
+
+impl GeofenceRegistry {
+    pub fn new() -> Arc<GeofenceRegistry> {
+        Arc::new(GeofenceRegistry::default())
+    }
+
+    /// Registers `fence` under a new id, and returns it with the id.
+    pub fn create(&self, mut fence: Geofence) -> Geofence {
+        fence.id = (self.last_id.fetch_add(1, Ordering::Relaxed) + 1).to_string();
+        self.fences.write().unwrap_or_else(PoisonError::into_inner).insert(fence.id.clone(), fence.clone());
+        fence
+    }
+
+    pub fn get(&self, id: &str) -> Option<Geofence> {
+        self.fences.read().unwrap_or_else(PoisonError::into_inner).get(id).cloned()
+    }
+
+    /// Every geofence, by id.
+    pub fn list(&self) -> Vec<Geofence> {
+        self.fences.read().unwrap_or_else(PoisonError::into_inner).values().cloned().collect()
+    }
+
+    /// Replaces the geofence with the id of `fence`. `None` if there is no such geofence.
+    pub fn update(&self, fence: Geofence) -> Option<Geofence> {
+        let mut fences = self.fences.write().unwrap_or_else(PoisonError::into_inner);
+        let current = fences.get_mut(&fence.id)?;
+        *current = fence.clone();
+        Some(fence)
+    }
+
+    pub fn delete(&self, id: &str) -> Option<Geofence> {
+        self.fences.write().unwrap_or_else(PoisonError::into_inner).remove(id)
+    }
+}
+
+
+pub fn contains(fence: &Geofence, point: &Point) -> bool {
+    match &fence.shape {
+        Some(Shape::Rectangle(rectangle)) => in_range(point, rectangle),
+        Some(Shape::Circle(circle)) => {
+            circle.center.as_ref().is_some_and(|center| in_circle(point, center, circle.radius))
+        },
+        None => false,
+    }
+}
+
+
+/// The geofences one client is in.
+#[derive(Default)]
+pub struct Tracker {
+    inside: HashMap<String, Geofence>,
+}
+
+impl Tracker {
+    pub fn new() -> Tracker {
+        Tracker::default()
+    }
+
+    /// Moves the client to `point`, and returns what it left and then what it entered.
+    pub fn update(&mut self, registry: &GeofenceRegistry, point: &Point) -> Vec<GeofenceEvent> {
+        let fences = registry.fences.read().unwrap_or_else(PoisonError::into_inner);
+        let event = |kind: Kind, geofence: &Geofence| GeofenceEvent {
+            kind: kind as i32,
+            geofence: Some(geofence.clone()),
+            location: Some(point.clone()),
+        };
+
+        let mut left: Vec<String> = self
+            .inside
+            .keys()
+            .filter(|id| fences.get(*id).is_none_or(|fence| !contains(fence, point)))
+            .cloned()
+            .collect();
+        left.sort();
+        let mut events = Vec::new();
+        for id in left {
+            if let Some(fence) = self.inside.remove(&id) {
+                events.push(event(Kind::Exit, &fence));
+            }
+        }
+
+        for (id, fence) in fences.iter() {
+            if !self.inside.contains_key(id) && contains(fence, point) {
+                events.push(event(Kind::Enter, fence));
+                self.inside.insert(id.clone(), fence.clone());
+            }
+        }
+
+        events
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::route_guide::{Circle, Rectangle};
+
+    fn point(latitude: i32, longitude: i32) -> Point {
+        Point { latitude, longitude }
+    }
+
+    fn rectangle(name: &str, lo: Point, hi: Point) -> Geofence {
+        Geofence { id: String::new(), name: name.to_string(), shape: Some(Shape::Rectangle(Rectangle { lo: Some(lo), hi: Some(hi) })) }
+    }
+
+    fn circle(name: &str, center: Point, radius: i32) -> Geofence {
+        Geofence { id: String::new(), name: name.to_string(), shape: Some(Shape::Circle(Circle { center: Some(center), radius })) }
+    }
+
+    /// The kind and geofence name of each event.
+    fn events(events: Vec<GeofenceEvent>) -> Vec<(Kind, String)> {
+        events
+            .into_iter()
+            .map(|event| (Kind::from_i32(event.kind).unwrap(), event.geofence.unwrap().name))
+            .collect()
+    }
+
+    #[test]
+    fn creates_updates_and_deletes_geofences() {
+        let registry = GeofenceRegistry::new();
+        let a = registry.create(circle("a", point(0, 0), 100));
+        let b = registry.create(circle("b", point(0, 0), 200));
+        assert_ne!(a.id, b.id);
+        assert_eq!(registry.get(&a.id), Some(a.clone()));
+        assert_eq!(registry.list(), [a.clone(), b.clone()]);
+
+        let renamed = Geofence { name: "a2".to_string(), ..a.clone() };
+        assert_eq!(registry.update(renamed.clone()), Some(renamed.clone()));
+        assert_eq!(registry.get(&a.id), Some(renamed));
+        assert_eq!(registry.update(Geofence { id: "unknown".to_string(), ..b.clone() }), None);
+
+        assert!(registry.delete(&b.id).is_some());
+        assert!(registry.delete(&b.id).is_none());
+        assert_eq!(registry.list().len(), 1);
+        // Ids aren't reused.
+        assert_ne!(registry.create(circle("c", point(0, 0), 1)).id, b.id);
+    }
+
+    #[test]
+    fn contains_points_in_its_shape() {
+        let rectangle = rectangle("r", point(0, 0), point(10_000, 10_000));
+        assert!(contains(&rectangle, &point(5_000, 5_000)));
+        assert!(!contains(&rectangle, &point(5_000, 20_000)));
+
+        // 1e-3 degrees of latitude are about 111 metres.
+        let circle = circle("c", point(0, 0), 150);
+        assert!(contains(&circle, &point(10_000, 0)));
+        assert!(!contains(&circle, &point(20_000, 0)));
+
+        let shapeless = Geofence { shape: None, ..circle };
+        assert!(!contains(&shapeless, &point(0, 0)));
+    }
+
+    #[test]
+    fn reports_exits_then_entries() {
+        let registry = GeofenceRegistry::new();
+        registry.create(rectangle("west", point(0, 0), point(10_000, 10_000)));
+        registry.create(rectangle("east", point(0, 20_000), point(10_000, 30_000)));
+        registry.create(rectangle("both", point(0, 0), point(10_000, 30_000)));
+        let mut tracker = Tracker::new();
+
+        assert_eq!(events(tracker.update(&registry, &point(5_000, 5_000))), [
+            (Kind::Enter, "west".to_string()),
+            (Kind::Enter, "both".to_string()),
+        ]);
+        assert!(tracker.update(&registry, &point(6_000, 6_000)).is_empty());
+        assert_eq!(events(tracker.update(&registry, &point(5_000, 25_000))), [
+            (Kind::Exit, "west".to_string()),
+            (Kind::Enter, "east".to_string()),
+        ]);
+        assert_eq!(events(tracker.update(&registry, &point(50_000, 50_000))), [
+            (Kind::Exit, "east".to_string()),
+            (Kind::Exit, "both".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn leaves_deleted_and_moved_geofences_as_they_were_entered() {
+        let registry = GeofenceRegistry::new();
+        let moved = registry.create(rectangle("moved", point(0, 0), point(10_000, 10_000)));
+        let deleted = registry.create(rectangle("deleted", point(0, 0), point(10_000, 10_000)));
+        let mut tracker = Tracker::new();
+        tracker.update(&registry, &point(5_000, 5_000));
+
+        registry.update(Geofence { id: moved.id.clone(), ..rectangle("moved away", point(50_000, 50_000), point(60_000, 60_000)) });
+        registry.delete(&deleted.id);
+
+        let left = tracker.update(&registry, &point(5_000, 5_000));
+        assert_eq!(left.iter().map(|event| event.geofence.clone().unwrap()).collect::<Vec<_>>(), [moved, deleted]);
+        assert!(left.iter().all(|event| event.kind == Kind::Exit as i32 && event.location == Some(point(5_000, 5_000))));
+    }
+}
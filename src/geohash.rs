@@ -0,0 +1,128 @@
+// Geohashes: a point's cell in a grid that halves longitude and latitude by turns, written as
+// base 32. Every character narrows the cell by 5 bits, and a hash is a prefix of the hashes of all
+// the cells inside it.
+#![allow(dead_code)]
+
+use crate::route_guide::{Point, Rectangle};
+
+
+pub const MAX_PRECISION: usize = 12;
+
+const ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const CORD_FACTOR: f64 = 1e7;
+
+
+/// The geohash of `point`, `precision` characters long.
+pub fn encode(point: &Point, precision: usize) -> String {
+    let latitude = f64::from(point.latitude) / CORD_FACTOR;
+    let longitude = f64::from(point.longitude) / CORD_FACTOR;
+    let (mut latitudes, mut longitudes) = ((-90.0, 90.0), (-180.0, 180.0));
+
+    let mut hash = String::with_capacity(precision);
+    let mut bit = 0;
+    let mut character = 0;
+    // Even bits split longitude, odd ones latitude.
+    for index in 0..precision.min(MAX_PRECISION) * 5 {
+        let (range, value): (&mut (f64, f64), f64) = if index % 2 == 0 {
+            (&mut longitudes, longitude)
+        } else {
+            (&mut latitudes, latitude)
+        };
+        let middle = (range.0 + range.1) / 2.0;
+        character <<= 1;
+        if value >= middle {
+            character |= 1;
+            range.0 = middle;
+        } else {
+            range.1 = middle;
+        }
+
+        bit += 1;
+        if bit == 5 {
+            hash.push(ALPHABET[character] as char);
+            bit = 0;
+            character = 0;
+        }
+    }
+    hash
+}
+
+/// The cell of `hash`, corners rounded to E7. `None` if it has characters that aren't base 32.
+pub fn bounds(hash: &str) -> Option<Rectangle> {
+    let (mut latitudes, mut longitudes) = ((-90.0f64, 90.0f64), (-180.0f64, 180.0f64));
+
+    let mut index = 0;
+    for c in hash.bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)?;
+        for shift in (0..5).rev() {
+            let range = if index % 2 == 0 { &mut longitudes } else { &mut latitudes };
+            let middle = (range.0 + range.1) / 2.0;
+            if (value >> shift) & 1 == 1 {
+                range.0 = middle;
+            } else {
+                range.1 = middle;
+            }
+            index += 1;
+        }
+    }
+
+    let e7 = |degrees: f64| (degrees * CORD_FACTOR).round() as i32;
+    Some(Rectangle {
+        lo: Some(Point { latitude: e7(latitudes.0), longitude: e7(longitudes.0) }),
+        hi: Some(Point { latitude: e7(latitudes.1), longitude: e7(longitudes.1) }),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::geo::in_range;
+
+    #[test]
+    fn encodes_known_points() {
+        assert_eq!(encode(&Point { latitude: 576_491_100, longitude: 104_074_400 }, 11), "u4pruydqqvj");
+        assert_eq!(encode(&Point { latitude: 0, longitude: 0 }, 5), "s0000");
+        assert_eq!(encode(&Point { latitude: -900_000_000, longitude: -1_800_000_000 }, 3), "000");
+        assert_eq!(encode(&Point { latitude: 900_000_000, longitude: 1_800_000_000 }, 3), "zzz");
+    }
+
+    #[test]
+    fn caps_the_precision() {
+        let point = Point { latitude: 407_838_351, longitude: -746_143_763 };
+        assert_eq!(encode(&point, 0), "");
+        assert_eq!(encode(&point, 20).len(), MAX_PRECISION);
+        assert!(encode(&point, MAX_PRECISION).starts_with(&encode(&point, 4)));
+    }
+
+    #[test]
+    fn bounds_contain_the_points_of_the_cell() {
+        let points = [
+            Point { latitude: 407_838_351, longitude: -746_143_763 },
+            Point { latitude: -335_000_000, longitude: 1_512_000_000 },
+            Point { latitude: 0, longitude: 0 },
+        ];
+        for point in &points {
+            for precision in 1..=8 {
+                let cell = bounds(&encode(point, precision)).unwrap();
+                assert!(in_range(point, &cell), "{:?} is outside of its cell {:?}", point, cell);
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_halve_by_turns() {
+        let world = bounds("").unwrap();
+        assert_eq!(world.lo, Some(Point { latitude: -900_000_000, longitude: -1_800_000_000 }));
+        assert_eq!(world.hi, Some(Point { latitude: 900_000_000, longitude: 1_800_000_000 }));
+
+        // Five bits: longitude, latitude, longitude, latitude, longitude.
+        let cell = bounds("s").unwrap();
+        assert_eq!(cell.lo, Some(Point { latitude: 0, longitude: 0 }));
+        assert_eq!(cell.hi, Some(Point { latitude: 450_000_000, longitude: 450_000_000 }));
+
+        assert!(bounds("u4pa").is_none());
+        assert!(bounds("U4").is_none());
+    }
+}
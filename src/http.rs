@@ -0,0 +1,701 @@
+// An incremental HTTP/1.1 request parser for the hand-rolled TcpListener server.
+//
+// Bytes are fed into the `Parser` as they arrive from the socket, and complete requests are taken
+// out one at a time with `next_request`. Whatever follows a request stays in the buffer, so
+// pipelined requests are handled by simply calling `next_request` again. Bodies can be delimited by
+// `Content-Length` or by `Transfer-Encoding: chunked`.
+#![allow(dead_code)]
+
+use std::fmt;
+use std::io::{self, Write};
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct Limits {
+    /// Maximum size of the request line and headers together (and of chunked trailers).
+    pub max_header_bytes: usize,
+    /// Maximum number of header fields.
+    pub max_headers: usize,
+    /// Maximum size of a (decoded) body.
+    pub max_body_bytes: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_header_bytes: 8 * 1024,
+            max_headers: 100,
+            max_body_bytes: 1024 * 1024,
+        }
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => write!(f, "HTTP/1.0"),
+            Version::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    pub target: String,
+    pub version: Version,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The first value of the header `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The path of the request target, without the query string.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+
+    /// Whether the connection should stay open after this request has been answered.
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.header("connection").unwrap_or("");
+        let has_option = |option: &str| {
+            connection.split(',').any(|value| value.trim().eq_ignore_ascii_case(option))
+        };
+
+        match self.version {
+            Version::Http11 => !has_option("close"),
+            Version::Http10 => has_option("keep-alive"),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The request is malformed. The string says how, for logging.
+    BadRequest(&'static str),
+    /// The body is larger than `Limits::max_body_bytes`.
+    PayloadTooLarge,
+    /// The request line and headers are larger than `Limits::max_header_bytes`, or there are
+    /// more than `Limits::max_headers` of them.
+    HeadersTooLarge,
+    /// A transfer coding other than chunked was used.
+    NotImplemented,
+    /// The request isn't HTTP/1.0 or HTTP/1.1.
+    VersionNotSupported,
+}
+
+impl ParseError {
+    pub fn status(&self) -> u16 {
+        match self {
+            ParseError::BadRequest(_)       => 400,
+            ParseError::PayloadTooLarge     => 413,
+            ParseError::HeadersTooLarge     => 431,
+            ParseError::NotImplemented      => 501,
+            ParseError::VersionNotSupported => 505,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::BadRequest(reason) => write!(f, "bad request: {}", reason),
+            _ => write!(f, "{}", reason_phrase(self.status())),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+
+pub struct Parser {
+    buffer: Vec<u8>,
+    limits: Limits,
+    /// The request whose head has been parsed while its body is still arriving, so that neither
+    /// the head nor what came of a chunked body is parsed again every time more data comes in.
+    partial: Option<Partial>,
+}
+
+struct Partial {
+    request: Request,
+    head_length: usize,
+    framing: Framing,
+}
+
+impl Parser {
+    pub fn new(limits: Limits) -> Parser {
+        Parser { buffer: Vec::new(), limits, partial: None }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Number of bytes received but not yet consumed by a request.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Whether the request line and headers of the next request have been received in full.
+    pub fn has_complete_head(&self) -> bool {
+        let leading = leading_empty_lines(&self.buffer);
+        self.partial.is_some() || find_head_end(&self.buffer[leading..]).is_some()
+    }
+
+    /// Takes the next complete request out of the buffer.
+    ///
+    /// Returns `Ok(None)` if more data is needed. After an error the connection can't be
+    /// resynchronized and should be closed once the error response has been sent.
+    pub fn next_request(&mut self) -> Result<Option<Request>, ParseError> {
+        if self.partial.is_none() {
+            // RFC 7230 3.5: ignore empty lines received before the request line.
+            let leading = leading_empty_lines(&self.buffer);
+            self.buffer.drain(..leading);
+
+            let head_length = match find_head_end(&self.buffer) {
+                Some(length) if length > self.limits.max_header_bytes => return Err(ParseError::HeadersTooLarge),
+                Some(length) => length,
+                None if self.buffer.len() > self.limits.max_header_bytes => return Err(ParseError::HeadersTooLarge),
+                None => return Ok(None),
+            };
+
+            let (request, framing) = parse_head(&self.buffer[..head_length], &self.limits)?;
+            self.partial = Some(Partial { request, head_length, framing });
+        }
+
+        let partial = self.partial.as_mut().expect("the head has just been parsed");
+        let head_length = partial.head_length;
+        let consumed = match &mut partial.framing {
+            Framing::None => head_length,
+            Framing::Length(length) => {
+                if self.buffer.len() < head_length + *length {
+                    return Ok(None);
+                }
+                partial.request.body = self.buffer[head_length..head_length + *length].to_vec();
+                head_length + *length
+            },
+            Framing::Chunked(chunked) => match chunked.decode(&self.buffer[head_length..], &self.limits)? {
+                Some(length) => {
+                    partial.request.body = std::mem::take(&mut chunked.body);
+                    head_length + length
+                },
+                None => return Ok(None),
+            },
+        };
+
+        let request = self.partial.take().expect("the head has just been parsed").request;
+        self.buffer.drain(..consumed);
+        Ok(Some(request))
+    }
+}
+
+
+enum Framing {
+    None,
+    Length(usize),
+    Chunked(Chunked),
+}
+
+fn leading_empty_lines(buffer: &[u8]) -> usize {
+    buffer.iter().take_while(|&&byte| byte == b'\r' || byte == b'\n').count()
+}
+
+/// Returns the length of the head including the empty line that ends it.
+fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while let Some(offset) = buffer[i..].iter().position(|&byte| byte == b'\n') {
+        let end = i + offset + 1;
+        match &buffer[end..] {
+            [b'\n', ..] => return Some(end + 1),
+            [b'\r', b'\n', ..] => return Some(end + 2),
+            _ => i = end,
+        }
+    }
+    None
+}
+
+/// Splits on LF and strips an optional CR before it.
+fn lines(bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+    bytes.split(|&byte| byte == b'\n').map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+fn parse_head(head: &[u8], limits: &Limits) -> Result<(Request, Framing), ParseError> {
+    let mut lines = lines(head).filter(|line| !line.is_empty());
+
+    let request_line = lines.next().ok_or(ParseError::BadRequest("missing request line"))?;
+    let request_line = std::str::from_utf8(request_line)
+        .map_err(|_| ParseError::BadRequest("request line is not UTF-8"))?;
+
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version), None) => (method, target, version),
+        _ => return Err(ParseError::BadRequest("malformed request line")),
+    };
+
+    if method.is_empty() || !method.bytes().all(is_token_char) {
+        return Err(ParseError::BadRequest("invalid method"));
+    }
+    if target.is_empty() || target.bytes().any(|byte| byte.is_ascii_control()) {
+        return Err(ParseError::BadRequest("invalid request target"));
+    }
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        _ if version.starts_with("HTTP/") => return Err(ParseError::VersionNotSupported),
+        _ => return Err(ParseError::BadRequest("invalid version")),
+    };
+
+    let mut headers = Vec::new();
+    for line in lines {
+        if headers.len() == limits.max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        headers.push(parse_header(line)?);
+    }
+
+    let request = Request {
+        method: method.to_string(),
+        target: target.to_string(),
+        version,
+        headers,
+        body: Vec::new(),
+    };
+
+    if request.version == Version::Http11 && request.header("host").is_none() {
+        return Err(ParseError::BadRequest("missing Host header"));
+    }
+
+    let framing = body_framing(&request, limits)?;
+    Ok((request, framing))
+}
+
+fn parse_header(line: &[u8]) -> Result<(String, String), ParseError> {
+    // Obsolete line folding (RFC 7230 3.2.4) must be rejected.
+    if line.starts_with(b" ") || line.starts_with(b"\t") {
+        return Err(ParseError::BadRequest("obsolete header line folding"));
+    }
+
+    let colon = line
+        .iter()
+        .position(|&byte| byte == b':')
+        .ok_or(ParseError::BadRequest("header without colon"))?;
+
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    if name.is_empty() || !name.iter().all(|&byte| is_token_char(byte)) {
+        return Err(ParseError::BadRequest("invalid header name"));
+    }
+    if value.iter().any(|&byte| byte != b'\t' && byte.is_ascii_control()) {
+        return Err(ParseError::BadRequest("invalid header value"));
+    }
+
+    let name = String::from_utf8_lossy(name).into_owned();
+    let value = String::from_utf8_lossy(value).trim_matches(|c| c == ' ' || c == '\t').to_string();
+    Ok((name, value))
+}
+
+fn body_framing(request: &Request, limits: &Limits) -> Result<Framing, ParseError> {
+    let transfer_encoding = request.header("transfer-encoding");
+    let content_lengths: Vec<&str> = request.headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    // Both at once is how requests get smuggled past proxies (RFC 7230 3.3.3).
+    if transfer_encoding.is_some() && !content_lengths.is_empty() {
+        return Err(ParseError::BadRequest("both Transfer-Encoding and Content-Length"));
+    }
+
+    if let Some(transfer_encoding) = transfer_encoding {
+        if request.version == Version::Http10 {
+            return Err(ParseError::BadRequest("Transfer-Encoding in HTTP/1.0"));
+        }
+        let last = transfer_encoding.rsplit(',').next().unwrap_or("").trim();
+        if !last.eq_ignore_ascii_case("chunked") {
+            return Err(ParseError::NotImplemented);
+        }
+        return Ok(Framing::Chunked(Chunked::default()));
+    }
+
+    let length = match content_lengths.first() {
+        Some(first) => {
+            if content_lengths.iter().any(|length| length != first) {
+                return Err(ParseError::BadRequest("conflicting Content-Length values"));
+            }
+            if first.is_empty() || !first.bytes().all(|byte| byte.is_ascii_digit()) {
+                return Err(ParseError::BadRequest("invalid Content-Length"));
+            }
+            // Too many digits to fit is too large anyway.
+            first.parse::<usize>().map_err(|_| ParseError::PayloadTooLarge)?
+        },
+        None => return Ok(Framing::None),
+    };
+
+    if length > limits.max_body_bytes {
+        return Err(ParseError::PayloadTooLarge);
+    }
+
+    Ok(if length == 0 { Framing::None } else { Framing::Length(length) })
+}
+
+/// A chunked body being decoded. It keeps its place, so every byte is only looked at once
+/// however the body is split up as it arrives.
+#[derive(Default)]
+struct Chunked {
+    body: Vec<u8>,
+    /// Where the next chunk size line, chunk or trailer line starts, from the end of the head.
+    position: usize,
+    /// The size of the chunk at `position`, once its size line has been read.
+    chunk: Option<usize>,
+    /// Whether the last chunk has been read, so only trailers are left.
+    last_chunk: bool,
+    trailer_bytes: usize,
+}
+
+impl Chunked {
+    /// Decodes what has arrived of the body in `data`. Returns the number of bytes the body took
+    /// up on the wire once it is complete, or `None` if more is needed.
+    fn decode(&mut self, data: &[u8], limits: &Limits) -> Result<Option<usize>, ParseError> {
+        while !self.last_chunk {
+            let size = match self.chunk {
+                Some(size) => size,
+                None => {
+                    let line_end = match data[self.position..].iter().position(|&byte| byte == b'\n') {
+                        Some(offset) => self.position + offset,
+                        None if data.len() - self.position > 1024 => return Err(ParseError::BadRequest("chunk size line too long")),
+                        None => return Ok(None),
+                    };
+
+                    let line = &data[self.position..line_end];
+                    let line = line.strip_suffix(b"\r").unwrap_or(line);
+                    // Chunk extensions (`;name=value`) are allowed and ignored.
+                    let size = line.split(|&byte| byte == b';').next().unwrap_or(b"");
+                    let size = std::str::from_utf8(size)
+                        .ok()
+                        .map(str::trim)
+                        .filter(|size| !size.is_empty() && size.bytes().all(|byte| byte.is_ascii_hexdigit()))
+                        .ok_or(ParseError::BadRequest("invalid chunk size"))?;
+                    let size = usize::from_str_radix(size, 16).map_err(|_| ParseError::PayloadTooLarge)?;
+
+                    self.position = line_end + 1;
+                    if size == 0 {
+                        self.last_chunk = true;
+                        break;
+                    }
+                    // The size comes from the client; adding it to the length could overflow.
+                    if size > limits.max_body_bytes.saturating_sub(self.body.len()) {
+                        return Err(ParseError::PayloadTooLarge);
+                    }
+                    self.chunk = Some(size);
+                    size
+                },
+            };
+
+            // The chunk data is followed by a CRLF.
+            let chunk_end = self.position + size;
+            let next = match data.get(chunk_end..) {
+                None | Some([]) | Some([b'\r']) => return Ok(None),
+                Some([b'\n', ..]) => chunk_end + 1,
+                Some([b'\r', b'\n', ..]) => chunk_end + 2,
+                Some(_) => return Err(ParseError::BadRequest("missing CRLF after chunk")),
+            };
+            self.body.extend_from_slice(&data[self.position..chunk_end]);
+            self.position = next;
+            self.chunk = None;
+        }
+
+        // Trailer fields, ended by an empty line. They are parsed for validity and then dropped.
+        loop {
+            let line_end = match data[self.position..].iter().position(|&byte| byte == b'\n') {
+                Some(offset) => self.position + offset,
+                None if data.len() - self.position > limits.max_header_bytes => return Err(ParseError::HeadersTooLarge),
+                None => return Ok(None),
+            };
+
+            let line = &data[self.position..line_end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            self.trailer_bytes += line_end + 1 - self.position;
+            self.position = line_end + 1;
+
+            if line.is_empty() {
+                return Ok(Some(self.position));
+            }
+            if self.trailer_bytes > limits.max_header_bytes {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            parse_header(line)?;
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response { status, headers: Vec::new(), body: Vec::new() }
+    }
+
+    pub fn header<V: ToString>(mut self, name: &str, value: V) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Serializes the status line, headers and (unless `include_body` is false, as for HEAD) the
+    /// body. `Content-Length` is always set from the body.
+    pub fn to_bytes(&self, include_body: bool) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason_phrase(self.status));
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+
+        let mut bytes = head.into_bytes();
+        if include_body {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W, include_body: bool) -> io::Result<()> {
+        writer.write_all(&self.to_bytes(include_body))?;
+        writer.flush()
+    }
+}
+
+pub fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        505 => "HTTP Version Not Supported",
+        _ => "Unknown",
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn parse(data: &[u8]) -> Result<Option<Request>, ParseError> {
+        let mut parser = Parser::new(Limits::default());
+        parser.feed(data);
+        parser.next_request()
+    }
+
+    /// Feeds `data` a byte at a time, taking requests out as they complete.
+    fn parse_bytewise(data: &[u8]) -> Result<Vec<Request>, ParseError> {
+        let mut parser = Parser::new(Limits::default());
+        let mut requests = Vec::new();
+        for &byte in data {
+            parser.feed(&[byte]);
+            while let Some(request) = parser.next_request()? {
+                requests.push(request);
+            }
+        }
+        Ok(requests)
+    }
+
+    const CHUNKED: &[u8] = b"POST /echo HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+        4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\n";
+
+    #[test]
+    fn parses_a_simple_request() {
+        let request = parse(b"GET /index.html?x=1 HTTP/1.1\r\nHost: example.com\r\nConnection: close\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path(), "/index.html");
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.header("HOST"), Some("example.com"));
+        assert!(!request.keep_alive());
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_the_head_and_body() {
+        let mut parser = Parser::new(Limits::default());
+        parser.feed(b"PUT /x HTTP/1.1\r\nHost: a\r\n");
+        assert!(!parser.has_complete_head());
+        assert!(parser.next_request().unwrap().is_none());
+        parser.feed(b"Content-Length: 5\r\n\r\nab");
+        assert!(parser.has_complete_head());
+        assert!(parser.next_request().unwrap().is_none());
+        parser.feed(b"cde");
+        assert_eq!(parser.next_request().unwrap().unwrap().body, b"abcde");
+        assert_eq!(parser.buffered(), 0);
+    }
+
+    #[test]
+    fn splits_pipelined_requests() {
+        let data = b"\r\nGET /a HTTP/1.1\r\nHost: a\r\n\r\nPOST /b HTTP/1.0\r\nContent-Length: 2\r\n\r\nhiGET /c HTTP/1.1\r\nHost: a\r\n\r\n";
+        let requests = parse_bytewise(data).unwrap();
+        let paths: Vec<&str> = requests.iter().map(Request::path).collect();
+        assert_eq!(paths, ["/a", "/b", "/c"]);
+        assert_eq!(requests[1].body, b"hi");
+        assert!(!requests[1].keep_alive());
+    }
+
+    #[test]
+    fn decodes_chunked_bodies_however_they_arrive() {
+        assert_eq!(parse(CHUNKED).unwrap().unwrap().body, b"Wikipedia");
+        let requests = parse_bytewise(CHUNKED).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].body, b"Wikipedia");
+
+        for split in 0..CHUNKED.len() {
+            let mut parser = Parser::new(Limits::default());
+            parser.feed(&CHUNKED[..split]);
+            assert!(parser.next_request().unwrap().is_none(), "complete after {} bytes", split);
+            parser.feed(&CHUNKED[split..]);
+            assert_eq!(parser.next_request().unwrap().unwrap().body, b"Wikipedia");
+        }
+    }
+
+    #[test]
+    fn waits_for_truncated_chunked_bodies() {
+        let head = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for tail in &[&b"4\r\nWi"[..], b"4\r\nWiki", b"4\r\nWiki\r", b"4\r\nWiki\r\n0\r\n", b"4\r\nWiki\r\n0\r\nA: b\r\n"] {
+            let data = [&head[..], tail].concat();
+            assert!(parse(&data).unwrap().is_none(), "{:?}", String::from_utf8_lossy(tail));
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_chunks_without_overflowing() {
+        let head = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        for tail in &[&b"a\r\n0123456789\r\nffffffffffffffff\r\n"[..], b"fffffffffffffffffffff\r\n", b"100001\r\n"] {
+            let data = [&head[..], tail].concat();
+            assert_eq!(parse(&data).err(), Some(ParseError::PayloadTooLarge), "{:?}", String::from_utf8_lossy(tail));
+        }
+
+        let limits = Limits { max_body_bytes: 8, ..Limits::default() };
+        let mut parser = Parser::new(limits);
+        parser.feed(&head);
+        parser.feed(b"5\r\nhello\r\n4\r\n");
+        assert_eq!(parser.next_request().err(), Some(ParseError::PayloadTooLarge));
+    }
+
+    #[test]
+    fn rejects_malformed_chunked_bodies() {
+        let head = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        let malformed: &[&[u8]] = &[b"x\r\n", b"\r\n", b"-1\r\n", b"4\r\nWikiX\r\n", b"0\r\nnot a header\r\n\r\n"];
+        for tail in malformed {
+            let data = [&head[..], tail].concat();
+            assert!(matches!(parse(&data), Err(ParseError::BadRequest(_))), "{:?}", String::from_utf8_lossy(tail));
+        }
+
+        let long_line = [&head[..], &[b'1'; 2000][..]].concat();
+        assert!(matches!(parse(&long_line), Err(ParseError::BadRequest(_))));
+        let long_trailers = [&head[..], b"0\r\n", &[b'a'; 9000][..]].concat();
+        assert_eq!(parse(&long_trailers).err(), Some(ParseError::HeadersTooLarge));
+    }
+
+    #[test]
+    fn rejects_bad_heads() {
+        let cases: &[(&[u8], u16)] = &[
+            (b"GET / HTTP/1.1\r\n\r\n", 400),
+            (b"GET /  HTTP/1.1\r\nHost: a\r\n\r\n", 400),
+            (b"G(T / HTTP/1.1\r\nHost: a\r\n\r\n", 400),
+            (b"GET / HTTP/2.0\r\nHost: a\r\n\r\n", 505),
+            (b"GET / FTP/1.1\r\nHost: a\r\n\r\n", 400),
+            (b"GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n", 400),
+            (b"GET / HTTP/1.1\r\nHost a\r\n\r\n", 400),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n", 400),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1, 2\r\n\r\n", 400),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: -1\r\n\r\n", 400),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 99999999999999999999999\r\n\r\n", 413),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2000000\r\n\r\n", 413),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip\r\n\r\n", 501),
+            (b"POST / HTTP/1.0\r\nTransfer-Encoding: chunked\r\n\r\n", 400),
+        ];
+        for &(data, status) in cases {
+            let error = parse(data).expect_err(&String::from_utf8_lossy(data));
+            assert_eq!(error.status(), status, "{:?}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn limits_the_head() {
+        let limits = Limits { max_header_bytes: 64, max_headers: 2, ..Limits::default() };
+        let mut parser = Parser::new(limits);
+        parser.feed(&[b'a'; 65]);
+        assert_eq!(parser.next_request().err(), Some(ParseError::HeadersTooLarge));
+
+        let mut parser = Parser::new(limits);
+        parser.feed(b"GET / HTTP/1.1\r\nHost: a\r\nA: b\r\nC: d\r\n\r\n");
+        assert_eq!(parser.next_request().err(), Some(ParseError::HeadersTooLarge));
+    }
+
+    #[test]
+    fn survives_random_input() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let alphabet = b"0123456789abcdefxzGETPOST /:;,-\r\n\r\n\t";
+        for _ in 0..2000 {
+            let mut data = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            if rng.gen_bool(0.3) {
+                data.clear();
+            }
+            let length = rng.gen_range(0, 200);
+            data.extend((0..length).map(|_| alphabet[rng.gen_range(0, alphabet.len())]));
+
+            let mut parser = Parser::new(Limits { max_body_bytes: 64, max_header_bytes: 128, max_headers: 8 });
+            let mut position = 0;
+            while position < data.len() {
+                let step = rng.gen_range(1, 16).min(data.len() - position);
+                parser.feed(&data[position..position + step]);
+                position += step;
+                match parser.next_request() {
+                    Ok(Some(request)) => assert!(request.body.len() <= 64),
+                    Ok(None) => {},
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+}
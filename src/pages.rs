@@ -0,0 +1,54 @@
+// The routing shared by the blocking and the async raw servers. Only deciding which page to serve
+// lives here; reading it is left to the server, with `std::fs` or `tokio::fs` respectively.
+#![allow(dead_code)]
+
+use std::io;
+
+use crate::error_pages::ErrorContext;
+use crate::http;
+
+
+pub enum Route {
+    Page(&'static str),
+    Error(u16),
+}
+
+pub fn resolve(request: &http::Request) -> Route {
+    match (request.method.as_str(), request.path()) {
+        ("GET", "/") | ("HEAD", "/") => Route::Page("static/html/index.html"),
+        (_, "/") => Route::Error(405),
+        _ => Route::Error(404),
+    }
+}
+
+/// Builds the response for the page at `path` from the result of reading it.
+pub fn page_response(request: &http::Request, path: &str, contents: io::Result<Vec<u8>>) -> http::Response {
+    match contents {
+        Ok(contents) => http::Response::new(200)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(contents),
+        Err(e) => {
+            tracing::error!(path = %path, error = %e, "failed to read page");
+            error_response(500, Some(request))
+        },
+    }
+}
+
+/// Renders the error page for `status`. `request` is `None` when the error happened before a
+/// request could be parsed.
+pub fn error_response(status: u16, request: Option<&http::Request>) -> http::Response {
+    let path = request.map_or("", |request| request.path());
+    let header = |name| request.and_then(|request| request.header(name));
+
+    let context = ErrorContext::new(status, http::reason_phrase(status), path, header("x-request-id"));
+    let page = context.render(header("accept"));
+
+    let mut response = http::Response::new(status)
+        .header("Content-Type", page.content_type)
+        .header("X-Request-Id", &context.request_id)
+        .body(page.body);
+    if status == 405 {
+        response = response.header("Allow", "GET, HEAD");
+    }
+    response
+}
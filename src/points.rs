@@ -0,0 +1,90 @@
+// Random points, for clients that exercise the RouteGuide service.
+#![allow(dead_code)]
+
+use std::str::FromStr;
+
+use rand::Rng;
+
+use crate::route_guide::{Feature, Point};
+
+
+const MAX_LATITUDE: i32 = 900_000_000;
+const MAX_LONGITUDE: i32 = 1_800_000_000;
+
+
+/// A point on a whole degree, anywhere on earth.
+pub fn random_point<R: Rng>(rng: &mut R) -> Point {
+    let latitude = (rng.gen_range(0, 180) - 90) * 10_000_000;
+    let longitude = (rng.gen_range(0, 360) - 180) * 10_000_000;
+    Point {
+        latitude,
+        longitude,
+    }
+}
+
+
+/// Where the points of a load test fall.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Distribution {
+    /// `random_point`: anywhere, and almost never on a feature.
+    Uniform,
+    /// Exactly on a known feature, each as likely as the others.
+    Features,
+    /// Anywhere within `radius` E7 units of `center`, in both latitude and longitude.
+    Cluster { center: Point, radius: i32 },
+}
+
+impl Distribution {
+    /// A point of the distribution. `features` is only used by `Features`, and `Uniform` is used
+    /// instead if there are none.
+    pub fn sample<R: Rng>(&self, rng: &mut R, features: &[Feature]) -> Point {
+        match self {
+            Distribution::Features if !features.is_empty() => {
+                features[rng.gen_range(0, features.len())].location.clone().unwrap_or_default()
+            },
+            Distribution::Cluster { center, radius } => {
+                let radius = i64::from(*radius);
+                let offset = |rng: &mut R| rng.gen_range(-radius, radius + 1);
+                let clamp = |value: i64, max: i32| value.max(-i64::from(max)).min(i64::from(max)) as i32;
+                Point {
+                    latitude: clamp(i64::from(center.latitude) + offset(rng), MAX_LATITUDE),
+                    longitude: clamp(i64::from(center.longitude) + offset(rng), MAX_LONGITUDE),
+                }
+            },
+            _ => random_point(rng),
+        }
+    }
+}
+
+/// `uniform`, `features` or `cluster:<lat>,<lng>,<radius>` with all three in E7. The center has
+/// to be a valid coordinate, and the radius at most the range of longitudes.
+impl FromStr for Distribution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Distribution, String> {
+        match s {
+            "uniform"  => return Ok(Distribution::Uniform),
+            "features" => return Ok(Distribution::Features),
+            _ => {},
+        }
+
+        let invalid = || format!("unknown distribution {:?}; use uniform, features or cluster:lat,lng,radius", s);
+        let valid = |latitude: i32, longitude: i32, radius: i32| {
+            (-MAX_LATITUDE..=MAX_LATITUDE).contains(&latitude)
+                && (-MAX_LONGITUDE..=MAX_LONGITUDE).contains(&longitude)
+                && (1..=MAX_LONGITUDE).contains(&radius)
+        };
+        let values = s
+            .strip_prefix("cluster:")
+            .ok_or_else(invalid)?
+            .split(',')
+            .map(|value| value.trim().parse::<i32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match values[..] {
+            [latitude, longitude, radius] if valid(latitude, longitude, radius) => {
+                Ok(Distribution::Cluster { center: Point { latitude, longitude }, radius })
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
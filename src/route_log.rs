@@ -0,0 +1,39 @@
+// The routes clients recorded with RecordRoute, kept in memory for aggregate queries. Only the
+// last `CAPACITY` routes are kept; older ones are forgotten as new ones come in.
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::route_guide::Point;
+
+
+const CAPACITY: usize = 10_000;
+
+
+#[derive(Default)]
+pub struct RouteLog {
+    routes: Mutex<VecDeque<Arc<Vec<Point>>>>,
+}
+
+impl RouteLog {
+    pub fn new() -> Arc<RouteLog> {
+        Arc::new(RouteLog::default())
+    }
+
+    pub fn record(&self, points: Vec<Point>) {
+        if points.is_empty() {
+            return;
+        }
+        let mut routes = self.routes.lock().unwrap_or_else(PoisonError::into_inner);
+        if routes.len() == CAPACITY {
+            routes.pop_front();
+        }
+        routes.push_back(Arc::new(points));
+    }
+
+    /// The routes recorded, oldest first.
+    pub fn routes(&self) -> Vec<Arc<Vec<Point>>> {
+        self.routes.lock().unwrap_or_else(PoisonError::into_inner).iter().cloned().collect()
+    }
+}
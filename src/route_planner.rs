@@ -0,0 +1,221 @@
+// Plans routes through the features: from a point to another, hopping from feature to feature.
+//
+// The graph has a node for every feature, plus the two ends, and an edge between any two that are
+// at most `max_hop` apart, weighted with `get_distance`. Edges are found with the spatial index
+// as the search reaches a node rather than all up front, so planning only looks at the features
+// around the way. The search is A*, with the straight distance to the goal as the estimate.
+// Distances are in whole metres, rounded down at every hop, so the estimate can be a metre per hop
+// too high and a route that is a few metres shorter may be missed.
+#![allow(dead_code)]
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::geo::get_distance;
+use crate::route_guide::{Feature, Point};
+use crate::spatial::SpatialIndex;
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Constraints {
+    /// The longest distance between two waypoints, in metres.
+    pub max_hop: i32,
+    /// The longest route, in metres, if there is a limit.
+    pub max_distance: Option<i64>,
+}
+
+/// A point on a planned route, with the feature there if it is one, and the distance from the
+/// start of the route.
+#[derive(Debug, Clone)]
+pub struct Step<'a> {
+    pub location: Point,
+    pub feature: Option<&'a Feature>,
+    pub distance: i64,
+}
+
+/// The shortest route from `from` to `to` within `constraints`, through the features of `index`,
+/// starting with `from` and ending with `to`. `None` if there is none.
+pub fn plan<'a>(index: &'a SpatialIndex, from: &Point, to: &Point, constraints: &Constraints) -> Option<Vec<Step<'a>>> {
+    let features = index.features();
+    // Features are nodes 0 to n - 1; the ends come after them.
+    let start = features.len();
+    let goal = features.len() + 1;
+    let location = |node: usize| -> Point {
+        if node == start {
+            from.clone()
+        } else if node == goal {
+            to.clone()
+        } else {
+            features[node].location.clone().unwrap_or_default()
+        }
+    };
+
+    let mut best: HashMap<usize, i64> = HashMap::new();
+    let mut previous: HashMap<usize, usize> = HashMap::new();
+    // By estimated total, then by distance so far; `Reverse` makes it a min-heap.
+    let mut open = BinaryHeap::new();
+    best.insert(start, 0);
+    open.push(Reverse((i64::from(get_distance(from, to)), 0i64, start)));
+
+    while let Some(Reverse((_, distance, node))) = open.pop() {
+        if node == goal {
+            return Some(steps(index, &previous, goal, &best, location));
+        }
+        if best.get(&node).is_some_and(|&known| known < distance) {
+            continue; // A shorter way here was found after this one was queued.
+        }
+
+        let here = location(node);
+        let mut neighbours: Vec<(usize, i32)> = index
+            .within(&here, constraints.max_hop)
+            .into_iter()
+            .map(|nearby| (nearby.index, nearby.distance))
+            .collect();
+        let to_goal = get_distance(&here, to);
+        if to_goal <= constraints.max_hop {
+            neighbours.push((goal, to_goal));
+        }
+
+        for (next, hop) in neighbours {
+            let next_distance = distance + i64::from(hop);
+            if next == node || constraints.max_distance.is_some_and(|max| next_distance > max) {
+                continue;
+            }
+            if best.get(&next).is_none_or(|&known| next_distance < known) {
+                best.insert(next, next_distance);
+                previous.insert(next, node);
+                let estimate = next_distance + i64::from(get_distance(&location(next), to));
+                open.push(Reverse((estimate, next_distance, next)));
+            }
+        }
+    }
+
+    None
+}
+
+fn steps<'a, L>(
+    index: &'a SpatialIndex,
+    previous: &HashMap<usize, usize>,
+    goal: usize,
+    best: &HashMap<usize, i64>,
+    location: L,
+) -> Vec<Step<'a>>
+where
+    L: Fn(usize) -> Point,
+{
+    let features = index.features();
+    let mut nodes = vec![goal];
+    while let Some(&node) = previous.get(nodes.last().unwrap()) {
+        nodes.push(node);
+    }
+    nodes.reverse();
+
+    let mut steps: Vec<Step<'a>> = nodes
+        .into_iter()
+        .map(|node| {
+            let location = location(node);
+            // The ends are named after a feature that is right there, if there is one.
+            let feature = match features.get(node) {
+                Some(feature) => Some(feature),
+                None => index.within(&location, 0).first().map(|nearby| nearby.feature),
+            };
+            Step { location, feature, distance: best[&node] }
+        })
+        .collect();
+    // An end at a feature would otherwise be listed twice.
+    steps.dedup_by(|step, before| step.location == before.location);
+    steps
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    /// A feature every tenth of a degree along the equator, about 11 km apart.
+    fn equator(count: i32) -> SpatialIndex {
+        let features = (0..count)
+            .map(|i| Feature { name: format!("f{}", i), location: Some(Point { latitude: 0, longitude: i * 1_000_000 }) })
+            .collect();
+        SpatialIndex::new(Arc::new(features))
+    }
+
+    fn point(longitude: i32) -> Point {
+        Point { latitude: 0, longitude }
+    }
+
+    fn names(steps: &[Step<'_>]) -> Vec<String> {
+        steps.iter().map(|step| step.feature.map_or_else(|| "-".to_string(), |feature| feature.name.clone())).collect()
+    }
+
+    fn constraints(max_hop: i32) -> Constraints {
+        Constraints { max_hop, max_distance: None }
+    }
+
+    #[test]
+    fn hops_through_every_feature_when_they_have_to() {
+        let index = equator(5);
+        let steps = plan(&index, &point(-500_000), &point(4_500_000), &constraints(12_000)).unwrap();
+
+        assert_eq!(names(&steps), ["-", "f0", "f1", "f2", "f3", "f4", "-"]);
+        assert_eq!(steps[0].distance, 0);
+        assert!(steps.windows(2).all(|pair| pair[0].distance < pair[1].distance));
+        let straight = i64::from(get_distance(&point(-500_000), &point(4_500_000)));
+        assert!((steps.last().unwrap().distance - straight).abs() <= steps.len() as i64);
+    }
+
+    #[test]
+    fn goes_straight_when_the_ends_are_close_enough() {
+        // The features are on the way, but not on the straight line.
+        let index = equator(5);
+        let from = Point { latitude: 500_000, longitude: 500_000 };
+        let to = Point { latitude: 500_000, longitude: 2_500_000 };
+        let steps = plan(&index, &from, &to, &constraints(1_000_000)).unwrap();
+        assert_eq!(names(&steps), ["-", "-"]);
+    }
+
+    #[test]
+    fn names_ends_that_are_at_a_feature_once() {
+        let index = equator(3);
+        let steps = plan(&index, &point(0), &point(2_000_000), &constraints(12_000)).unwrap();
+        assert_eq!(names(&steps), ["f0", "f1", "f2"]);
+        assert_eq!(steps[2].distance, steps.iter().map(|step| step.distance).max().unwrap());
+    }
+
+    #[test]
+    fn fails_when_a_gap_is_longer_than_a_hop() {
+        let index = equator(5);
+        assert!(plan(&index, &point(0), &point(4_000_000), &constraints(10_000)).is_none());
+        // Past the last feature, the goal is out of reach.
+        assert!(plan(&index, &point(0), &point(6_000_000), &constraints(12_000)).is_none());
+    }
+
+    #[test]
+    fn respects_the_longest_route() {
+        let index = equator(5);
+        let from = point(0);
+        let to = point(4_000_000);
+        let length = plan(&index, &from, &to, &constraints(12_000)).unwrap().last().unwrap().distance;
+
+        let limited = |max_distance| Constraints { max_hop: 12_000, max_distance: Some(max_distance) };
+        assert!(plan(&index, &from, &to, &limited(length)).is_some());
+        assert!(plan(&index, &from, &to, &limited(length - 1)).is_none());
+    }
+
+    #[test]
+    fn takes_the_shortest_way_rather_than_the_fewest_hops() {
+        // From a to b either through one far detour, or through three features on the way.
+        let features = vec![
+            Feature { name: "detour".to_string(), location: Some(Point { latitude: 1_400_000, longitude: 1_500_000 }) },
+            Feature { name: "w1".to_string(), location: Some(Point { latitude: 0, longitude: 750_000 }) },
+            Feature { name: "w2".to_string(), location: Some(Point { latitude: 0, longitude: 1_500_000 }) },
+            Feature { name: "w3".to_string(), location: Some(Point { latitude: 0, longitude: 2_250_000 }) },
+        ];
+        let index = SpatialIndex::new(Arc::new(features));
+
+        let steps = plan(&index, &point(0), &point(3_000_000), &constraints(25_000)).unwrap();
+        assert_eq!(names(&steps), ["-", "w1", "w2", "w3", "-"]);
+    }
+}
@@ -0,0 +1,275 @@
+// A k-d tree over the features, for nearest-neighbour and radius searches.
+//
+// Points are placed on the unit sphere in 3D, where the straight-line (chord) distance between two
+// points grows with the distance along the surface. That keeps the tree an ordinary Euclidean one,
+// with no trouble at the poles or across the antimeridian. The tree only picks the candidates; the
+// results are measured and ordered with `geo::get_distance`, like every other distance we report.
+//
+// The tree is implicit: the node for a range of `entries` is its middle element, split on the
+// axis of its depth, with the smaller half before it and the larger after.
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use crate::geo::get_distance;
+use crate::route_guide::{Feature, Point};
+
+
+const EARTH_RADIUS: f64 = 6_371_000.0; // meters, as in `get_distance`
+const CORD_FACTOR: f64 = 1e7;
+
+
+#[derive(Debug, Clone)]
+struct Entry {
+    position: [f64; 3],
+    /// Index into the features.
+    feature: usize,
+}
+
+pub struct SpatialIndex {
+    features: Arc<Vec<Feature>>,
+    entries: Vec<Entry>,
+}
+
+/// A feature found by a search, `distance` meters from where it was searched from.
+#[derive(Debug, Clone, Copy)]
+pub struct Nearby<'a> {
+    pub feature: &'a Feature,
+    /// Where the feature is in `SpatialIndex::features`.
+    pub index: usize,
+    pub distance: i32,
+}
+
+impl SpatialIndex {
+    /// Indexes the features that have a location.
+    pub fn new(features: Arc<Vec<Feature>>) -> SpatialIndex {
+        let mut entries: Vec<Entry> = features
+            .iter()
+            .enumerate()
+            .filter_map(|(feature, f)| f.location.as_ref().map(|point| Entry { position: position(point), feature }))
+            .collect();
+        build(&mut entries, 0);
+
+        SpatialIndex { features, entries }
+    }
+
+    /// The features this index was built from.
+    pub fn features(&self) -> &Arc<Vec<Feature>> {
+        &self.features
+    }
+
+    /// The `k` features nearest to `point`, nearest first.
+    pub fn nearest(&self, point: &Point, k: usize) -> Vec<Nearby<'_>> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut best = Vec::with_capacity(k + 1);
+        self.search_nearest(&position(point), k, 0, self.entries.len(), 0, &mut best);
+        self.measure(point, best.into_iter().map(|(_, feature)| feature))
+    }
+
+    /// The features at most `meters` from `point`, nearest first.
+    pub fn within(&self, point: &Point, meters: i32) -> Vec<Nearby<'_>> {
+        // The chord of the arc, with some room for rounding; `get_distance` has the last word.
+        let angle = f64::from(meters.max(0)) / EARTH_RADIUS;
+        let chord = 2.0 * (angle / 2.0).min(std::f64::consts::FRAC_PI_2).sin() * (1.0 + 1e-9) + 1e-12;
+
+        let mut found = Vec::new();
+        self.search_within(&position(point), chord * chord, 0, self.entries.len(), 0, &mut found);
+        // In database order, which `measure` keeps for features at the same distance.
+        found.sort_unstable();
+
+        let mut nearby = self.measure(point, found.into_iter());
+        nearby.retain(|nearby| nearby.distance <= meters);
+        nearby
+    }
+
+    fn measure(&self, point: &Point, features: impl Iterator<Item = usize>) -> Vec<Nearby<'_>> {
+        let mut nearby: Vec<Nearby<'_>> = features
+            .map(|index| {
+                let feature = &self.features[index];
+                let location = feature.location.clone().unwrap_or_default();
+                Nearby { feature, index, distance: get_distance(point, &location) }
+            })
+            .collect();
+        // Stable, so features at the same distance stay in database order.
+        nearby.sort_by_key(|nearby| nearby.distance);
+        nearby
+    }
+
+    /// Keeps `best` as the up to `k` closest entries seen, by squared chord, closest first.
+    fn search_nearest(&self, target: &[f64; 3], k: usize, lo: usize, hi: usize, depth: usize, best: &mut Vec<(f64, usize)>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let entry = &self.entries[mid];
+        let axis = depth % 3;
+
+        let distance = squared_distance(target, &entry.position);
+        if best.len() < k || distance < best[best.len() - 1].0 {
+            let at = best
+                .iter()
+                .position(|&(d, feature)| (distance, entry.feature) < (d, feature))
+                .unwrap_or(best.len());
+            best.insert(at, (distance, entry.feature));
+            best.truncate(k);
+        }
+
+        let offset = target[axis] - entry.position[axis];
+        let (near, far) = if offset < 0.0 { ((lo, mid), (mid + 1, hi)) } else { ((mid + 1, hi), (lo, mid)) };
+        self.search_nearest(target, k, near.0, near.1, depth + 1, best);
+        // The other side can only hold something closer if the splitting plane is.
+        if best.len() < k || offset * offset <= best[best.len() - 1].0 {
+            self.search_nearest(target, k, far.0, far.1, depth + 1, best);
+        }
+    }
+
+    fn search_within(&self, target: &[f64; 3], limit: f64, lo: usize, hi: usize, depth: usize, found: &mut Vec<usize>) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let entry = &self.entries[mid];
+        let axis = depth % 3;
+
+        if squared_distance(target, &entry.position) <= limit {
+            found.push(entry.feature);
+        }
+
+        let offset = target[axis] - entry.position[axis];
+        if offset <= 0.0 || offset * offset <= limit {
+            self.search_within(target, limit, lo, mid, depth + 1, found);
+        }
+        if offset >= 0.0 || offset * offset <= limit {
+            self.search_within(target, limit, mid + 1, hi, depth + 1, found);
+        }
+    }
+}
+
+
+fn build(entries: &mut [Entry], depth: usize) {
+    if entries.len() <= 1 {
+        return;
+    }
+    let axis = depth % 3;
+    entries.sort_by(|a, b| a.position[axis].partial_cmp(&b.position[axis]).unwrap_or(Ordering::Equal));
+
+    let mid = entries.len() / 2;
+    let (before, after) = entries.split_at_mut(mid);
+    build(before, depth + 1);
+    build(&mut after[1..], depth + 1);
+}
+
+/// `point` on the unit sphere.
+fn position(point: &Point) -> [f64; 3] {
+    let latitude = (f64::from(point.latitude) / CORD_FACTOR).to_radians();
+    let longitude = (f64::from(point.longitude) / CORD_FACTOR).to_radians();
+    [latitude.cos() * longitude.cos(), latitude.cos() * longitude.sin(), latitude.sin()]
+}
+
+fn squared_distance(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    (0..3).map(|axis| (a[axis] - b[axis]) * (a[axis] - b[axis])).sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn feature(name: &str, latitude: i32, longitude: i32) -> Feature {
+        Feature { name: name.to_string(), location: Some(Point { latitude, longitude }) }
+    }
+
+    fn random_features(rng: &mut StdRng, count: usize) -> Arc<Vec<Feature>> {
+        let features = (0..count)
+            .map(|i| feature(&i.to_string(), rng.gen_range(-900_000_000, 900_000_001), rng.gen_range(-1_800_000_000, 1_800_000_001)))
+            .collect();
+        Arc::new(features)
+    }
+
+    /// Every feature with its distance from `point`, nearest first, as the index should order them.
+    fn brute_force(features: &[Feature], point: &Point) -> Vec<(usize, i32)> {
+        let mut all: Vec<(usize, i32)> = features
+            .iter()
+            .enumerate()
+            .filter_map(|(index, feature)| feature.location.as_ref().map(|location| (index, get_distance(point, location))))
+            .collect();
+        all.sort_by_key(|&(_, distance)| distance);
+        all
+    }
+
+    fn distances(nearby: &[Nearby<'_>]) -> Vec<i32> {
+        nearby.iter().map(|nearby| nearby.distance).collect()
+    }
+
+    #[test]
+    fn nearest_matches_a_brute_force_search() {
+        let mut rng = StdRng::seed_from_u64(45);
+        let features = random_features(&mut rng, 500);
+        let index = SpatialIndex::new(features.clone());
+
+        for _ in 0..100 {
+            let point = Point { latitude: rng.gen_range(-900_000_000, 900_000_001), longitude: rng.gen_range(-1_800_000_000, 1_800_000_001) };
+            let k = rng.gen_range(1, 20);
+            let expected: Vec<i32> = brute_force(&features, &point).into_iter().take(k).map(|(_, distance)| distance).collect();
+            assert_eq!(distances(&index.nearest(&point, k)), expected);
+        }
+    }
+
+    #[test]
+    fn within_matches_a_brute_force_search() {
+        let mut rng = StdRng::seed_from_u64(46);
+        let features = random_features(&mut rng, 500);
+        let index = SpatialIndex::new(features.clone());
+
+        for _ in 0..100 {
+            let point = Point { latitude: rng.gen_range(-900_000_000, 900_000_001), longitude: rng.gen_range(-1_800_000_000, 1_800_000_001) };
+            let meters = rng.gen_range(0, 3_000_000);
+            let mut expected: Vec<usize> = brute_force(&features, &point)
+                .into_iter()
+                .filter(|&(_, distance)| distance <= meters)
+                .map(|(index, _)| index)
+                .collect();
+            let mut found: Vec<usize> = index.within(&point, meters).iter().map(|nearby| nearby.index).collect();
+            expected.sort_unstable();
+            found.sort_unstable();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn finds_neighbours_across_the_antimeridian_and_near_the_poles() {
+        let features = Arc::new(vec![
+            feature("east", 0, 1_799_990_000),
+            feature("west", 0, -1_799_990_000),
+            feature("far", 0, 0),
+            feature("pole", 899_990_000, 0),
+            feature("pole, other side", 899_990_000, 1_790_000_000),
+        ]);
+        let index = SpatialIndex::new(features);
+
+        let names = |nearby: Vec<Nearby<'_>>| nearby.iter().map(|nearby| nearby.feature.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(index.nearest(&Point { latitude: 0, longitude: -1_799_999_999 }, 2)), ["west", "east"]);
+        assert_eq!(names(index.within(&Point { latitude: 900_000_000, longitude: 0 }, 10_000)), ["pole", "pole, other side"]);
+    }
+
+    #[test]
+    fn skips_features_without_a_location() {
+        let features = Arc::new(vec![
+            Feature { name: "nowhere".to_string(), location: None },
+            feature("somewhere", 10, 10),
+        ]);
+        let index = SpatialIndex::new(features);
+
+        let nearest = index.nearest(&Point::default(), 5);
+        assert_eq!(nearest.len(), 1);
+        assert_eq!(nearest[0].index, 1);
+        assert!(index.nearest(&Point::default(), 0).is_empty());
+        assert!(index.within(&Point { latitude: 500_000_000, longitude: 0 }, 1_000).is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+// `GET /events/features?lo=<lat>,<lng>&hi=<lat>,<lng>` as Server-Sent Events.
+//
+// The features inside the rectangle are sent first, like ListFeatures does, each as a `feature`
+// event. A `listed` event marks the end of the listing, after which the connection stays open and
+// `added` and `removed` events follow whenever the feature set changes.
+//
+// Listing events have the id `<version>:<index>` and change events the id `<version>`, so that a
+// client reconnecting with `Last-Event-ID` continues where it left off: in the middle of the
+// listing if the feature set hasn't changed since, or with the changes it missed otherwise. If
+// neither is possible the listing starts over.
+#![allow(dead_code)]
+
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header;
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::sync::broadcast::RecvError;
+
+use crate::error_pages;
+use crate::feature_store::{Change, ChangeKind, FeatureStore};
+use crate::geo::in_range;
+use crate::route_guide::{Feature, Point, Rectangle};
+
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const RETRY_MILLISECONDS: u32 = 3000;
+
+
+enum Resume {
+    /// Send the whole listing.
+    FromStart,
+    /// Continue the listing of `version` after `index`.
+    Listing { version: u64, index: usize },
+    /// The listing is done; send the changes after `version`.
+    Changes { version: u64 },
+}
+
+impl Resume {
+    fn parse(last_event_id: Option<&str>) -> Resume {
+        let id = match last_event_id {
+            Some(id) => id.trim(),
+            None => return Resume::FromStart,
+        };
+
+        let mut parts = id.splitn(2, ':');
+        let version = parts.next().and_then(|version| version.parse().ok());
+        let index = parts.next().map(|index| index.parse().ok());
+
+        match (version, index) {
+            (Some(version), None) => Resume::Changes { version },
+            (Some(version), Some(Some(index))) => Resume::Listing { version, index },
+            _ => Resume::FromStart,
+        }
+    }
+}
+
+
+enum Event {
+    Changed(Result<Change, RecvError>),
+    KeepAlive,
+}
+
+
+pub fn feature_events(store: Arc<FeatureStore>, request: &Request<Body>) -> Response<Body> {
+    let rectangle = match parse_rectangle(request.uri().query().unwrap_or("")) {
+        Some(rectangle) => rectangle,
+        None => return error_pages::hyper_response(StatusCode::BAD_REQUEST, request),
+    };
+
+    let last_event_id = request.headers().get("last-event-id").and_then(|value| value.to_str().ok());
+    let resume = Resume::parse(last_event_id);
+
+    let stream = async_stream::stream! {
+        yield Ok::<_, Infallible>(format!("retry: {}\n\n", RETRY_MILLISECONDS));
+
+        // Subscribe before looking at the snapshot, so no change falls in between.
+        let mut changes = store.subscribe();
+        let (version, features) = store.versioned_snapshot();
+
+        let (listing_from, mut seen_version) = match resume {
+            Resume::Listing { version: resumed, index } if resumed == version => (Some(index + 1), version),
+            Resume::Changes { version: resumed } => match store.changes_since(resumed) {
+                Some(missed) => {
+                    for change in missed.iter().filter(|change| change.version <= version) {
+                        if let Some(event) = change_event(change, &rectangle) {
+                            yield Ok(event);
+                        }
+                    }
+                    (None, version)
+                },
+                None => (Some(0), version),
+            },
+            _ => (Some(0), version),
+        };
+
+        if let Some(start) = listing_from {
+            for (index, feature) in features.iter().enumerate().skip(start) {
+                if feature.location.as_ref().is_some_and(|location| in_range(location, &rectangle)) {
+                    yield Ok(event("feature", Some(&format!("{}:{}", version, index)), &feature_json(feature)));
+                }
+            }
+            yield Ok(event("listed", Some(&version.to_string()), "{}"));
+        }
+
+        let mut keep_alive = tokio::time::interval(KEEP_ALIVE_INTERVAL);
+        loop {
+            let next = tokio::select! {
+                change = changes.recv() => Event::Changed(change),
+                _ = keep_alive.tick() => Event::KeepAlive,
+            };
+
+            match next {
+                Event::Changed(Ok(change)) => {
+                    if change.version <= seen_version {
+                        continue;
+                    }
+                    seen_version = change.version;
+                    if let Some(event) = change_event(&change, &rectangle) {
+                        yield Ok(event);
+                    }
+                },
+                // We missed changes. Ending the stream makes the client reconnect with the last
+                // version it has seen, and catch up from the log.
+                Event::Changed(Err(RecvError::Lagged(_))) | Event::Changed(Err(RecvError::Closed)) => break,
+                Event::KeepAlive => yield Ok(String::from(": keep-alive\n\n")),
+            }
+        }
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Body::wrap_stream(stream))
+        .unwrap()
+}
+
+
+fn change_event(change: &Change, rectangle: &Rectangle) -> Option<String> {
+    let location = change.feature.location.as_ref()?;
+    if !in_range(location, rectangle) {
+        return None;
+    }
+
+    let name = match change.kind {
+        ChangeKind::Added   => "added",
+        ChangeKind::Removed => "removed",
+    };
+    Some(event(name, Some(&change.version.to_string()), &feature_json(&change.feature)))
+}
+
+fn event(name: &str, id: Option<&str>, data: &str) -> String {
+    let mut event = format!("event: {}\n", name);
+    if let Some(id) = id {
+        event.push_str(&format!("id: {}\n", id));
+    }
+    // Every line of the payload needs its own `data:` prefix.
+    for line in data.lines() {
+        event.push_str(&format!("data: {}\n", line));
+    }
+    event.push('\n');
+    event
+}
+
+fn feature_json(feature: &Feature) -> String {
+    let location = feature.location.clone().unwrap_or_default();
+    serde_json::json!({
+        "name": feature.name,
+        "location": {
+            "latitude": location.latitude,
+            "longitude": location.longitude,
+        },
+    }).to_string()
+}
+
+/// Parses `lo=<lat>,<lng>&hi=<lat>,<lng>`, with coordinates in E7 form.
+fn parse_rectangle(query: &str) -> Option<Rectangle> {
+    let mut lo = None;
+    let mut hi = None;
+
+    for pair in query.split('&') {
+        let mut parts = pair.splitn(2, '=');
+        let (name, value) = (parts.next()?, parts.next().unwrap_or(""));
+        // Browsers escape the comma.
+        let value = value.replace("%2C", ",").replace("%2c", ",");
+        match name {
+            "lo" => lo = Some(parse_point(&value)?),
+            "hi" => hi = Some(parse_point(&value)?),
+            _ => {},
+        }
+    }
+
+    Some(Rectangle { lo: Some(lo?), hi: Some(hi?) })
+}
+
+fn parse_point(value: &str) -> Option<Point> {
+    let mut parts = value.splitn(2, ',');
+    let latitude = parts.next()?.trim().parse().ok()?;
+    let longitude = parts.next()?.trim().parse().ok()?;
+    Some(Point { latitude, longitude })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(latitude: i32, longitude: i32) -> Option<Point> {
+        Some(Point { latitude, longitude })
+    }
+
+    #[test]
+    fn resumes_from_the_last_event_id() {
+        assert!(matches!(Resume::parse(None), Resume::FromStart));
+        assert!(matches!(Resume::parse(Some("7")), Resume::Changes { version: 7 }));
+        assert!(matches!(Resume::parse(Some(" 7 ")), Resume::Changes { version: 7 }));
+        assert!(matches!(Resume::parse(Some("7:12")), Resume::Listing { version: 7, index: 12 }));
+    }
+
+    #[test]
+    fn starts_over_on_malformed_ids() {
+        for id in &["", "x", "-1", "7:", ":12", "7:x", "7:-1", "7:1:2", "18446744073709551616", "7;12"] {
+            assert!(matches!(Resume::parse(Some(id)), Resume::FromStart), "{:?} was understood", id);
+        }
+    }
+
+    #[test]
+    fn parses_rectangles() {
+        let rectangle = parse_rectangle("lo=400000000,-750000000&hi=420000000,-730000000").unwrap();
+        assert_eq!((rectangle.lo, rectangle.hi), (point(400_000_000, -750_000_000), point(420_000_000, -730_000_000)));
+
+        // In any order, with escaped commas and other parameters.
+        let rectangle = parse_rectangle("hi=2%2C3&lang=en&lo=0%2c1").unwrap();
+        assert_eq!((rectangle.lo, rectangle.hi), (point(0, 1), point(2, 3)));
+        let rectangle = parse_rectangle("lo= 1 , 2 &hi=3,4").unwrap();
+        assert_eq!(rectangle.lo, point(1, 2));
+    }
+
+    #[test]
+    fn refuses_malformed_rectangles() {
+        for query in &["", "lo=1,2", "hi=1,2", "lo=1,2&hi=3", "lo=1,2&hi=", "lo&hi=3,4", "lo=a,b&hi=3,4", "lo=1;2&hi=3,4", "lo=1,2&hi=3,4000000000"] {
+            assert!(parse_rectangle(query).is_none(), "{:?} was parsed", query);
+        }
+    }
+}
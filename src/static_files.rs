@@ -0,0 +1,536 @@
+// A small static file service for hyper.
+//
+// Files are looked up relative to a root directory. Directories are served through their index
+// file, `..` segments and symlinks pointing outside the root are rejected, and the usual caching
+// headers (ETag, Last-Modified, Cache-Control) are set. `Range` requests and precompressed `.gz`
+// variants are supported, and the file is always streamed instead of being read into memory.
+#![allow(dead_code)]
+
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::TryStreamExt as _;
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use tokio::fs::File;
+use tokio::io::AsyncReadExt as _;
+use tokio_util::codec::{BytesCodec, FramedRead};
+
+use crate::error_pages;
+
+
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+    max_age: u32,
+}
+
+impl StaticFiles {
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index_files: vec![String::from("index.html")],
+            max_age: 3600,
+        }
+    }
+
+    /// The files looked for, in order, when a directory is requested.
+    pub fn index_files(mut self, names: &[&str]) -> StaticFiles {
+        self.index_files = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    /// Value of `max-age` in the `Cache-Control` header, in seconds.
+    pub fn max_age(mut self, seconds: u32) -> StaticFiles {
+        self.max_age = seconds;
+        self
+    }
+
+    pub async fn serve(&self, request: &Request<Body>) -> Response<Body> {
+        match *request.method() {
+            Method::GET | Method::HEAD => {},
+            _ => {
+                let mut response = error_pages::hyper_response(StatusCode::METHOD_NOT_ALLOWED, request);
+                response.headers_mut().insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+                return response;
+            }
+        }
+
+        let uri_path = request.uri().path();
+        let path = match self.resolve(uri_path).await {
+            Some(path) => path,
+            None => return error_pages::hyper_response(StatusCode::NOT_FOUND, request),
+        };
+
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return error_pages::hyper_response(StatusCode::NOT_FOUND, request),
+        };
+
+        // Directories must be requested with a trailing slash, otherwise relative links in the
+        // index file would resolve against the parent directory.
+        let path = if metadata.is_dir() {
+            if !uri_path.ends_with('/') {
+                return redirect(&format!("{}/", uri_path));
+            }
+            match self.find_index(&path).await {
+                Some(index) => index,
+                None => return error_pages::hyper_response(StatusCode::NOT_FOUND, request),
+            }
+        } else {
+            path
+        };
+
+        match self.serve_file(request, &path).await {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::error!(path = %path.display(), error = %e, "failed to serve file");
+                error_pages::hyper_response(StatusCode::INTERNAL_SERVER_ERROR, request)
+            }
+        }
+    }
+
+    /// Maps a request path to a path on disk, or `None` if it would escape the root.
+    async fn resolve(&self, uri_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(uri_path)?;
+
+        let mut path = self.root.clone();
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                _ if segment.contains('\\') || segment.contains('\0') => return None,
+                _ => path.push(segment),
+            }
+        }
+
+        // A symlink inside the root could still point outside of it.
+        let root = tokio::fs::canonicalize(&self.root).await.ok()?;
+        match tokio::fs::canonicalize(&path).await {
+            Ok(canonical) if canonical.starts_with(&root) => Some(canonical),
+            Ok(_) => None,
+            // Leave missing files for the caller to turn into a 404.
+            Err(_) => Some(path),
+        }
+    }
+
+    async fn find_index(&self, directory: &Path) -> Option<PathBuf> {
+        for name in &self.index_files {
+            let candidate = directory.join(name);
+            if let Ok(metadata) = tokio::fs::metadata(&candidate).await {
+                if metadata.is_file() {
+                    return Some(candidate);
+                }
+            }
+        }
+        None
+    }
+
+    async fn serve_file(&self, request: &Request<Body>, path: &Path) -> std::io::Result<Response<Body>> {
+        let headers = request.headers();
+        let content_type = mime_type(path);
+
+        // Prefer a precompressed sibling (`style.css.gz`) if the client accepts gzip.
+        let gzip_path = PathBuf::from(format!("{}.gz", path.display()));
+        let gzip_metadata = tokio::fs::metadata(&gzip_path).await.ok().filter(|m| m.is_file());
+        let has_variants = gzip_metadata.is_some();
+
+        let (path, metadata, encoding) = match gzip_metadata {
+            Some(metadata) if accepts_gzip(headers) => (gzip_path.as_path(), metadata, Some("gzip")),
+            _ => (path, tokio::fs::metadata(path).await?, None),
+        };
+
+        let length = metadata.len();
+        let modified = metadata.modified().ok();
+        let etag = entity_tag(length, modified, encoding);
+
+        let mut builder = Response::builder()
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::ETAG, etag.as_str())
+            .header(header::CACHE_CONTROL, format!("public, max-age={}", self.max_age));
+        if let Some(modified) = modified {
+            builder = builder.header(header::LAST_MODIFIED, httpdate::fmt_http_date(modified));
+        }
+        if let Some(encoding) = encoding {
+            builder = builder.header(header::CONTENT_ENCODING, encoding);
+        }
+        if has_variants {
+            builder = builder.header(header::VARY, "Accept-Encoding");
+        }
+
+        if is_not_modified(headers, &etag, modified) {
+            return Ok(builder.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap());
+        }
+
+        let range = match headers.get(header::RANGE) {
+            Some(range) if if_range_matches(headers, &etag, modified) => parse_range(range, length),
+            _ => Ok(None),
+        };
+
+        let (status, start, count) = match range {
+            Ok(Some((start, end))) => {
+                builder = builder.header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, length));
+                (StatusCode::PARTIAL_CONTENT, start, end - start + 1)
+            },
+            Ok(None) => (StatusCode::OK, 0, length),
+            Err(()) => {
+                let response = builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(header::CONTENT_RANGE, format!("bytes */{}", length))
+                    .body(Body::empty())
+                    .unwrap();
+                return Ok(response);
+            },
+        };
+
+        builder = builder.status(status).header(header::CONTENT_LENGTH, count);
+
+        if request.method() == Method::HEAD {
+            return Ok(builder.body(Body::empty()).unwrap());
+        }
+
+        let mut file = File::open(path).await?;
+        if start > 0 {
+            file.seek(SeekFrom::Start(start)).await?;
+        }
+
+        // Stream the file in chunks so large files never have to fit in memory.
+        let stream = FramedRead::new(file.take(count), BytesCodec::new())
+            .map_ok(|chunk| chunk.freeze());
+
+        Ok(builder.body(Body::wrap_stream(stream)).unwrap())
+    }
+}
+
+
+fn redirect(location: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css")   => "text/css; charset=utf-8",
+        Some("js")    => "application/javascript; charset=utf-8",
+        Some("json")  => "application/json",
+        Some("txt")   => "text/plain; charset=utf-8",
+        Some("xml")   => "application/xml",
+        Some("svg")   => "image/svg+xml",
+        Some("png")   => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif")   => "image/gif",
+        Some("webp")  => "image/webp",
+        Some("ico")   => "image/x-icon",
+        Some("woff")  => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("wasm")  => "application/wasm",
+        Some("pdf")   => "application/pdf",
+        Some("mp4")   => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let accept_encoding = match headers.get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()) {
+        Some(value) => value,
+        None => return false,
+    };
+
+    accept_encoding.split(',').any(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let rejected = parts.any(|parameter| {
+            parameter
+                .strip_prefix("q=")
+                .and_then(|q| q.parse::<f32>().ok())
+                .is_some_and(|q| q == 0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !rejected
+    })
+}
+
+fn entity_tag(length: u64, modified: Option<SystemTime>, encoding: Option<&str>) -> String {
+    let seconds = modified
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs());
+
+    match encoding {
+        Some(encoding) => format!("\"{:x}-{:x}-{}\"", length, seconds, encoding),
+        None => format!("\"{:x}-{:x}\"", length, seconds),
+    }
+}
+
+fn is_not_modified(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since when both are present.
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == "*" || tag == etag);
+    }
+
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok());
+
+    match (since, modified) {
+        (Some(since), Some(modified)) => truncate_to_seconds(modified) <= since,
+        _ => false,
+    }
+}
+
+fn if_range_matches(headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    let if_range = match headers.get(header::IF_RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) => value,
+        None => return true,
+    };
+
+    if if_range.starts_with('"') {
+        return if_range == etag;
+    }
+
+    match (httpdate::parse_http_date(if_range), modified) {
+        (Ok(date), Some(modified)) => truncate_to_seconds(modified) == date,
+        _ => false,
+    }
+}
+
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => UNIX_EPOCH + std::time::Duration::from_secs(duration.as_secs()),
+        Err(_) => time,
+    }
+}
+
+/// Parses a single `bytes=` range into inclusive `(start, end)` offsets.
+///
+/// Returns `Ok(None)` for ranges we don't handle (other units, multiple ranges) or that aren't valid
+/// syntax (`bytes=5-3`), which means the whole file is sent, and `Err(())` if the range is valid but
+/// can't be satisfied.
+fn parse_range(value: &HeaderValue, length: u64) -> Result<Option<(u64, u64)>, ()> {
+    let value = match value.to_str() {
+        Ok(value) => value.trim(),
+        Err(_) => return Ok(None),
+    };
+
+    let spec = match value.strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+
+    let mut parts = spec.splitn(2, '-');
+    let start = parts.next().unwrap_or("").trim();
+    let end = match parts.next() {
+        Some(end) => end.trim(),
+        None => return Ok(None),
+    };
+
+    // Only digits; `parse` would also take a sign.
+    let number = |digits: &str| Some(digits).filter(|digits| digits.bytes().all(|b| b.is_ascii_digit()))?.parse::<u64>().ok();
+    let (start, end) = match (number(start), number(end)) {
+        // `bytes=-500` is the last 500 bytes.
+        (None, Some(suffix)) if start.is_empty() => {
+            if suffix == 0 {
+                return Err(());
+            }
+            (length.saturating_sub(suffix), length.saturating_sub(1))
+        },
+        // `bytes=500-` is everything from byte 500.
+        (Some(start), None) if end.is_empty() => (start, length.saturating_sub(1)),
+        (Some(start), Some(end)) if start <= end => (start, end.min(length.saturating_sub(1))),
+        // Not a range at all, so it is ignored (RFC 7233, section 3.1).
+        _ => return Ok(None),
+    };
+
+    if length == 0 || start >= length || start > end {
+        return Err(());
+    }
+
+    Ok(Some((start, end)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// A fresh directory with `public/index.html`, `public/notes.txt` and `secret.txt` next to
+    /// `public`, which is the root.
+    fn root(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("static-files-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("public/docs")).unwrap();
+        fs::write(directory.join("public/index.html"), "<h1>index</h1>").unwrap();
+        fs::write(directory.join("public/notes.txt"), "0123456789").unwrap();
+        fs::write(directory.join("secret.txt"), "secret").unwrap();
+        directory.join("public")
+    }
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        pairs.iter().map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap())).collect()
+    }
+
+    fn range(value: &str, length: u64) -> Result<Option<(u64, u64)>, ()> {
+        parse_range(&HeaderValue::from_str(value).unwrap(), length)
+    }
+
+    async fn get(files: &StaticFiles, path: &str, pairs: &[(header::HeaderName, &str)]) -> Response<Body> {
+        let mut request = Request::get(path).body(Body::empty()).unwrap();
+        *request.headers_mut() = headers(pairs);
+        files.serve(&request).await
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("/a%20b/%2e%2E").as_deref(), Some("/a b/.."));
+        assert_eq!(percent_decode("/caf%C3%A9").as_deref(), Some("/café"));
+        assert_eq!(percent_decode("/100%"), None);
+        assert_eq!(percent_decode("/%zz"), None);
+        assert_eq!(percent_decode("/%ff"), None);
+    }
+
+    #[tokio::test]
+    async fn refuses_paths_out_of_the_root() {
+        let root = root("traversal");
+        let files = StaticFiles::new(&root);
+
+        assert!(files.resolve("/notes.txt").await.is_some());
+        assert!(files.resolve("/./docs/../notes.txt").await.is_none());
+        for path in &["/../secret.txt", "/%2e%2e/secret.txt", "/%2E%2E%2Fsecret.txt", "/..%5Csecret.txt", "/notes.txt%00"] {
+            assert_eq!(files.resolve(path).await, None, "{} was resolved", path);
+            assert_eq!(get(&files, path, &[]).await.status(), StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_symlinks_out_of_the_root() {
+        let root = root("symlink");
+        std::os::unix::fs::symlink(root.join("../secret.txt"), root.join("escape.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("notes.txt"), root.join("inside.txt")).unwrap();
+        let files = StaticFiles::new(&root);
+
+        assert_eq!(files.resolve("/escape.txt").await, None);
+        assert_eq!(get(&files, "/escape.txt", &[]).await.status(), StatusCode::NOT_FOUND);
+        assert!(files.resolve("/inside.txt").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn serves_files_directories_and_ranges() {
+        let files = StaticFiles::new(root("serve"));
+
+        let response = get(&files, "/notes.txt", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain; charset=utf-8");
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "0123456789");
+
+        let response = get(&files, "/notes.txt", &[(header::RANGE, "bytes=2-4")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(hyper::body::to_bytes(response.into_body()).await.unwrap(), "234");
+
+        let response = get(&files, "/notes.txt", &[(header::RANGE, "bytes=5-3")]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get(&files, "/notes.txt", &[(header::RANGE, "bytes=10-")]).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+
+        assert_eq!(get(&files, "/", &[]).await.status(), StatusCode::OK);
+        let response = get(&files, "/docs", &[]).await;
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()[header::LOCATION], "/docs/");
+        assert_eq!(get(&files, "/docs/", &[]).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(range("bytes=0-9", 100), Ok(Some((0, 9))));
+        assert_eq!(range("bytes=90-200", 100), Ok(Some((90, 99))));
+        // Suffix and open-ended ranges.
+        assert_eq!(range("bytes=-10", 100), Ok(Some((90, 99))));
+        assert_eq!(range("bytes=-500", 100), Ok(Some((0, 99))));
+        assert_eq!(range("bytes=95-", 100), Ok(Some((95, 99))));
+        // Past the end of the file, or nothing at all.
+        assert_eq!(range("bytes=100-", 100), Err(()));
+        assert_eq!(range("bytes=100-200", 100), Err(()));
+        assert_eq!(range("bytes=-0", 100), Err(()));
+        assert_eq!(range("bytes=0-0", 0), Err(()));
+        // Ignored, so the whole file is sent.
+        assert_eq!(range("bytes=0-1,5-6", 100), Ok(None));
+        assert_eq!(range("items=0-1", 100), Ok(None));
+        assert_eq!(range("bytes=5-3", 100), Ok(None));
+        assert_eq!(range("bytes=-", 100), Ok(None));
+        assert_eq!(range("bytes=a-b", 100), Ok(None));
+        assert_eq!(range("bytes=+1-2", 100), Ok(None));
+        assert_eq!(range("bytes=5", 100), Ok(None));
+    }
+
+    #[test]
+    fn answers_conditional_requests() {
+        let modified = UNIX_EPOCH + std::time::Duration::from_millis(1_600_000_000_500);
+        let etag = entity_tag(10, Some(modified), None);
+        let date = |time: SystemTime| httpdate::fmt_http_date(time);
+
+        assert!(is_not_modified(&headers(&[(header::IF_NONE_MATCH, &etag)]), &etag, Some(modified)));
+        assert!(is_not_modified(&headers(&[(header::IF_NONE_MATCH, &format!("\"other\", W/{}", etag))]), &etag, Some(modified)));
+        assert!(is_not_modified(&headers(&[(header::IF_NONE_MATCH, "*")]), &etag, Some(modified)));
+        assert!(!is_not_modified(&headers(&[(header::IF_NONE_MATCH, "\"other\"")]), &etag, Some(modified)));
+        assert!(!is_not_modified(&HeaderMap::new(), &etag, Some(modified)));
+
+        // Dates only have whole seconds.
+        let since = |time| headers(&[(header::IF_MODIFIED_SINCE, &date(time))]);
+        assert!(is_not_modified(&since(modified), &etag, Some(modified)));
+        assert!(!is_not_modified(&since(modified - std::time::Duration::from_secs(1)), &etag, Some(modified)));
+        assert!(!is_not_modified(&since(modified), &etag, None));
+        // If-None-Match wins over If-Modified-Since.
+        let both = headers(&[(header::IF_NONE_MATCH, "\"other\""), (header::IF_MODIFIED_SINCE, &date(modified))]);
+        assert!(!is_not_modified(&both, &etag, Some(modified)));
+    }
+
+    #[test]
+    fn accepts_gzip_unless_refused() {
+        let accepts = |value: &str| accepts_gzip(&headers(&[(header::ACCEPT_ENCODING, value)]));
+
+        assert!(accepts("gzip"));
+        assert!(accepts("br, GZIP;q=0.5"));
+        assert!(accepts("*"));
+        assert!(!accepts("br, deflate"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("*; q=0"));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+}
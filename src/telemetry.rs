@@ -0,0 +1,139 @@
+// Logging and tracing.
+//
+// Everything logs through `tracing`, to stderr, filtered by `RUST_LOG` (`info` by default). The
+// gRPC server and client also turn their spans into OpenTelemetry spans, so that a trace can cross
+// from one to the other in the W3C `traceparent` header: the client adds it to the request
+// metadata, and the server continues the trace from it.
+//
+// Finished spans are exported when asked to:
+//
+//     OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4317   to an OTLP collector
+//     TRACE_JSON_FILE=traces.json                          as JSON lines, for events and closed spans
+#![allow(dead_code)]
+
+use std::env;
+use std::error::Error;
+use std::path::Path;
+
+use hyper::HeaderMap;
+use opentelemetry::propagation::{Extractor, Injector};
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace as sdktrace, Resource};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::{global, Context, KeyValue};
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::fmt::{self, format::FmtSpan};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Registry};
+
+
+/// Keeps the exporters running. Hold on to it until the end of `main`; dropping it flushes the
+/// spans that haven't been exported yet.
+pub struct Telemetry {
+    _provider: Option<sdktrace::TracerProvider>,
+    _otlp: Option<opentelemetry_otlp::Uninstall>,
+    _json_file: Option<tracing_appender::non_blocking::WorkerGuard>,
+}
+
+
+/// Logs to stderr only. For the servers that don't take part in traces.
+pub fn init_logging() {
+    let _ = tracing_subscriber::fmt().with_env_filter(filter()).try_init();
+}
+
+/// Logs to stderr and records spans for traces, exported as configured in the environment.
+pub fn init(service_name: &'static str) -> Result<Telemetry, Box<dyn Error>> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let resource = Resource::new(vec![KeyValue::new("service.name", service_name)]);
+    let (tracer, provider, otlp) = match env::var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        Ok(endpoint) => {
+            let (tracer, uninstall) = opentelemetry_otlp::new_pipeline()
+                .with_endpoint(endpoint)
+                .with_trace_config(sdktrace::config().with_resource(resource))
+                .install()?;
+            (tracer, None, Some(uninstall))
+        },
+        // Spans still need ids when nothing exports them, or there is no trace context to pass on.
+        Err(_) => {
+            let provider = sdktrace::TracerProvider::builder()
+                .with_config(sdktrace::config().with_resource(resource))
+                .build();
+            (provider.get_tracer(service_name, None), Some(provider), None)
+        },
+    };
+
+    let (json_layer, json_file) = match env::var_os("TRACE_JSON_FILE") {
+        Some(path) => {
+            let path = Path::new(&path);
+            let file_name = path.file_name().ok_or("TRACE_JSON_FILE has no file name")?;
+            let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+
+            let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::never(directory, file_name));
+            let layer = fmt::layer()
+                .json()
+                .with_span_list(true)
+                .with_span_events(FmtSpan::CLOSE)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        },
+        None => (None, None),
+    };
+
+    Registry::default()
+        .with(filter())
+        .with(fmt::layer().with_writer(std::io::stderr))
+        .with(json_layer)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+
+    Ok(Telemetry { _provider: provider, _otlp: otlp, _json_file: json_file })
+}
+
+fn filter() -> EnvFilter {
+    EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))
+}
+
+
+/// Adds the trace context of the current span to outgoing gRPC metadata.
+pub fn inject_context(metadata: &mut MetadataMap) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut MetadataInjector(metadata))
+    });
+}
+
+/// Makes `span` part of the trace in the request headers, if they carry one. gRPC metadata are
+/// headers too.
+pub fn continue_trace(span: &Span, headers: &HeaderMap) {
+    let context: Context = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    });
+    span.set_parent(context);
+}
+
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (MetadataKey::from_bytes(key.as_bytes()), MetadataValue::from_str(&value)) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
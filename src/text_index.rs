@@ -0,0 +1,263 @@
+// An inverted index of feature names, for searching them by words.
+//
+// Names are split into lowercase words at anything that isn't a letter or a digit. A query matches
+// a feature when every word of the query matches a word of its name, either exactly, as a prefix
+// ("berk" finds "Berkshire"), or within a few typos (one for words of 4 to 7 letters, two for
+// longer ones). Matches are scored by how exact they are and by how rare the word is across the
+// database, summed over the words of the query; the best scores come first.
+#![allow(dead_code)]
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::route_guide::Feature;
+
+
+const EXACT_WEIGHT: f32 = 1.0;
+const PREFIX_WEIGHT: f32 = 0.6;
+const FUZZY_WEIGHT: f32 = 0.4;
+
+/// Prefixes shorter than this would match too much to be useful.
+const MIN_PREFIX: usize = 2;
+
+
+pub struct TextIndex {
+    features: Arc<Vec<Feature>>,
+    /// Each word, with the features whose name has it, in ascending order. Sorted, so that the
+    /// words with a prefix are a range.
+    terms: BTreeMap<String, Vec<usize>>,
+}
+
+/// A feature found by a search, with its relevance.
+#[derive(Debug, Clone, Copy)]
+pub struct Hit<'a> {
+    pub feature: &'a Feature,
+    pub score: f32,
+}
+
+impl TextIndex {
+    pub fn new(features: Arc<Vec<Feature>>) -> TextIndex {
+        let mut terms: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (index, feature) in features.iter().enumerate() {
+            let mut words = tokenize(&feature.name);
+            words.sort();
+            words.dedup();
+            for word in words {
+                terms.entry(word).or_default().push(index);
+            }
+        }
+
+        TextIndex { features, terms }
+    }
+
+    /// The features this index was built from.
+    pub fn features(&self) -> &Arc<Vec<Feature>> {
+        &self.features
+    }
+
+    /// The features whose name matches `query` and that pass `filter`, best first. Features that
+    /// score the same stay in database order.
+    pub fn search<F>(&self, query: &str, filter: F) -> Vec<Hit<'_>>
+    where
+        F: Fn(&Feature) -> bool,
+    {
+        let words = tokenize(query);
+        if words.is_empty() {
+            return Vec::new();
+        }
+
+        // The score of each feature, and how many words of the query it matched.
+        let mut scores: HashMap<usize, (f32, usize)> = HashMap::new();
+        for word in &words {
+            // A feature gets the best of the matches of a word, not all of them.
+            let mut best: HashMap<usize, f32> = HashMap::new();
+            for (postings, weight) in self.matches(word) {
+                let score = weight * self.rarity(postings.len());
+                for &feature in postings {
+                    let entry = best.entry(feature).or_insert(0.0);
+                    *entry = entry.max(score);
+                }
+            }
+            for (feature, score) in best {
+                let entry = scores.entry(feature).or_insert((0.0, 0));
+                entry.0 += score;
+                entry.1 += 1;
+            }
+        }
+
+        let mut hits: Vec<(usize, f32)> = scores
+            .into_iter()
+            .filter(|&(feature, (_, matched))| matched == words.len() && filter(&self.features[feature]))
+            .map(|(feature, (score, _))| (feature, score))
+            .collect();
+        hits.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+
+        hits.into_iter().map(|(feature, score)| Hit { feature: &self.features[feature], score }).collect()
+    }
+
+    /// The postings of the words that `word` matches, with the weight of the match.
+    fn matches(&self, word: &str) -> Vec<(&[usize], f32)> {
+        let mut matches = Vec::new();
+
+        if let Some(postings) = self.terms.get(word) {
+            matches.push((&postings[..], EXACT_WEIGHT));
+        }
+
+        if word.chars().count() >= MIN_PREFIX {
+            let longer = self.terms
+                .range::<str, _>((Bound::Excluded(word), Bound::Unbounded))
+                .take_while(|(term, _)| term.starts_with(word));
+            for (_, postings) in longer {
+                matches.push((&postings[..], PREFIX_WEIGHT));
+            }
+        }
+
+        // The vocabulary is the words of the names, small enough to go through for the few words
+        // of a query. Only words of about the same length can be close enough.
+        let max_edits = max_edits(word);
+        if max_edits > 0 {
+            let length = word.chars().count();
+            for (term, postings) in &self.terms {
+                let term_length = term.chars().count();
+                if term == word || term.starts_with(word) || term_length + max_edits < length || term_length > length + max_edits {
+                    continue;
+                }
+                if let Some(edits) = edit_distance(word, term, max_edits) {
+                    matches.push((&postings[..], FUZZY_WEIGHT / edits as f32));
+                }
+            }
+        }
+
+        matches
+    }
+
+    /// How much a match on a word that `count` features have is worth: the rarer, the more.
+    fn rarity(&self, count: usize) -> f32 {
+        (1.0 + self.features.len() as f32 / count.max(1) as f32).ln()
+    }
+}
+
+
+/// The lowercase words of `text`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn max_edits(word: &str) -> usize {
+    match word.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+/// The Levenshtein distance between `a` and `b`, if it is at most `limit`.
+fn edit_distance(a: &str, b: &str, limit: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        current[0] = i;
+        for j in 1..=b.len() {
+            let substitution = previous[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            current[j] = substitution.min(previous[j] + 1).min(current[j - 1] + 1);
+        }
+        // Every later row is at least the smallest of this one.
+        if current.iter().min().is_none_or(|&least| least > limit) {
+            return None;
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    Some(previous[b.len()]).filter(|&distance| distance <= limit)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::route_guide::Point;
+
+    fn index(names: &[&str]) -> TextIndex {
+        let features = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| Feature { name: name.to_string(), location: Some(Point { latitude: i as i32, longitude: 0 }) })
+            .collect();
+        TextIndex::new(Arc::new(features))
+    }
+
+    fn search(index: &TextIndex, query: &str) -> Vec<String> {
+        index.search(query, |_| true).iter().map(|hit| hit.feature.name.clone()).collect()
+    }
+
+    #[test]
+    fn splits_names_into_lowercase_words() {
+        assert_eq!(tokenize("Berkshire Valley-Management Area Trail, Jefferson 2"), [
+            "berkshire", "valley", "management", "area", "trail", "jefferson", "2",
+        ]);
+        assert!(tokenize(" -- ").is_empty());
+    }
+
+    #[test]
+    fn matches_exactly_by_prefix_and_with_typos() {
+        let index = index(&["Berkshire Valley", "Bear Mountain", "Mount Tabor", "Hopatcong"]);
+
+        assert_eq!(search(&index, "berkshire"), ["Berkshire Valley"]);
+        assert_eq!(search(&index, "BERK"), ["Berkshire Valley"]);
+        assert_eq!(search(&index, "valey"), ["Berkshire Valley"]);
+        assert_eq!(search(&index, "hopatkong"), ["Hopatcong"]);
+        // Too short for typos or a prefix.
+        assert!(search(&index, "bar").is_empty());
+        assert!(search(&index, "b").is_empty());
+        assert!(search(&index, "").is_empty());
+    }
+
+    #[test]
+    fn needs_every_word_of_the_query() {
+        let index = index(&["Bear Mountain", "Mount Tabor", "Bear Creek"]);
+
+        assert_eq!(search(&index, "bear mount"), ["Bear Mountain"]);
+        assert!(search(&index, "bear tabor").is_empty());
+    }
+
+    #[test]
+    fn ranks_exact_and_rare_matches_first() {
+        // Exact before prefix, for words that are as rare.
+        let small = index(&["Mountainside", "Mount Tabor"]);
+        assert_eq!(search(&small, "mount"), ["Mount Tabor", "Mountainside"]);
+
+        // "hope" is rarer than "mount", so a name with it outweighs the typo in "mount".
+        let index = index(&["Mount Tabor", "Mount Hope", "Mount Arlington", "Hope Mounr"]);
+        assert_eq!(search(&index, "hope mounr"), ["Hope Mounr", "Mount Hope"]);
+        assert_eq!(search(&index, "mount"), ["Mount Tabor", "Mount Hope", "Mount Arlington", "Hope Mounr"]);
+
+        let hits = index.search("mount", |_| true);
+        assert!(hits.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert_eq!(hits[0].score, hits[2].score);
+    }
+
+    #[test]
+    fn leaves_out_features_that_fail_the_filter() {
+        let index = index(&["Mount Tabor", "Mount Hope", "Mount Arlington"]);
+        let hits = index.search("mount", |feature| feature.location.as_ref().is_some_and(|point| point.latitude > 0));
+        let names: Vec<&str> = hits.iter().map(|hit| hit.feature.name.as_str()).collect();
+        assert_eq!(names, ["Mount Hope", "Mount Arlington"]);
+    }
+
+    #[test]
+    fn limits_the_edit_distance() {
+        assert_eq!(edit_distance("kitten", "sitting", 3), Some(3));
+        assert_eq!(edit_distance("kitten", "sitting", 2), None);
+        assert_eq!(edit_distance("", "abc", 3), Some(3));
+        assert_eq!(edit_distance("same", "same", 0), Some(0));
+    }
+}
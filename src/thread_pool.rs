@@ -0,0 +1,772 @@
+// A fixed-size thread pool for the blocking server.
+//
+// Jobs are queued in a `VecDeque` behind a mutex, and workers wait on a condition variable for
+// new jobs. Jobs always run outside of the lock and inside `catch_unwind`, so a panicking job
+// neither kills its worker nor poisons the queue for everyone else. Should a worker thread die
+// anyway, a sentinel notices it while unwinding and spawns a replacement.
+//
+// Besides fire-and-forget jobs (`execute`), jobs can return a value through a `JobHandle`
+// (`submit`), and `scope` lets jobs borrow from the caller's stack, which makes the pool usable for
+// CPU-bound work like computing distances for a batch of points, not only for handling sockets.
+#![allow(dead_code)]
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::num::NonZeroU32;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub enum Message {
+    NewJob(Job),
+    Terminate
+}
+
+
+/// What `execute` does when the queue is full.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RejectionPolicy {
+    /// Wait until a worker has taken a job off the queue.
+    Block,
+    /// Give the job back to the caller in an `ExecuteError::QueueFull`.
+    Reject,
+    /// Run the job on the calling thread.
+    CallerRuns,
+}
+
+pub enum ExecuteError<F> {
+    QueueFull(F),
+    ShutDown(F),
+}
+
+impl<F> ExecuteError<F> {
+    /// The job that wasn't executed.
+    pub fn into_inner(self) -> F {
+        match self {
+            ExecuteError::QueueFull(job) | ExecuteError::ShutDown(job) => job,
+        }
+    }
+
+    fn map<G>(self, f: impl FnOnce(F) -> G) -> ExecuteError<G> {
+        match self {
+            ExecuteError::QueueFull(job) => ExecuteError::QueueFull(f(job)),
+            ExecuteError::ShutDown(job)  => ExecuteError::ShutDown(f(job)),
+        }
+    }
+}
+
+impl<F> fmt::Debug for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::QueueFull(_) => write!(f, "QueueFull(..)"),
+            ExecuteError::ShutDown(_)  => write!(f, "ShutDown(..)"),
+        }
+    }
+}
+
+impl<F> fmt::Display for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::QueueFull(_) => write!(f, "the thread pool queue is full"),
+            ExecuteError::ShutDown(_)  => write!(f, "the thread pool is shut down"),
+        }
+    }
+}
+
+
+/// A snapshot of what a worker has been up to.
+#[derive(Debug, Clone)]
+pub struct WorkerStatus {
+    pub id: usize,
+    pub busy: bool,
+    pub jobs_run: u64,
+    pub jobs_panicked: u64,
+    pub busy_time: Duration,
+    pub restarts: u64,
+}
+
+#[derive(Default)]
+struct WorkerStats {
+    busy: AtomicBool,
+    jobs_run: AtomicU64,
+    jobs_panicked: AtomicU64,
+    busy_nanos: AtomicU64,
+    restarts: AtomicU64,
+}
+
+
+struct Queue {
+    messages: VecDeque<Message>,
+    shutting_down: bool,
+    live_workers: usize,
+    running_jobs: usize,
+}
+
+impl Queue {
+    fn is_idle(&self) -> bool {
+        self.running_jobs == 0 && !self.messages.iter().any(|message| matches!(message, Message::NewJob(_)))
+    }
+}
+
+struct Shared {
+    name: String,
+    capacity: Option<usize>,
+    policy: RejectionPolicy,
+    queue: Mutex<Queue>,
+    message_available: Condvar,
+    space_available: Condvar,
+    worker_exited: Condvar,
+    idle: Condvar,
+    stats: Vec<WorkerStats>,
+    threads: Mutex<Vec<Option<thread::JoinHandle<()>>>>,
+}
+
+/// Locks `mutex` even if a thread panicked while holding it. Nothing we guard can be left in an
+/// inconsistent state by a panic, since jobs never run while the lock is held.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+
+pub struct Builder {
+    size: usize,
+    name: String,
+    capacity: Option<usize>,
+    policy: RejectionPolicy,
+}
+
+impl Builder {
+    pub fn name(mut self, name: &str) -> Builder {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Bounds the number of queued (not yet running) jobs. Unbounded by default.
+    pub fn queue_capacity(mut self, capacity: usize) -> Builder {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    pub fn rejection_policy(mut self, policy: RejectionPolicy) -> Builder {
+        self.policy = policy;
+        self
+    }
+
+    pub fn build(self) -> ThreadPool {
+        let shared = Arc::new(Shared {
+            name: self.name,
+            capacity: self.capacity,
+            policy: self.policy,
+            queue: Mutex::new(Queue {
+                messages: VecDeque::new(),
+                shutting_down: false,
+                live_workers: self.size,
+                running_jobs: 0,
+            }),
+            message_available: Condvar::new(),
+            space_available: Condvar::new(),
+            worker_exited: Condvar::new(),
+            idle: Condvar::new(),
+            stats: (0..self.size).map(|_| WorkerStats::default()).collect(),
+            threads: Mutex::new((0..self.size).map(|_| None).collect()),
+        });
+
+        for id in 0..self.size {
+            Worker::spawn(shared.clone(), id);
+        }
+
+        ThreadPool { shared, size: self.size }
+    }
+}
+
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+    size: usize,
+}
+
+impl ThreadPool {
+    pub fn new(size: NonZeroU32) -> ThreadPool {
+        ThreadPool::builder(size).build()
+    }
+
+    pub fn builder(size: NonZeroU32) -> Builder {
+        Builder {
+            size: size.get() as usize,
+            name: String::from("worker"),
+            capacity: None,
+            policy: RejectionPolicy::Block,
+        }
+    }
+
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.push(f)
+    }
+
+    /// Like `execute`, but the returned handle gives access to the job's result, or to the panic
+    /// payload if it panicked.
+    pub fn submit<F, T>(&self, f: F) -> Result<JobHandle<T>, ExecuteError<()>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, handle) = with_handle(f);
+        self.push(job).map_err(|e| e.map(|_| ()))?;
+        Ok(handle)
+    }
+
+    /// Runs `f` with a `Scope` through which jobs may borrow anything that outlives the call.
+    /// Doesn't return until every job spawned on the scope has finished. If a job panicked, the
+    /// panic is resumed here once they all have.
+    pub fn scope<'pool, 'scope, F, R>(&'pool self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'pool, 'scope>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            pending: Arc::new((Mutex::new(0), Condvar::new())),
+            panic: Arc::new(Mutex::new(None)),
+            _marker: PhantomData,
+        };
+
+        // The jobs must be waited for even if `f` panics, since they may borrow from its caller.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+
+        match result {
+            Ok(value) => match lock(&scope.panic).take() {
+                Some(payload) => panic::resume_unwind(payload),
+                None => value,
+            },
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    /// Blocks until the queue is empty and no job is running.
+    pub fn wait_idle(&self) {
+        let mut queue = lock(&self.shared.queue);
+        while !queue.is_idle() {
+            queue = self.shared.idle.wait(queue).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    fn push<F>(&self, f: F) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let shared = &self.shared;
+        let mut queue = lock(&shared.queue);
+
+        loop {
+            if queue.shutting_down {
+                return Err(ExecuteError::ShutDown(f));
+            }
+
+            let full = shared.capacity.is_some_and(|capacity| queue.messages.len() >= capacity);
+            if !full {
+                break;
+            }
+
+            match shared.policy {
+                RejectionPolicy::Block => {
+                    queue = shared.space_available.wait(queue).unwrap_or_else(PoisonError::into_inner);
+                },
+                RejectionPolicy::Reject => return Err(ExecuteError::QueueFull(f)),
+                RejectionPolicy::CallerRuns => {
+                    drop(queue);
+                    f();
+                    return Ok(());
+                },
+            }
+        }
+
+        queue.messages.push_back(Message::NewJob(Box::new(f)));
+        shared.message_available.notify_one();
+        Ok(())
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of jobs waiting for a worker.
+    pub fn queued(&self) -> usize {
+        lock(&self.shared.queue)
+            .messages
+            .iter()
+            .filter(|message| matches!(message, Message::NewJob(_)))
+            .count()
+    }
+
+    pub fn stats(&self) -> Vec<WorkerStatus> {
+        self.shared.stats
+            .iter()
+            .enumerate()
+            .map(|(id, stats)| WorkerStatus {
+                id,
+                busy: stats.busy.load(Ordering::Relaxed),
+                jobs_run: stats.jobs_run.load(Ordering::Relaxed),
+                jobs_panicked: stats.jobs_panicked.load(Ordering::Relaxed),
+                busy_time: Duration::from_nanos(stats.busy_nanos.load(Ordering::Relaxed)),
+                restarts: stats.restarts.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Stops accepting jobs, lets the workers finish what is already queued and waits for them.
+    pub fn shutdown(self) {
+        self.terminate(None);
+    }
+
+    /// Like `shutdown`, but gives up waiting after `timeout`. Returns whether every worker exited
+    /// in time; workers that didn't are detached and finish on their own.
+    pub fn shutdown_timeout(self, timeout: Duration) -> bool {
+        self.terminate(Some(timeout))
+    }
+
+    fn terminate(&self, timeout: Option<Duration>) -> bool {
+        let shared = &self.shared;
+
+        {
+            let mut queue = lock(&shared.queue);
+            if !queue.shutting_down {
+                queue.shutting_down = true;
+                // Terminate messages go behind the queued jobs, so those still get to run.
+                for _ in 0..self.size {
+                    queue.messages.push_back(Message::Terminate);
+                }
+                shared.message_available.notify_all();
+                shared.space_available.notify_all();
+            }
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut queue = lock(&shared.queue);
+        while queue.live_workers > 0 {
+            queue = match deadline {
+                None => shared.worker_exited.wait(queue).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    shared.worker_exited
+                        .wait_timeout(queue, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                },
+            };
+        }
+        let finished = queue.live_workers == 0;
+        drop(queue);
+
+        for (id, thread) in lock(&shared.threads).iter_mut().enumerate() {
+            if !finished {
+                tracing::warn!(worker = id, "did not shut down in time; detaching it");
+                continue;
+            }
+            tracing::debug!(worker = id, "shutting down");
+            if let Some(thread) = thread.take() {
+                if thread.join().is_err() {
+                    tracing::error!(worker = id, "panicked while shutting down");
+                }
+            }
+        }
+
+        finished
+    }
+}
+
+/// Waits for every handle and collects the results in the same order.
+pub fn join_all<T, I>(handles: I) -> Vec<thread::Result<T>>
+where
+    I: IntoIterator<Item = JobHandle<T>>,
+{
+    handles.into_iter().map(JobHandle::join).collect()
+}
+
+/// Wraps `f` in a job that sends its result, or its panic payload, to the returned handle.
+fn with_handle<'a, F, T>(f: F) -> (impl FnOnce() + Send + 'a, JobHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'a,
+    T: Send + 'a,
+{
+    let (sender, receiver) = mpsc::channel();
+    let job = move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        // The handle may have been dropped, in which case nobody wants the result.
+        let _ = sender.send(result);
+    };
+    (job, JobHandle { receiver })
+}
+
+
+pub struct JobHandle<T> {
+    receiver: mpsc::Receiver<thread::Result<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// Waits for the job to finish. Returns `Err` with the panic payload if it panicked.
+    pub fn join(self) -> thread::Result<T> {
+        self.receiver
+            .recv()
+            .unwrap_or_else(|_| Err(Box::new("the job was dropped before it ran")))
+    }
+
+    /// Returns the result if the job has finished, or gives the handle back if it hasn't.
+    pub fn try_join(self) -> Result<thread::Result<T>, JobHandle<T>> {
+        match self.receiver.try_recv() {
+            Ok(result) => Ok(result),
+            Err(mpsc::TryRecvError::Empty) => Err(self),
+            Err(mpsc::TryRecvError::Disconnected) => Ok(Err(Box::new("the job was dropped before it ran"))),
+        }
+    }
+}
+
+
+pub struct Scope<'pool, 'scope> {
+    pool: &'pool ThreadPool,
+    pending: Arc<(Mutex<usize>, Condvar)>,
+    panic: Arc<Mutex<Option<Box<dyn Any + Send + 'static>>>>,
+    // Invariant in 'scope, so the borrow checker can't shrink it to fit a shorter-lived borrow.
+    _marker: PhantomData<&'scope mut &'scope ()>,
+}
+
+impl<'pool, 'scope> Scope<'pool, 'scope> {
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        let pending = self.pending.clone();
+        let panic = self.panic.clone();
+
+        *lock(&pending.0) += 1;
+
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                lock(&panic).get_or_insert(payload);
+            }
+            let (count, finished) = &*pending;
+            *lock(count) -= 1;
+            finished.notify_all();
+        });
+
+        // SAFETY: `ThreadPool::scope` doesn't return before `pending` is back to zero, i.e.
+        // before this job has run, so nothing it borrows can go away while it is queued.
+        let job: Job = unsafe { mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+
+        // A full or shut down pool can't refuse a scoped job, since `scope` would then wait for
+        // it forever. Run it here instead.
+        if let Err(e) = self.pool.push(job) {
+            (e.into_inner())();
+        }
+    }
+
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (job, handle) = with_handle(f);
+        self.execute(job);
+        handle
+    }
+
+    fn wait(&self) {
+        let (count, finished) = &*self.pending;
+        let mut count = lock(count);
+        while *count > 0 {
+            count = finished.wait(count).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // A no-op if `shutdown` or `shutdown_timeout` already ran.
+        if !lock(&self.shared.queue).shutting_down {
+            self.terminate(None);
+        }
+    }
+}
+
+
+struct Worker;
+
+impl Worker {
+    fn spawn(shared: Arc<Shared>, id: usize) {
+        let name = format!("{}-{}", shared.name, id);
+        let thread_shared = shared.clone();
+
+        let thread = thread::Builder::new()
+            .name(name)
+            .spawn(move || {
+                let sentinel = Sentinel { shared: thread_shared, id };
+                Worker::run(&sentinel.shared, id);
+            })
+            .expect("failed to spawn worker thread");
+
+        lock(&shared.threads)[id] = Some(thread);
+    }
+
+    fn run(shared: &Shared, id: usize) {
+        let stats = &shared.stats[id];
+
+        loop {
+            let message = {
+                let mut queue = lock(&shared.queue);
+                loop {
+                    if let Some(message) = queue.messages.pop_front() {
+                        if let Message::NewJob(_) = message {
+                            queue.running_jobs += 1;
+                        }
+                        break message;
+                    }
+                    queue = shared.message_available.wait(queue).unwrap_or_else(PoisonError::into_inner);
+                }
+            };
+            shared.space_available.notify_one();
+
+            match message {
+                Message::NewJob(job) => {
+                    tracing::trace!(worker = id, "got a job; executing");
+
+                    stats.busy.store(true, Ordering::Relaxed);
+                    let start = Instant::now();
+                    let result = panic::catch_unwind(AssertUnwindSafe(job));
+                    stats.busy_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                    stats.jobs_run.fetch_add(1, Ordering::Relaxed);
+                    stats.busy.store(false, Ordering::Relaxed);
+
+                    if result.is_err() {
+                        stats.jobs_panicked.fetch_add(1, Ordering::Relaxed);
+                        tracing::warn!(worker = id, "recovered from a panicking job");
+                    }
+
+                    Worker::finish_job(shared);
+                },
+                Message::Terminate => {
+                    return;
+                },
+            }
+        }
+    }
+}
+
+impl Worker {
+    fn finish_job(shared: &Shared) {
+        let mut queue = lock(&shared.queue);
+        queue.running_jobs -= 1;
+        if queue.is_idle() {
+            shared.idle.notify_all();
+        }
+    }
+}
+
+/// Lives on a worker's stack. Dropping it during a panic means the worker is dying, so it spawns
+/// a replacement; dropping it normally means the worker was told to terminate.
+struct Sentinel {
+    shared: Arc<Shared>,
+    id: usize,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            tracing::error!(worker = self.id, "died; spawning a replacement");
+            self.shared.stats[self.id].busy.store(false, Ordering::Relaxed);
+            self.shared.stats[self.id].restarts.fetch_add(1, Ordering::Relaxed);
+            Worker::spawn(self.shared.clone(), self.id);
+            return;
+        }
+
+        let mut queue = lock(&self.shared.queue);
+        queue.live_workers -= 1;
+        self.shared.worker_exited.notify_all();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::AtomicUsize;
+
+    fn pool(size: u32) -> Builder {
+        ThreadPool::builder(NonZeroU32::new(size).unwrap()).name("test")
+    }
+
+    /// Occupies the single worker of `pool` until the returned sender is dropped or sent to.
+    fn block_worker(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (started_sender, started) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_sender.send(()).unwrap();
+            let _ = released.recv();
+        }).ok().unwrap();
+        started.recv().unwrap();
+        release
+    }
+
+    #[test]
+    fn runs_every_queued_job_before_shutting_down() {
+        let pool = pool(4).build();
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let count = count.clone();
+            pool.execute(move || { count.fetch_add(1, Ordering::SeqCst); }).ok().unwrap();
+        }
+        pool.shutdown();
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn survives_panicking_jobs_and_counts_them() {
+        let pool = pool(1).build();
+        let shared = pool.shared.clone();
+        let count = Arc::new(AtomicUsize::new(0));
+        for i in 0..4 {
+            let count = count.clone();
+            pool.execute(move || {
+                if i % 2 == 0 {
+                    panic!("job {} panicked", i);
+                }
+                count.fetch_add(1, Ordering::SeqCst);
+            }).ok().unwrap();
+        }
+        pool.shutdown();
+
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        let stats = &shared.stats[0];
+        assert_eq!(stats.jobs_run.load(Ordering::SeqCst), 4);
+        assert_eq!(stats.jobs_panicked.load(Ordering::SeqCst), 2);
+        assert_eq!(stats.restarts.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn rejects_jobs_when_the_queue_is_full() {
+        let pool = pool(1).queue_capacity(1).rejection_policy(RejectionPolicy::Reject).build();
+        let release = block_worker(&pool);
+
+        pool.execute(|| {}).ok().unwrap();
+        assert_eq!(pool.queued(), 1);
+        let (sender, ran) = mpsc::channel();
+        let rejected = pool.execute(move || sender.send(()).unwrap()).err().unwrap();
+        assert!(matches!(rejected, ExecuteError::QueueFull(_)));
+        (rejected.into_inner())();
+        assert_eq!(ran.try_recv(), Ok(()));
+
+        drop(release);
+        pool.shutdown();
+    }
+
+    #[test]
+    fn runs_jobs_on_the_caller_when_the_queue_is_full() {
+        let pool = pool(1).queue_capacity(1).rejection_policy(RejectionPolicy::CallerRuns).build();
+        let release = block_worker(&pool);
+
+        pool.execute(|| {}).ok().unwrap();
+        let caller = thread::current().id();
+        let (sender, ran_on) = mpsc::channel();
+        pool.execute(move || sender.send(thread::current().id()).unwrap()).ok().unwrap();
+        assert_eq!(ran_on.try_recv(), Ok(caller));
+
+        drop(release);
+        pool.shutdown();
+    }
+
+    #[test]
+    fn shutdown_timeout_detaches_a_stuck_worker() {
+        let pool = pool(1).build();
+        let release = block_worker(&pool);
+
+        assert!(!pool.shutdown_timeout(Duration::from_millis(50)));
+        drop(release);
+    }
+
+    #[test]
+    fn submit_returns_results_and_panics() {
+        let pool = pool(2).build();
+        let handles: Vec<_> = (0..10).map(|i| pool.submit(move || i * i).ok().unwrap()).collect();
+        let results: Vec<_> = join_all(handles).into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, (0..10).map(|i| i * i).collect::<Vec<_>>());
+
+        let panicked = pool.submit(|| -> u32 { panic!("boom") }).ok().unwrap();
+        let payload = panicked.join().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    }
+
+    #[test]
+    fn try_join_gives_the_handle_back_until_the_job_is_done() {
+        let pool = pool(1).build();
+        let release = block_worker(&pool);
+
+        let handle = pool.submit(|| "done").ok().unwrap();
+        let handle = handle.try_join().err().unwrap();
+        drop(release);
+        assert_eq!(handle.join().unwrap(), "done");
+    }
+
+    #[test]
+    fn scoped_jobs_borrow_from_the_caller() {
+        let pool = pool(4).build();
+        let numbers: Vec<u64> = (1..=1000).collect();
+        let mut sums = [0; 4];
+
+        pool.scope(|scope| {
+            for (chunk, sum) in numbers.chunks(250).zip(sums.iter_mut()) {
+                scope.execute(move || *sum = chunk.iter().sum());
+            }
+        });
+
+        assert_eq!(sums.iter().sum::<u64>(), 500_500);
+    }
+
+    #[test]
+    fn scope_resumes_a_panic_once_every_job_finished() {
+        let pool = pool(2).build();
+        let finished = AtomicUsize::new(0);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|scope| {
+                scope.execute(|| panic!("scoped job panicked"));
+                for _ in 0..8 {
+                    scope.execute(|| { finished.fetch_add(1, Ordering::SeqCst); });
+                }
+            })
+        }));
+
+        assert!(result.is_err());
+        assert_eq!(finished.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn wait_idle_waits_for_queued_and_running_jobs() {
+        let pool = pool(2).build();
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..20 {
+            let count = count.clone();
+            pool.execute(move || {
+                thread::sleep(Duration::from_millis(1));
+                count.fetch_add(1, Ordering::SeqCst);
+            }).ok().unwrap();
+        }
+
+        pool.wait_idle();
+        assert_eq!(count.load(Ordering::SeqCst), 20);
+        assert_eq!(pool.queued(), 0);
+    }
+}
@@ -0,0 +1,331 @@
+// TLS termination for the hyper and tonic servers, which share the identity in `data/tls`.
+//
+// The certificate is served through a resolver that can swap in a new certificate and key while
+// the server runs. Handshakes that are already done keep the old one; new handshakes get the new
+// one. `watch` reloads when the files change on disk and `reload_on_hangup` on SIGHUP. A replacement
+// that can't be parsed, or whose key doesn't match the certificate, is rejected and the old one kept.
+//
+// ALPN offers both h2 and http/1.1. hyper's server speaks whichever the client picks.
+#![allow(dead_code)]
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+use futures::Stream;
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig, SignatureScheme};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::file_watch;
+
+
+pub const CERTIFICATE_PATH: &str = "data/tls/server.pem";
+pub const KEY_PATH: &str = "data/tls/server.key";
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long a client has to finish the handshake after connecting.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Handshakes in progress at once. Connections beyond that wait in the listen backlog.
+const MAX_HANDSHAKES: usize = 256;
+/// How long to wait after `accept` fails, which it does when we're out of file descriptors, say.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+
+#[derive(Debug)]
+pub enum TlsError {
+    Io(PathBuf, io::Error),
+    NoCertificates(PathBuf),
+    NoPrivateKey(PathBuf),
+    UnsupportedKey(PathBuf),
+    KeyMismatch(String),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Io(path, e)          => write!(f, "failed to read {}: {}", path.display(), e),
+            TlsError::NoCertificates(path) => write!(f, "no PEM certificates in {}", path.display()),
+            TlsError::NoPrivateKey(path)   => write!(f, "no PKCS#8 or RSA private key in {}", path.display()),
+            TlsError::UnsupportedKey(path) => write!(f, "unsupported private key type in {}", path.display()),
+            TlsError::KeyMismatch(e)       => write!(f, "the private key doesn't match the certificate: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+
+/// Loads a certificate chain and private key from PEM files and checks that they belong together.
+pub fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, TlsError> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| TlsError::Io(path.to_path_buf(), e))
+    };
+
+    let certificates = pemfile::certs(&mut open(cert_path)?)
+        .ok()
+        .filter(|certificates| !certificates.is_empty())
+        .ok_or_else(|| TlsError::NoCertificates(cert_path.to_path_buf()))?;
+
+    // Either kind of key may be in the file, so look for PKCS#8 first and RSA after that.
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_path)?).unwrap_or_default();
+    }
+    let key = keys.into_iter().next().ok_or_else(|| TlsError::NoPrivateKey(key_path.to_path_buf()))?;
+
+    let signing_key = sign::any_supported_type(&key)
+        .map_err(|_| TlsError::UnsupportedKey(key_path.to_path_buf()))?;
+
+    let certified_key = CertifiedKey::new(certificates, Arc::new(signing_key));
+    certified_key
+        .cross_check_end_entity_cert(None)
+        .map_err(|e| TlsError::KeyMismatch(e.to_string()))?;
+    check_key_belongs_to_certificate(&certified_key).map_err(TlsError::KeyMismatch)?;
+
+    Ok(certified_key)
+}
+
+/// Signs a probe with the key and checks the signature with the certificate's public key. rustls
+/// only checks that the certificate parses, and would otherwise serve a key that doesn't match.
+fn check_key_belongs_to_certificate(certified_key: &CertifiedKey) -> Result<(), String> {
+    const PROBE: &[u8] = b"does this key belong to the certificate?";
+    let schemes: [(SignatureScheme, &webpki::SignatureAlgorithm); 4] = [
+        (SignatureScheme::ED25519,               &webpki::ED25519),
+        (SignatureScheme::ECDSA_NISTP256_SHA256, &webpki::ECDSA_P256_SHA256),
+        (SignatureScheme::ECDSA_NISTP384_SHA384, &webpki::ECDSA_P384_SHA384),
+        (SignatureScheme::RSA_PKCS1_SHA256,      &webpki::RSA_PKCS1_2048_8192_SHA256),
+    ];
+
+    let offered: Vec<SignatureScheme> = schemes.iter().map(|&(scheme, _)| scheme).collect();
+    let signer = certified_key
+        .key
+        .choose_scheme(&offered)
+        .ok_or_else(|| "the key can't sign with any scheme we can check".to_string())?;
+    let algorithm = schemes
+        .iter()
+        .find(|&&(scheme, _)| scheme == signer.get_scheme())
+        .map(|&(_, algorithm)| algorithm)
+        .ok_or_else(|| "the key chose a scheme that wasn't offered".to_string())?;
+    let signature = signer.sign(PROBE).map_err(|e| e.to_string())?;
+
+    let certificate = certified_key.end_entity_cert().map_err(|()| "there is no certificate".to_string())?;
+    webpki::EndEntityCert::from(&certificate.0)
+        .map_err(|e| e.to_string())?
+        .verify_signature(algorithm, PROBE, &signature)
+        .map_err(|_| "the key's signature doesn't verify with the certificate".to_string())
+}
+
+
+pub struct ReloadingResolver {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<CertifiedKey>,
+}
+
+impl ReloadingResolver {
+    pub fn new<P: Into<PathBuf>>(cert_path: P, key_path: P) -> Result<Arc<ReloadingResolver>, TlsError> {
+        let cert_path = cert_path.into();
+        let key_path = key_path.into();
+        let current = load_certified_key(&cert_path, &key_path)?;
+
+        Ok(Arc::new(ReloadingResolver { cert_path, key_path, current: RwLock::new(current) }))
+    }
+
+    /// Reads the files again and, if they are valid, uses them for every new handshake.
+    pub fn reload(&self) -> Result<(), TlsError> {
+        let certified_key = load_certified_key(&self.cert_path, &self.key_path)?;
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = certified_key;
+        Ok(())
+    }
+
+    /// Reloads whenever the certificate or key file changes.
+    pub fn watch(self: &Arc<Self>) {
+        let resolver = self.clone();
+        let paths = vec![self.cert_path.clone(), self.key_path.clone()];
+
+        file_watch::watch(paths, WATCH_INTERVAL, move || resolver.reload_and_log());
+    }
+
+    /// Reloads whenever the process gets SIGHUP, for when the files are replaced in a way that
+    /// leaves their modification times alone.
+    #[cfg(unix)]
+    pub fn reload_on_hangup(self: &Arc<Self>) -> io::Result<()> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangups = signal(SignalKind::hangup())?;
+        let resolver = self.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                resolver.reload_and_log();
+            }
+        });
+        Ok(())
+    }
+
+    /// There is no SIGHUP off unix; `watch` still picks up changed files.
+    #[cfg(not(unix))]
+    pub fn reload_on_hangup(self: &Arc<Self>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// The certificate and key new handshakes get.
+    fn certified_key(&self) -> CertifiedKey {
+        self.current.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn reload_and_log(&self) {
+        match self.reload() {
+            Ok(()) => tracing::info!(path = %self.cert_path.display(), "reloaded TLS certificate"),
+            Err(e) => tracing::error!(error = %e, "rejected new TLS certificate; keeping the current one"),
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<CertifiedKey> {
+        Some(self.certified_key())
+    }
+}
+
+
+/// A server configuration that gets its certificate from `resolver` and offers h2 and http/1.1.
+pub fn server_config(resolver: Arc<ReloadingResolver>) -> ServerConfig {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = resolver;
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    config
+}
+
+/// The same, for gRPC, which only speaks h2.
+pub fn grpc_server_config(resolver: Arc<ReloadingResolver>) -> ServerConfig {
+    let mut config = server_config(resolver);
+    config.set_protocols(&[b"h2".to_vec()]);
+    config
+}
+
+/// Binds `address` and yields TLS connections with the identity in `data/tls`, reloading it when
+/// the files change. Pass the result to `hyper::server::accept::from_stream`.
+pub async fn incoming(address: SocketAddr) -> Result<impl Stream<Item = io::Result<TlsStream<tokio::net::TcpStream>>>, Box<dyn std::error::Error>> {
+    let resolver = ReloadingResolver::new(CERTIFICATE_PATH, KEY_PATH)?;
+    resolver.watch();
+
+    Ok(incoming_with(address, Arc::new(server_config(resolver))).await?)
+}
+
+pub async fn incoming_with(address: SocketAddr, config: Arc<ServerConfig>) -> io::Result<impl Stream<Item = io::Result<TlsStream<tokio::net::TcpStream>>>> {
+    let mut listener = TcpListener::bind(address).await?;
+    let acceptor = TlsAcceptor::from(config);
+    let (sender, receiver) = mpsc::channel(64);
+
+    // Handshakes run in their own tasks, so a slow client can't hold up the others. There are at
+    // most `MAX_HANDSHAKES` of them, and each gets `HANDSHAKE_TIMEOUT`, so clients that connect and
+    // never send a ClientHello can't pile up.
+    let handshakes = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+    tokio::spawn(async move {
+        loop {
+            let permit = handshakes.clone().acquire_owned().await;
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!(error = %e, "failed to accept connection");
+                    tokio::time::delay_for(ACCEPT_BACKOFF).await;
+                    continue;
+                },
+            };
+
+            let acceptor = acceptor.clone();
+            let mut sender = sender.clone();
+            tokio::spawn(async move {
+                let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await;
+                drop(permit);
+                match handshake {
+                    Ok(Ok(stream)) => {
+                        let _ = sender.send(Ok(stream)).await;
+                    },
+                    Ok(Err(e)) => tracing::debug!(error = %e, "TLS handshake failed"),
+                    Err(_) => tracing::debug!("TLS handshake timed out"),
+                }
+            });
+        }
+    });
+
+    Ok(receiver)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    /// A directory with a copy of the server's certificate and key, which the tests overwrite.
+    fn identity(name: &str) -> (PathBuf, PathBuf) {
+        let directory = std::env::temp_dir().join(format!("tls-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&directory).unwrap();
+        let (cert_path, key_path) = (directory.join("server.pem"), directory.join("server.key"));
+        fs::copy(CERTIFICATE_PATH, &cert_path).unwrap();
+        fs::copy(KEY_PATH, &key_path).unwrap();
+        (cert_path, key_path)
+    }
+
+    fn certificate(resolver: &ReloadingResolver) -> Vec<u8> {
+        resolver.certified_key().end_entity_cert().unwrap().0.clone()
+    }
+
+    #[test]
+    fn keeps_the_current_identity_when_the_new_one_is_invalid() {
+        let (cert_path, key_path) = identity("invalid");
+        let resolver = ReloadingResolver::new(&cert_path, &key_path).unwrap();
+        let original = certificate(&resolver);
+
+        // The client's key doesn't belong to the server's certificate.
+        fs::copy("data/tls/client.key", &key_path).unwrap();
+        assert!(matches!(resolver.reload(), Err(TlsError::KeyMismatch(_))));
+        assert_eq!(certificate(&resolver), original);
+
+        fs::copy(KEY_PATH, &key_path).unwrap();
+        fs::write(&cert_path, "-----BEGIN CERTIFICATE-----\nnot base64\n-----END CERTIFICATE-----\n").unwrap();
+        assert!(matches!(resolver.reload(), Err(TlsError::NoCertificates(_))));
+        assert_eq!(certificate(&resolver), original);
+
+        fs::write(&cert_path, "garbage").unwrap();
+        assert!(matches!(resolver.reload(), Err(TlsError::NoCertificates(_))));
+        fs::copy(CERTIFICATE_PATH, &cert_path).unwrap();
+        fs::write(&key_path, "garbage").unwrap();
+        assert!(matches!(resolver.reload(), Err(TlsError::NoPrivateKey(_))));
+        fs::remove_file(&key_path).unwrap();
+        assert!(matches!(resolver.reload(), Err(TlsError::Io(..))));
+        assert_eq!(certificate(&resolver), original);
+    }
+
+    #[test]
+    fn switches_to_a_valid_new_identity() {
+        let (cert_path, key_path) = identity("valid");
+        let resolver = ReloadingResolver::new(&cert_path, &key_path).unwrap();
+        let original = certificate(&resolver);
+
+        fs::copy("data/tls/client.pem", &cert_path).unwrap();
+        fs::copy("data/tls/client.key", &key_path).unwrap();
+        resolver.reload().unwrap();
+        assert_ne!(certificate(&resolver), original);
+    }
+
+    #[test]
+    fn refuses_to_start_without_a_valid_identity() {
+        let (cert_path, _) = identity("start");
+        assert!(matches!(ReloadingResolver::new(&cert_path, &PathBuf::from("data/tls/client.key")), Err(TlsError::KeyMismatch(_))));
+    }
+}
@@ -0,0 +1,176 @@
+// RouteChat for web clients, as a WebSocket at `/ws/route-chat`.
+//
+// The HTTP request is upgraded with hyper and the upgraded connection is handed to
+// tokio-tungstenite. Notes are JSON encoded (see `chat::JsonNote`) and go through the same
+// `ChatHub` as the gRPC `route_chat` stream, so both kinds of clients see each other's notes.
+// `/ws/route-chat?room=<name>` joins a room other than the default one.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use hyper::header::{self, HeaderValue};
+use hyper::upgrade::Upgraded;
+use hyper::{Body, Request, Response, StatusCode};
+use sha1::{Digest, Sha1};
+use tokio::sync::broadcast::RecvError;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use tracing_futures::Instrument;
+
+use crate::chat::{ChatHub, JsonNote, Posted, DEFAULT_ROOM};
+use crate::error_pages;
+
+
+const PING_INTERVAL: Duration = Duration::from_secs(20);
+// A client that hasn't answered a ping within this long is considered gone.
+const PONG_TIMEOUT: Duration = Duration::from_secs(60);
+
+// From RFC 6455, section 1.3.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+
+/// Answers the upgrade request and, once hyper has switched protocols, serves the socket.
+pub fn upgrade(request: Request<Body>, chat: Arc<ChatHub>) -> Response<Body> {
+    let headers = request.headers();
+    let has_token = |name, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value: &HeaderValue| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+
+    let key = match headers.get(header::SEC_WEBSOCKET_KEY) {
+        Some(key) if has_token(header::UPGRADE, "websocket") && has_token(header::CONNECTION, "upgrade") => key,
+        _ => return error_pages::hyper_response(StatusCode::BAD_REQUEST, &request),
+    };
+    if headers.get(header::SEC_WEBSOCKET_VERSION).map(|v| v.as_bytes()) != Some(b"13") {
+        let mut response = error_pages::hyper_response(StatusCode::UPGRADE_REQUIRED, &request);
+        response.headers_mut().insert(header::SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+        return response;
+    }
+
+    let accept = accept_key(key.as_bytes());
+    let room = room(request.uri().query().unwrap_or(""));
+
+    tokio::spawn(async move {
+        match request.into_body().on_upgrade().await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                serve(socket, chat, room).await;
+            },
+            Err(e) => tracing::warn!(error = %e, "websocket upgrade failed"),
+        }
+    }.instrument(tracing::Span::current()));
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::UPGRADE, "websocket")
+        .header(header::CONNECTION, "upgrade")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn room(query: &str) -> String {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("room"), Some(room)) => Some(room.to_string()),
+                _ => None,
+            }
+        })
+        .next()
+        .unwrap_or_else(|| DEFAULT_ROOM.to_string())
+}
+
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key);
+    sha1.update(WEBSOCKET_GUID.as_bytes());
+    base64::encode(sha1.finalize())
+}
+
+
+enum Event {
+    Incoming(Option<Result<Message, tokio_tungstenite::tungstenite::Error>>),
+    Posted(Result<Posted, RecvError>),
+    Ping,
+}
+
+async fn serve(socket: WebSocketStream<Upgraded>, chat: Arc<ChatHub>, room: String) {
+    let (mut outgoing, mut incoming) = socket.split();
+    let (id, mut posted) = chat.join();
+
+    let mut ping = tokio::time::interval(PING_INTERVAL);
+    let mut last_pong = Instant::now();
+
+    let close = loop {
+        let event = tokio::select! {
+            message = incoming.next() => Event::Incoming(message),
+            note = posted.recv() => Event::Posted(note),
+            _ = ping.tick() => Event::Ping,
+        };
+
+        let reply = match event {
+            Event::Incoming(Some(Ok(Message::Text(text)))) => match serde_json::from_str::<JsonNote>(&text) {
+                Ok(note) => chat.post(id, &room, note.into()),
+                Err(e) => break Some((CloseCode::Invalid, format!("expected a JSON RouteNote: {}", e))),
+            },
+            Event::Incoming(Some(Ok(Message::Binary(_)))) => {
+                break Some((CloseCode::Unsupported, String::from("only text messages are supported")));
+            },
+            Event::Incoming(Some(Ok(Message::Pong(_)))) => {
+                last_pong = Instant::now();
+                continue;
+            },
+            // tungstenite answers pings and close frames by itself.
+            Event::Incoming(Some(Ok(Message::Ping(_)))) => continue,
+            Event::Incoming(Some(Ok(Message::Close(_)))) | Event::Incoming(None) => break None,
+            Event::Incoming(Some(Err(e))) => {
+                tracing::warn!(client = id, error = %e, "websocket client failed");
+                break None;
+            },
+
+            Event::Posted(Ok(posted)) if posted.is_for(id, &room) => vec![posted.note],
+            Event::Posted(Ok(_)) => continue,
+            // Too slow to keep up; the skipped notes are lost for this client.
+            Event::Posted(Err(RecvError::Lagged(skipped))) => {
+                tracing::warn!(client = id, skipped, "websocket client is too slow; skipped notes");
+                continue;
+            },
+            Event::Posted(Err(RecvError::Closed)) => {
+                break Some((CloseCode::Away, String::from("the server is shutting down")));
+            },
+
+            Event::Ping => {
+                if last_pong.elapsed() > PONG_TIMEOUT {
+                    break Some((CloseCode::Policy, String::from("no pong received")));
+                }
+                if outgoing.send(Message::Ping(Vec::new())).await.is_err() {
+                    break None;
+                }
+                continue;
+            },
+        };
+
+        for note in &reply {
+            let json = serde_json::to_string(&JsonNote::from(note)).expect("notes always serialize");
+            if outgoing.send(Message::Text(json)).await.is_err() {
+                return;
+            }
+        }
+    };
+
+    if let Some((code, reason)) = close {
+        let frame = CloseFrame { code, reason: reason.into() };
+        let _ = outgoing.send(Message::Close(Some(frame))).await;
+    }
+}